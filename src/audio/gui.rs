@@ -1,13 +1,24 @@
-use crate::{main_view::gui::GuiComponent, settings::Settings};
-use egui::{Slider, Ui};
+use crate::{
+    bundle::Bundle,
+    main_view::gui::{GuiComponent, MainGui},
+    settings::Settings,
+};
+use egui::{RichText, Slider, Ui};
 
 use super::{
     //debug::{AudioStat, AudioStats},
+    wav::WavRecording,
     Audio,
+    AudioSettings,
 };
 
+/// How much the volume hotkeys change the volume, in percent
+const VOLUME_STEP: i16 = 10;
+
 pub struct AudioGui {
     pub audio: Audio,
+    /// The volume from before muting
+    muted_volume: Option<u8>,
     // #[cfg(feature = "debug")]
     // stats: AudioStats,
 }
@@ -16,9 +27,75 @@ impl AudioGui {
     pub fn new(audio: Audio) -> Self {
         Self {
             audio,
+            muted_volume: None,
             //stats: AudioStats::new(),
         }
     }
+
+    pub fn volume_up(&mut self) {
+        self.change_volume(VOLUME_STEP);
+    }
+
+    pub fn volume_down(&mut self) {
+        self.change_volume(-VOLUME_STEP);
+    }
+
+    fn change_volume(&mut self, change: i16) {
+        if Bundle::current().config.lock_volume {
+            return;
+        }
+        self.muted_volume = None;
+        let volume = {
+            let audio_settings = &mut Settings::current_mut().audio;
+            audio_settings.volume = (audio_settings.volume as i16 + change).clamp(0, 100) as u8;
+            audio_settings.volume
+        };
+        MainGui::show_toast(format!("Volume {volume}%"));
+    }
+
+    /// Mute, or go back to the volume from before muting if still muted
+    pub fn toggle_mute(&mut self) {
+        if Bundle::current().config.lock_volume {
+            return;
+        }
+        let audio_settings = &mut Settings::current_mut().audio;
+        match self.muted_volume.take() {
+            Some(volume) if audio_settings.volume == 0 => {
+                audio_settings.volume = volume;
+                MainGui::show_toast(format!("Volume {volume}%"));
+            }
+            _ => {
+                self.muted_volume = Some(audio_settings.volume);
+                audio_settings.volume = 0;
+                MainGui::show_toast("Muted");
+            }
+        }
+    }
+
+    fn recording_ui(&mut self, ui: &mut Ui) {
+        if WavRecording::is_recording() {
+            if ui.button("Stop recording audio").clicked() {
+                match WavRecording::stop() {
+                    Ok(_) => MainGui::show_toast("Audio recording saved"),
+                    Err(e) => {
+                        log::error!("Could not save the audio recording: {e:?}");
+                        MainGui::show_toast("Could not save the audio recording");
+                    }
+                }
+            }
+        } else if let Some(sample_rate) = self.audio.stream.sample_rate() {
+            if ui
+                .button("Record audio")
+                .on_hover_text("Save the sound of the game to a WAV file")
+                .clicked()
+            {
+                if let Err(e) = WavRecording::start(sample_rate) {
+                    log::error!("Could not start recording audio: {e:?}");
+                    MainGui::show_toast("Could not start recording audio");
+                }
+            }
+        }
+    }
 }
 // #[cfg(feature = "debug")]
 // impl AudioGui {
@@ -93,16 +170,51 @@ impl GuiComponent for AudioGui {
                 }
             });
 
+            if !Bundle::current().config.lock_volume {
+                ui.horizontal(|ui| {
+                    ui.label("Volume");
+                    ui.add(Slider::new(&mut audio_settings.volume, 0..=100).suffix("%"));
+                });
+            }
+
             ui.horizontal(|ui| {
-                ui.label("Volume");
-                ui.add(Slider::new(&mut audio_settings.volume, 0..=100).suffix("%"));
+                ui.label("Latency");
+                ui.add(
+                    Slider::new(
+                        &mut audio_settings.latency,
+                        AudioSettings::min_latency()..=u8::MAX,
+                    )
+                    .suffix(" ms"),
+                )
+                .on_hover_text("Lower is more responsive, raise it if the sound crackles");
             });
+            let latency = self.audio.stream.latency().as_millis();
+            if latency != audio_settings.latency as u128 {
+                ui.label(
+                    RichText::new(format!(
+                        "Using {latency} ms until the game is started again"
+                    ))
+                    .weak(),
+                );
+            }
+
+            ui.checkbox(&mut audio_settings.limiter, "Limit loud peaks")
+                .on_hover_text("Evens out games that are too loud or clip");
+            if audio_settings.limiter {
+                ui.add(
+                    Slider::new(&mut audio_settings.limiter_threshold, 50..=100)
+                        .suffix("%")
+                        .text("Limiter threshold"),
+                );
+            }
 
             new_device
         };
         if let Some(new_device) = new_device {
             self.audio.stream.set_output_device(Some(new_device));
         }
+
+        self.recording_ui(ui);
     }
 
     fn name(&self) -> Option<&str> {
@@ -1,6 +1,9 @@
+use std::collections::VecDeque;
 use std::ops::Add;
 
-use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SendError, SyncSender, TrySendError};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
@@ -8,9 +11,16 @@ use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired, AudioStatus};
 use sdl2::{AudioSubsystem, Sdl};
 use serde::{Deserialize, Serialize};
 
-use crate::settings::Settings;
+use crate::{
+    bundle::Bundle,
+    emulation::{NesRegion, SAMPLE_RATE},
+    settings::Settings,
+};
+
+use self::wav::WavRecording;
 
 pub mod gui;
+pub mod wav;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash)]
 pub struct AudioSettings {
@@ -18,25 +28,145 @@ pub struct AudioSettings {
     #[serde(default = "AudioSettings::default_latency")]
     pub latency: u8,
     pub output_device: Option<String>,
+    /// Turn down peaks that go above the limiter threshold, so loud games don't clip
+    #[serde(default = "Default::default")]
+    pub limiter: bool,
+    /// Where the limiter kicks in, in % of full scale
+    #[serde(default = "AudioSettings::default_limiter_threshold")]
+    pub limiter_threshold: u8,
 }
 impl AudioSettings {
     fn default_latency() -> u8 {
         30
     }
+
+    /// The shortest latency (in ms) that works, the buffer needs room for a couple of frames or the
+    /// emulation will stall on every frame. The region can change while running, so it makes room for
+    /// the region with the most samples per frame.
+    pub fn min_latency() -> u8 {
+        let samples_per_frame = Bundle::current()
+            .config
+            .supported_nes_regions
+            .iter()
+            .map(NesRegion::samples_per_frame)
+            .fold(0.0, f32::max);
+        (samples_per_frame * 2.0 / SAMPLE_RATE * 1000.0).ceil() as u8
+    }
+
+    /// The latency to start the audio with, never shorter than `min_latency`
+    pub fn latency(&self) -> Duration {
+        Duration::from_millis(self.latency.max(Self::min_latency()) as u64)
+    }
+
+    fn default_limiter_threshold() -> u8 {
+        80
+    }
+
+    /// The level (0.0 - 1.0) the output is limited to, if the limiter is on
+    pub fn limiter_threshold(&self) -> Option<f32> {
+        self.limiter
+            .then(|| self.limiter_threshold.clamp(50, 100) as f32 / 100.0)
+    }
+
+    /// The volume to play at (0.0 - 1.0), the bundle's default volume if it's locked
+    pub fn volume(&self) -> f32 {
+        let config = &Bundle::current().config;
+        let volume = if config.lock_volume {
+            config.default_settings.audio.volume
+        } else {
+            self.volume
+        };
+        volume.min(100) as f32 / 100.0
+    }
+}
+struct AudioReceiverCallback {
+    consumer: AudioReceiver,
+    /// How many samples are waiting in the channel, shared with the `AudioSender`
+    buffered: Arc<AtomicUsize>,
+    /// Samples queued up before playback starts, so the start isn't a stream of underruns
+    prefill: VecDeque<f32>,
+    /// How many samples to queue up before playing, 0 once playing
+    prefill_target: usize,
+    /// The gain the limiter currently applies, 1.0 when nothing is being limited
+    limiter_gain: f32,
+    /// The samples of the current callback, while the audio is being recorded. Handed over to the WAV writer thread.
+    recorded: Vec<f32>,
+}
+
+/// How fast the limiter lets go after a peak, per sample (about 50 ms at 44.1 kHz)
+const LIMITER_RELEASE: f32 = 0.0005;
+
+impl AudioReceiverCallback {
+    fn new(consumer: AudioReceiver, buffered: Arc<AtomicUsize>, prefill_target: usize) -> Self {
+        Self {
+            consumer,
+            buffered,
+            prefill: VecDeque::with_capacity(prefill_target),
+            prefill_target,
+            limiter_gain: 1.0,
+            recorded: Vec::new(),
+        }
+    }
+
+    /// A peak limiter that clamps down instantly and releases slowly, so it doesn't pump on every wave
+    fn limit(&mut self, sample: f32, threshold: f32) -> f32 {
+        let peak = sample.abs();
+        let target_gain = if peak > threshold {
+            threshold / peak
+        } else {
+            1.0
+        };
+        if target_gain < self.limiter_gain {
+            self.limiter_gain = target_gain;
+        } else {
+            self.limiter_gain += (target_gain - self.limiter_gain) * LIMITER_RELEASE;
+        }
+        sample * self.limiter_gain
+    }
+
+    fn receive(&self) -> Option<f32> {
+        let sample = self.consumer.try_recv().ok()?;
+        self.buffered.fetch_sub(1, Ordering::Relaxed);
+        Some(sample)
+    }
 }
-struct AudioReceiverCallback(AudioReceiver);
 
 impl AudioCallback for AudioReceiverCallback {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        let consumer = &mut self.0;
-
-        let volume = Settings::current().audio.volume as f32 / 100.0;
+        if self.prefill_target > 0 {
+            while self.prefill.len() < self.prefill_target {
+                match self.receive() {
+                    Some(sample) => self.prefill.push_back(sample),
+                    None => break,
+                }
+            }
+            if self.prefill.len() < self.prefill_target {
+                // Play silence until the emulator has produced enough samples
+                out.fill(0.0);
+                return;
+            }
+            log::debug!("Audio buffer filled, starting playback");
+            self.prefill_target = 0;
+        }
+        let (volume, limiter_threshold) = {
+            let audio_settings = &Settings::current().audio;
+            (audio_settings.volume(), audio_settings.limiter_threshold())
+        };
+        let recording = WavRecording::is_recording();
         let mut missing_samples = 0;
         for s in out {
-            if let Ok(new_sample) = consumer.try_recv() {
-                *s = new_sample * volume;
+            if let Some(new_sample) = self.prefill.pop_front().or_else(|| self.receive()) {
+                // Recorded before the volume, so the playback volume doesn't affect the recording
+                if recording {
+                    self.recorded.push(new_sample);
+                }
+                let new_sample = new_sample * volume;
+                *s = match limiter_threshold {
+                    Some(threshold) => self.limit(new_sample, threshold),
+                    None => new_sample,
+                };
             } else {
                 missing_samples += 1;
                 *s = 0.0;
@@ -45,15 +175,45 @@ impl AudioCallback for AudioReceiverCallback {
         if missing_samples > 0 {
             log::trace!("Buffer underrun: {missing_samples} samples");
         }
+        if recording {
+            WavRecording::write(std::mem::take(&mut self.recorded));
+        }
+    }
+}
+/// Sends samples to the audio device and keeps count of how many are waiting to be played
+#[derive(Clone)]
+pub struct AudioSender {
+    tx: SyncSender<f32>,
+    buffered: Arc<AtomicUsize>,
+}
+
+impl AudioSender {
+    /// Blocks until there is room for the sample
+    pub fn send(&self, sample: f32) -> Result<(), SendError<f32>> {
+        // Counted before sending so the receiver never takes it below zero
+        self.buffered.fetch_add(1, Ordering::Relaxed);
+        self.tx.send(sample).inspect_err(|_| {
+            self.buffered.fetch_sub(1, Ordering::Relaxed);
+        })
+    }
+
+    pub fn try_send(&self, sample: f32) -> Result<(), TrySendError<f32>> {
+        self.buffered.fetch_add(1, Ordering::Relaxed);
+        self.tx.try_send(sample).inspect_err(|_| {
+            self.buffered.fetch_sub(1, Ordering::Relaxed);
+        })
     }
 }
-pub type AudioSender = SyncSender<f32>;
+
 pub type AudioReceiver = Receiver<f32>;
 
 pub struct Stream {
     tx: Option<AudioSender>,
     output_device_name: Option<String>,
     audio_device: Option<AudioDevice<AudioReceiverCallback>>,
+    buffered: Arc<AtomicUsize>,
+    capacity: usize,
+    latency: Duration,
 }
 
 impl Stream {
@@ -65,29 +225,42 @@ impl Stream {
         log::debug!(
             "Trying to start audio: sample rate={desired_sample_rate}, latency={latency:?}"
         );
-        let sample_latency =
-            (latency.as_secs_f32() * desired_sample_rate as f32 * 1.0).ceil() as u16;
+        let sample_latency = (latency.as_secs_f32() * desired_sample_rate as f32).ceil() as u16;
 
         let (tx, audio_rx) = sync_channel(sample_latency as usize);
-        // Fill with silence
-        for _ in 0..sample_latency {
-            let _ = tx.send(0.0);
-        }
+        let buffered = Arc::new(AtomicUsize::new(0));
 
         let output_device = &Settings::current().audio.output_device;
         let audio_device = Stream::new_audio_device(
             desired_sample_rate,
             audio_subsystem,
             output_device,
-            audio_rx,
+            // Wait for the emulator to fill the buffer up to the latency before playing anything
+            AudioReceiverCallback::new(audio_rx, buffered.clone(), sample_latency as usize),
         )?;
         Ok(Self {
-            tx: Some(tx),
+            tx: Some(AudioSender {
+                tx,
+                buffered: buffered.clone(),
+            }),
             output_device_name: output_device.clone(),
             audio_device: Some(audio_device),
+            buffered,
+            capacity: sample_latency as usize,
+            latency,
         })
     }
 
+    /// The latency the audio was started with
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// How full the buffer between the emulator and the audio device is (0.0 - 1.0)
+    pub fn buffer_fill(&self) -> f32 {
+        (self.buffered.load(Ordering::Relaxed) as f32 / self.capacity.max(1) as f32).min(1.0)
+    }
+
     pub fn start(&mut self) -> Result<AudioSender> {
         if let Some(device) = &self.audio_device {
             device.resume();
@@ -95,11 +268,29 @@ impl Stream {
         self.tx.take().ok_or(anyhow!("Stream already started"))
     }
 
+    /// The sample rate the audio device plays at
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.audio_device
+            .as_ref()
+            .map(|audio_device| audio_device.spec().freq as u32)
+    }
+
+    /// Pause or resume the audio device, e.g. to save power while the emulation is paused
+    pub fn set_paused(&self, paused: bool) {
+        if let Some(audio_device) = &self.audio_device {
+            match (paused, audio_device.status()) {
+                (true, AudioStatus::Playing) => audio_device.pause(),
+                (false, AudioStatus::Paused) => audio_device.resume(),
+                _ => {}
+            }
+        }
+    }
+
     fn new_audio_device(
         desired_sample_rate: u32,
         audio_subsystem: &AudioSubsystem,
         output_device: &Option<String>,
-        audio_rx: AudioReceiver,
+        callback: AudioReceiverCallback,
     ) -> Result<AudioDevice<AudioReceiverCallback>> {
         let channels = 1;
 
@@ -120,9 +311,7 @@ impl Stream {
             .or_else(|| Audio::get_default_device_name_for_subsystem(audio_subsystem));
 
         let output_device = audio_subsystem
-            .open_playback(output_device.as_deref(), &desired_spec, |_| {
-                AudioReceiverCallback(audio_rx)
-            })
+            .open_playback(output_device.as_deref(), &desired_spec, |_| callback)
             .map_err(anyhow::Error::msg)?;
         log::info!("Audio started with {:?}", output_device.spec());
         Ok(output_device)
@@ -140,7 +329,7 @@ impl Stream {
                     desired_sample_rate,
                     &subsystem,
                     &output_device_name,
-                    old_callback.0,
+                    old_callback,
                 ) {
                     Ok(audio_device) => {
                         if old_device_status == AudioStatus::Playing {
@@ -0,0 +1,167 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError},
+        OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::bundle::Bundle;
+
+struct Recording {
+    path: PathBuf,
+    writer: WavWriter<BufWriter<File>>,
+}
+
+enum WriterMessage {
+    Start(Recording),
+    Samples(Vec<f32>),
+    Stop(Sender<Result<Option<PathBuf>>>),
+}
+
+/// How many callbacks worth of samples can wait for the writer before they are dropped
+const WRITER_BACKLOG: usize = 64;
+
+/// Records the sound going to the audio device to a WAV file, before the volume is applied.
+/// The file is written on a thread of its own, the audio callback only hands over the samples.
+pub struct WavRecording;
+
+impl WavRecording {
+    fn _writer() -> &'static OnceLock<SyncSender<WriterMessage>> {
+        static MEM: OnceLock<SyncSender<WriterMessage>> = OnceLock::new();
+        &MEM
+    }
+
+    /// The writer thread is started with the first recording
+    fn writer() -> &'static SyncSender<WriterMessage> {
+        Self::_writer().get_or_init(|| {
+            let (tx, rx) = sync_channel(WRITER_BACKLOG);
+            std::thread::Builder::new()
+                .name("wav-writer".to_string())
+                .spawn(move || Self::write_until_disconnected(rx))
+                .expect("the wav writer thread to start");
+            tx
+        })
+    }
+
+    /// Checked by the audio callback before it hands over samples
+    fn _active() -> &'static AtomicBool {
+        static MEM: AtomicBool = AtomicBool::new(false);
+        &MEM
+    }
+
+    /// Samples the writer had no room for since the recording started
+    fn _dropped() -> &'static AtomicUsize {
+        static MEM: AtomicUsize = AtomicUsize::new(0);
+        &MEM
+    }
+
+    pub fn is_recording() -> bool {
+        Self::_active().load(Ordering::Relaxed)
+    }
+
+    /// Start recording mono samples at `sample_rate` to `settings_path/recordings`
+    pub fn start(sample_rate: u32) -> Result<()> {
+        let dir = Bundle::current().settings_path.join("recordings");
+        fs::create_dir_all(&dir)?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = dir.join(format!("audio_{timestamp}.wav"));
+        let writer = WavWriter::create(
+            &path,
+            WavSpec {
+                channels: 1,
+                sample_rate,
+                bits_per_sample: 32,
+                sample_format: SampleFormat::Float,
+            },
+        )?;
+        log::info!("Recording audio to {path:?}");
+        Self::_dropped().store(0, Ordering::Relaxed);
+        Self::writer()
+            .send(WriterMessage::Start(Recording { path, writer }))
+            .map_err(|_| anyhow!("The audio recording writer has stopped"))?;
+        Self::_active().store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Stop recording, returns where the recording was saved once everything is written
+    pub fn stop() -> Result<Option<PathBuf>> {
+        Self::_active().store(false, Ordering::Relaxed);
+        let Some(writer) = Self::_writer().get() else {
+            return Ok(None);
+        };
+        let (tx, rx) = channel();
+        writer
+            .send(WriterMessage::Stop(tx))
+            .map_err(|_| anyhow!("The audio recording writer has stopped"))?;
+        let path = rx
+            .recv()
+            .map_err(|_| anyhow!("The audio recording writer has stopped"))??;
+        let dropped = Self::_dropped().swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            log::warn!(
+                "The audio recording is missing {dropped} samples, the disk could not keep up"
+            );
+        }
+        Ok(path)
+    }
+
+    /// Called from the audio callback, never blocks
+    pub(super) fn write(samples: Vec<f32>) {
+        if !Self::is_recording() {
+            return;
+        }
+        let count = samples.len();
+        if let Err(TrySendError::Full(_)) = Self::writer().try_send(WriterMessage::Samples(samples))
+        {
+            Self::_dropped().fetch_add(count, Ordering::Relaxed);
+        }
+    }
+
+    fn write_until_disconnected(rx: Receiver<WriterMessage>) {
+        let mut recording: Option<Recording> = None;
+        for message in rx {
+            match message {
+                WriterMessage::Start(new_recording) => {
+                    if let Some(old_recording) = recording.replace(new_recording) {
+                        let _ = old_recording.writer.finalize();
+                    }
+                }
+                WriterMessage::Samples(samples) => {
+                    let Some(Recording { writer, .. }) = recording.as_mut() else {
+                        continue;
+                    };
+                    if let Err(e) = samples
+                        .into_iter()
+                        .try_for_each(|sample| writer.write_sample(sample))
+                    {
+                        log::error!("Could not write audio recording, stopping it: {e:?}");
+                        Self::_active().store(false, Ordering::Relaxed);
+                        // Finalize what we have so far
+                        if let Some(recording) = recording.take() {
+                            let _ = recording.writer.finalize();
+                        }
+                    }
+                }
+                WriterMessage::Stop(reply) => {
+                    let result = recording
+                        .take()
+                        .map(|recording| -> Result<PathBuf> {
+                            recording.writer.finalize()?;
+                            log::info!("Saved audio recording to {:?}", recording.path);
+                            Ok(recording.path)
+                        })
+                        .transpose();
+                    let _ = reply.send(result);
+                }
+            }
+        }
+    }
+}
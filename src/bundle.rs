@@ -2,50 +2,178 @@ use std::{
     fs,
     path::{Path, PathBuf},
     sync::OnceLock,
+    time::Duration,
 };
 
 use anyhow::Result;
 use directories::ProjectDirs;
 use serde::Deserialize;
 
-use crate::{emulation::NesRegion, input::gui::InputButtonsVoca, settings::Settings};
+use crate::{
+    emulation::{high_score::HighScoreConfiguration, NesRegion, RegionOverrides},
+    input::gui::InputButtonsVoca,
+    main_view::gui::MenuHintVoca,
+    settings::{Settings, MAX_PLAYERS},
+    window::egui_winit_wgpu::filter::ShaderConfiguration,
+};
 
 #[derive(Deserialize, Default, Debug)]
 pub struct Vocabulary {
     #[serde(default = "Default::default")]
     pub input_buttons: InputButtonsVoca,
 
+    #[serde(default = "Default::default")]
+    pub menu_hint: MenuHintVoca,
+
     #[cfg(feature = "netplay")]
     #[serde(default = "Default::default")]
     pub netplay: crate::netplay::gui::NetplayVoca,
 }
 
+/// A game in a bundle with several games
+#[derive(Deserialize, Debug)]
+pub struct GameConfiguration {
+    pub name: String,
+    /// The ROM file, relative to the executable (like `rom.nes`)
+    pub rom: PathBuf,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct BuildConfiguration {
     pub name: String,
     pub manufacturer: String,
+    #[serde(default = "Default::default")]
+    pub version: Option<String>,
+    #[serde(default = "Default::default")]
+    pub author: Option<String>,
+    #[serde(default = "Default::default")]
+    pub website: Option<String>,
+    #[serde(default = "Default::default")]
+    pub license: Option<String>,
     pub default_settings: Settings,
+    #[serde(default = "BuildConfiguration::default_supported_nes_regions")]
     pub supported_nes_regions: Vec<NesRegion>,
     #[serde(default = "Default::default")]
+    pub region_overrides: RegionOverrides,
+    #[serde(default = "Default::default")]
     pub enable_vsync: bool,
     #[serde(default = "Default::default")]
     pub start_in_fullscreen: bool,
+    /// Hides the settings menu and ignores hotkeys that change settings
+    #[serde(default = "Default::default")]
+    pub lock_settings: bool,
+    /// Hides the quit option so that players can't exit the game
+    #[serde(default = "Default::default")]
+    pub lock_quit: bool,
+    /// Always plays at the volume from the default settings and hides the volume slider
+    #[serde(default = "Default::default")]
+    pub lock_volume: bool,
+    /// Decides if the game pauses while the menu is open, instead of leaving it to the player
+    #[serde(default = "Default::default")]
+    pub pause_in_menu: Option<bool>,
+    /// Pause the game while the window isn't focused, except during netplay where it would desync the session
+    /// and when the player has chosen to keep playing with a gamepad in the background
+    #[serde(default = "BuildConfiguration::default_pause_on_focus_loss")]
+    pub pause_on_focus_loss: bool,
+    /// Hide the mouse cursor (while playing) after it hasn't moved for this many seconds, 0 hides it right away
+    /// and `None` never hides it. Players can choose their own unless the settings are locked.
+    #[serde(default = "BuildConfiguration::default_cursor_hide_timeout_secs")]
+    pub cursor_hide_timeout_secs: Option<f32>,
+    /// How many times the normal speed fast-forwarding runs at, 1 turns fast-forwarding off
+    #[serde(default = "BuildConfiguration::default_max_fast_forward_speed")]
+    pub max_fast_forward_speed: u8,
+    /// Plug in a Four Score so that four players can play locally (netplay is still two players)
+    #[serde(default = "Default::default")]
+    pub four_player: bool,
+    /// Keep the settings and save data next to the executable instead of in the user's config directory
+    #[serde(default = "Default::default")]
+    pub portable: bool,
     #[serde(default = "Default::default")]
     pub vocabulary: Vocabulary,
+    #[serde(default = "Default::default")]
+    pub high_score: Option<HighScoreConfiguration>,
+    /// A `.pal` file to use instead of the built-in palette, 64 colors (192 bytes) or 512 colors with emphasis (1536 bytes)
+    #[serde(default = "Default::default")]
+    pub palette_path: Option<PathBuf>,
+    /// An optional shader to draw the game through
+    #[serde(default = "Default::default")]
+    pub shader: Option<ShaderConfiguration>,
+    /// More games to choose from in the menu, turning the bundle into a collection
+    #[serde(default = "Default::default")]
+    pub games: Vec<GameConfiguration>,
 
     #[cfg(feature = "netplay")]
     pub netplay: crate::netplay::NetplayBuildConfiguration,
 }
 
 impl BuildConfiguration {
+    fn default_supported_nes_regions() -> Vec<NesRegion> {
+        vec![
+            NesRegion::Auto,
+            NesRegion::Ntsc,
+            NesRegion::Pal,
+            NesRegion::Dendy,
+        ]
+    }
+
+    fn default_pause_on_focus_loss() -> bool {
+        true
+    }
+
+    fn default_max_fast_forward_speed() -> u8 {
+        4
+    }
+
+    fn default_cursor_hide_timeout_secs() -> Option<f32> {
+        Some(1.0)
+    }
+
+    /// How long the mouse needs to be still before the cursor is hidden by default, if it's hidden at all
+    pub fn cursor_hide_timeout(&self) -> Option<Duration> {
+        self.cursor_hide_timeout_secs
+            .map(|seconds| Duration::try_from_secs_f32(seconds.max(0.0)).unwrap_or(Duration::MAX))
+    }
+
+    /// The fast-forward speed, if fast-forwarding is allowed
+    pub fn fast_forward_speed(&self) -> Option<f32> {
+        let speed = self.max_fast_forward_speed.min(16);
+        (speed > 1).then_some(speed as f32)
+    }
+
+    /// How many players can play locally
+    pub fn players(&self) -> usize {
+        if self.four_player {
+            MAX_PLAYERS
+        } else {
+            2
+        }
+    }
+
+    /// The version of the bundle, defaults to the nes-bundler version if not configured
+    pub fn version(&self) -> &str {
+        self.version.as_deref().unwrap_or(env!("CARGO_PKG_VERSION"))
+    }
+
     pub fn get_default_region(&self) -> &NesRegion {
         self.supported_nes_regions
             .first()
             .expect("at least one supported nes region")
     }
+    /// Where the settings, save data and logs go. In order of priority that is the `--data-dir` argument,
+    /// the `NES_BUNDLER_DATA_DIR` environment variable, next to the executable in portable mode
+    /// and finally the user's config directory.
     pub fn get_config_dir(&self) -> Option<PathBuf> {
-        let path = ProjectDirs::from("", &self.manufacturer, &self.name)
-            .map(|pd| pd.config_dir().to_path_buf());
+        let path = Self::data_dir_override()
+            .or_else(|| {
+                self.portable
+                    .then(|| std::env::current_exe().ok())
+                    .flatten()
+                    .and_then(|exe| exe.parent().map(Path::to_path_buf))
+            })
+            .or_else(|| {
+                ProjectDirs::from("", &self.manufacturer, &self.name)
+                    .map(|pd| pd.config_dir().to_path_buf())
+            });
         if let Some(path) = path.clone() {
             if let Err(e) = fs::create_dir_all(path) {
                 log::error!("Could not create path: {:?}", e);
@@ -53,19 +181,81 @@ impl BuildConfiguration {
         }
         path
     }
+
+    /// The data directory given on the command line or in the environment, if any
+    pub fn data_dir_override() -> Option<PathBuf> {
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if let Some(path) = arg.strip_prefix("--data-dir=") {
+                return Some(PathBuf::from(path));
+            }
+            if arg == "--data-dir" {
+                return args.next().map(PathBuf::from);
+            }
+        }
+        std::env::var_os("NES_BUNDLER_DATA_DIR").map(PathBuf::from)
+    }
+}
+
+pub struct BundledGame {
+    pub name: String,
+    pub rom: Vec<u8>,
+    /// Where the battery backed RAM of the game is saved, named after the MD5 of the ROM
+    pub sram_path: PathBuf,
+}
+
+impl BundledGame {
+    fn new(name: String, rom: Vec<u8>, settings_path: &Path) -> Self {
+        let rom_hash = format!("{:x}", md5::compute(&rom));
+        Self {
+            name,
+            rom,
+            sram_path: settings_path.join("sram").join(format!("{rom_hash}.srm")),
+        }
+    }
 }
 
 pub struct Bundle {
     pub settings_path: PathBuf,
     pub config: BuildConfiguration,
-    pub rom: Vec<u8>,
+    /// The games in the bundle, the first one is the bundle's own `rom.nes`
+    pub games: Vec<BundledGame>,
     #[cfg(feature = "netplay")]
     pub netplay_rom: Vec<u8>,
 }
 impl Bundle {
+    fn mem() -> &'static OnceLock<Result<Bundle>> {
+        static MEM: OnceLock<Result<Bundle>> = OnceLock::new();
+        &MEM
+    }
+
+    fn _current() -> &'static Result<Bundle> {
+        Self::mem().get_or_init(Bundle::load)
+    }
+
     pub fn current() -> &'static Bundle {
-        static MEM: OnceLock<Bundle> = OnceLock::new();
-        MEM.get_or_init(|| Bundle::load().expect("bundle to load"))
+        Self::_current().as_ref().expect("bundle to load")
+    }
+
+    /// The game called `name`, or the first game if there is no such game
+    pub fn game(&self, name: Option<&str>) -> &BundledGame {
+        name.and_then(|name| self.games.iter().find(|game| game.name == name))
+            .unwrap_or(&self.games[0])
+    }
+
+    /// The game the player has chosen to play
+    pub fn selected_game(&self) -> &BundledGame {
+        self.game(Settings::current().game.as_deref())
+    }
+
+    /// Like `current` but without panicing if the bundle could not be loaded
+    pub fn try_current() -> Option<&'static Bundle> {
+        Self::_current().as_ref().ok()
+    }
+
+    /// The bundle if it's already loaded, never loads it so it's safe to call from the panic hook
+    pub fn loaded() -> Option<&'static Bundle> {
+        Self::mem().get()?.as_ref().ok()
     }
 
     fn load() -> Result<Bundle> {
@@ -90,10 +280,17 @@ impl Bundle {
 
         log::debug!("Settings path: {:?}", settings_path);
 
+        let mut games = vec![BundledGame::new(config.name.clone(), rom, &settings_path)];
+        for game in &config.games {
+            let rom = fs::read(&game.rom)
+                .map_err(|e| anyhow::anyhow!("Could not read {:?}: {e}", game.rom))?;
+            games.push(BundledGame::new(game.name.clone(), rom, &settings_path));
+        }
+
         Ok(Bundle {
             settings_path,
             config,
-            rom,
+            games,
 
             #[cfg(feature = "netplay")]
             netplay_rom: fs::read(Path::new("netplay-rom.nes"))
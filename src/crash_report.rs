@@ -0,0 +1,86 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{bundle::Bundle, settings::Settings};
+
+/// Extra state (like the netplay state) to include in crash reports
+fn context() -> &'static Mutex<BTreeMap<&'static str, String>> {
+    static MEM: OnceLock<Mutex<BTreeMap<&'static str, String>>> = OnceLock::new();
+    MEM.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Set a piece of state that will be included if we crash
+pub fn set_context(key: &'static str, value: impl Into<String>) {
+    if let Ok(mut context) = context().lock() {
+        context.insert(key, value.into());
+    }
+}
+
+/// Write a crash report to the settings directory and tell the user about it when something panics.
+/// A panic on any thread takes the whole application down, half a running game is worse than none.
+pub fn install() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        default_hook(panic_info);
+
+        let report = report(&panic_info.to_string());
+        let message = match save(&report) {
+            Ok(path) => format!(
+                "The game crashed :(\n\nA crash report was saved to {}",
+                path.display()
+            ),
+            Err(e) => format!("The game crashed :(\n\nThe crash report could not be saved: {e}"),
+        };
+        log::error!("{message}");
+        let title = Bundle::loaded().map_or("NES Bundler", |bundle| bundle.config.name.as_str());
+        let _ = sdl2::messagebox::show_simple_message_box(
+            sdl2::messagebox::MessageBoxFlag::ERROR,
+            title,
+            &message,
+            None::<&sdl2::video::Window>,
+        );
+        std::process::exit(1);
+    }));
+}
+
+fn report(panic: &str) -> String {
+    let thread = std::thread::current();
+    let mut report = format!(
+        "{} {} crashed in thread '{}'\n\n{panic}\n\nBacktrace:\n{}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        thread.name().unwrap_or("<unnamed>"),
+        std::backtrace::Backtrace::force_capture()
+    );
+    if let Ok(context) = context().try_lock() {
+        for (key, value) in context.iter() {
+            report.push_str(&format!("\n{key}: {value}"));
+        }
+        report.push('\n');
+    }
+    report.push_str("\nSettings:\n");
+    // The settings might not be loaded yet, or locked by the panicking thread, don't wait for them
+    match Settings::try_current() {
+        Some(settings) => report.push_str(&serde_yaml::to_string(&*settings).unwrap_or_default()),
+        None => report.push_str("unavailable\n"),
+    }
+    report.push_str("\nRecent log:\n");
+    report.push_str(&crate::logging::recent_log());
+    report
+}
+
+fn save(report: &str) -> std::io::Result<std::path::PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let path = Bundle::loaded()
+        .map(|bundle| bundle.settings_path.clone())
+        .unwrap_or_default()
+        .join(format!("crash-report-{timestamp}.txt"));
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
@@ -0,0 +1,163 @@
+use anyhow::{bail, Result};
+use egui::Ui;
+use serde::{Deserialize, Serialize};
+
+/// A cheat code entered by the player, a Game Genie code or a raw RAM code written as `AAAA:VV` (hex)
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: String,
+    #[serde(default = "Cheat::default_enabled")]
+    pub enabled: bool,
+}
+
+impl Cheat {
+    fn default_enabled() -> bool {
+        true
+    }
+
+    pub fn is_game_genie(&self) -> bool {
+        !self.code.contains(':')
+    }
+
+    pub fn patch(&self) -> Result<CheatPatch> {
+        if self.is_game_genie() {
+            CheatPatch::from_game_genie(&self.code)
+        } else {
+            CheatPatch::from_raw(&self.code)
+        }
+    }
+}
+
+/// What a cheat does, `value` replaces what is at `address` (only if it's `compare`, when set)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheatPatch {
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl CheatPatch {
+    const GAME_GENIE_LETTERS: &'static str = "APZLGITYEOXUKSVN";
+
+    /// Decode a 6 or 8 letter Game Genie code, they always patch the cartridge (0x8000-0xFFFF)
+    pub fn from_game_genie(code: &str) -> Result<Self> {
+        let n = code
+            .trim()
+            .to_uppercase()
+            .chars()
+            .map(|letter| {
+                Self::GAME_GENIE_LETTERS
+                    .find(letter)
+                    .map(|n| n as u16)
+                    .ok_or_else(|| anyhow::anyhow!("'{letter}' is not a Game Genie letter"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if n.len() != 6 && n.len() != 8 {
+            bail!("A Game Genie code has 6 or 8 letters");
+        }
+
+        let address = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+        Ok(if n.len() == 6 {
+            Self {
+                address,
+                value: (value | (n[5] & 8)) as u8,
+                compare: None,
+            }
+        } else {
+            Self {
+                address,
+                value: (value | (n[7] & 8)) as u8,
+                compare: Some(
+                    (((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8)) as u8,
+                ),
+            }
+        })
+    }
+
+    /// Decode a raw code like `0075:09`, these are written to the RAM every frame
+    pub fn from_raw(code: &str) -> Result<Self> {
+        let Some((address, value)) = code.trim().split_once(':') else {
+            bail!("A raw code is written as address:value");
+        };
+        let address = u16::from_str_radix(address.trim(), 16)?;
+        if address >= 0x8000 {
+            bail!("Raw codes can only change the RAM (below 8000), use a Game Genie code for the cartridge");
+        }
+        Ok(Self {
+            address,
+            value: u8::from_str_radix(value.trim(), 16)?,
+            compare: None,
+        })
+    }
+}
+
+impl std::fmt::Display for CheatPatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04X} = {:02X}", self.address, self.value)?;
+        if let Some(compare) = self.compare {
+            write!(f, " if {compare:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct CheatsGui {
+    new_code: String,
+    error: Option<String>,
+}
+
+impl CheatsGui {
+    pub fn ui(&mut self, ui: &mut Ui, cheats: &mut Vec<Cheat>) {
+        let mut remove = None;
+        for (idx, cheat) in cheats.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut cheat.enabled, &cheat.code);
+                if let Ok(patch) = cheat.patch() {
+                    ui.weak(patch.to_string());
+                }
+                if ui.small_button("Remove").clicked() {
+                    remove = Some(idx);
+                }
+            });
+        }
+        if let Some(idx) = remove {
+            cheats.remove(idx);
+        }
+
+        ui.horizontal(|ui| {
+            let input = ui.add(
+                egui::TextEdit::singleline(&mut self.new_code)
+                    .hint_text("Game Genie or 0075:09")
+                    .desired_width(160.0),
+            );
+            let submitted = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if (ui.button("Add").clicked() || submitted) && !self.new_code.trim().is_empty() {
+                let cheat = Cheat {
+                    code: self.new_code.trim().to_uppercase(),
+                    enabled: true,
+                };
+                match cheat.patch() {
+                    Ok(_) => {
+                        cheats.push(cheat);
+                        self.new_code.clear();
+                        self.error = None;
+                    }
+                    Err(e) => self.error = Some(e.to_string()),
+                }
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::DARK_RED, error);
+        }
+        ui.weak("Cheats are turned off during netplay");
+    }
+}
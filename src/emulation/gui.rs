@@ -66,6 +66,43 @@ impl DebugGui {
             let _ = self.emulator_tx.send(EmulatorCommand::SetSpeed(self.speed));
         }
         ui.end_row();
+
+        Self::joypads_ui(
+            ui,
+            super::NesStateHandler::joypads(std::ops::Deref::deref(
+                &self.nes_state.lock().unwrap(),
+            )),
+        );
+        ui.end_row();
+
+        crate::logging::ui(ui);
+        ui.end_row();
+    }
+
+    /// The exact bytes written to the joypads on the last frame, after auto-fire and SOCD
+    fn joypads_ui(
+        ui: &mut egui::Ui,
+        joypads: [crate::input::JoypadState; crate::settings::MAX_PLAYERS],
+    ) {
+        use crate::input::JoypadButton::*;
+        egui::Grid::new("debug-joypads")
+            .num_columns(10)
+            .spacing([6.0, 2.0])
+            .show(ui, |ui| {
+                for (player, joypad) in joypads.iter().enumerate() {
+                    ui.label(format!("P{}", player + 1));
+                    ui.monospace(format!("{:08b}", **joypad));
+                    for button in [Right, Left, Down, Up, Start, Select, B, A] {
+                        let text = egui::RichText::new(button.to_string()).monospace();
+                        ui.label(if joypad.is_pressed(button) {
+                            text.color(egui::Color32::WHITE)
+                        } else {
+                            text.weak()
+                        });
+                    }
+                    ui.end_row();
+                }
+            });
     }
 }
 
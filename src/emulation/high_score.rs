@@ -0,0 +1,59 @@
+use serde::Deserialize;
+
+use super::NesStateHandler;
+use crate::settings::Settings;
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub enum ScoreEncoding {
+    /// Two decimal digits per byte
+    #[default]
+    Bcd,
+    /// One decimal digit per byte
+    Digits,
+    /// A big endian unsigned number
+    Binary,
+}
+
+/// Where a game keeps its high score in memory
+#[derive(Deserialize, Clone, Debug)]
+pub struct HighScoreConfiguration {
+    /// CPU addresses holding the score, most significant first
+    pub addresses: Vec<u16>,
+    #[serde(default = "Default::default")]
+    pub encoding: ScoreEncoding,
+}
+
+impl HighScoreConfiguration {
+    fn read(&self, nes_state: &impl NesStateHandler) -> Option<u64> {
+        self.addresses.iter().try_fold(0u64, |score, addr| {
+            let byte = nes_state.peek(*addr)? as u64;
+            match self.encoding {
+                ScoreEncoding::Bcd => {
+                    let (high, low) = (byte >> 4, byte & 0x0F);
+                    // Anything that's not a valid BCD byte is not a score (yet)
+                    (high < 10 && low < 10).then(|| score * 100 + high * 10 + low)
+                }
+                ScoreEncoding::Digits => (byte < 10).then(|| score * 10 + byte),
+                ScoreEncoding::Binary => score.checked_mul(256).map(|score| score + byte),
+            }
+        })
+    }
+
+    /// Reads the score from memory and persists it if it's a new high score
+    pub fn update(&self, nes_state: &impl NesStateHandler) {
+        let frame = nes_state.frame();
+        // Give the game a moment to initialise its RAM after power on, and there's no need to check every frame
+        if frame < 60 || frame % 30 != 0 {
+            return;
+        }
+        if let Some(score) = self.read(nes_state) {
+            if Settings::current()
+                .high_score
+                .map_or(true, |high| score > high)
+            {
+                log::debug!("New high score: {score}");
+                Settings::current_mut().high_score = Some(score);
+            }
+        }
+    }
+}
@@ -1,9 +1,13 @@
 use std::{
+    fs,
     ops::{Deref, DerefMut},
+    path::PathBuf,
     sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
         mpsc::{channel, Sender},
-        Arc, Mutex, RwLock,
+        Arc, Mutex, OnceLock, RwLock,
     },
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
@@ -14,20 +18,33 @@ use thingbuf::{Recycle, ThingBuf};
 
 use crate::{
     audio::AudioSender,
-    input::JoypadState,
+    bundle::Bundle,
+    input::{macros::Macros, JoypadState},
+    main_view::gui::MainGui,
     settings::{Settings, MAX_PLAYERS},
 };
 
+pub mod cheats;
 pub mod gui;
+pub mod high_score;
+pub mod recorder;
+pub mod rewind;
 pub mod tetanes;
-use self::{gui::EmulatorGui, tetanes::TetanesNesState};
+use self::{
+    cheats::Cheat, gui::EmulatorGui, recorder::VideoRecorder, rewind::RewindBuffer,
+    tetanes::TetanesNesState,
+};
 pub type LocalNesState = TetanesNesState;
 
 pub const NES_WIDTH: u32 = 256;
 pub const NES_WIDTH_4_3: u32 = (NES_WIDTH as f32 * (4.0 / 3.0)) as u32;
 pub const NES_HEIGHT: u32 = 240;
-
-static NTSC_PAL: &[u8] = include_bytes!("../../config/palette.pal");
+/// The number of save state slots, they are stored in `states` in the settings directory
+pub const SAVE_SLOTS: u8 = 10;
+/// How often (in frames) the state hash is updated
+const STATE_HASH_INTERVAL: u32 = 60;
+/// How often the SRAM is checked and written if the game changed it
+const SRAM_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
 
 #[cfg(feature = "netplay")]
 pub type StateHandler = crate::netplay::NetplayStateHandler;
@@ -38,47 +55,700 @@ pub type StateHandler = crate::emulation::LocalNesState;
 pub enum EmulatorCommand {
     Reset(bool),
     SetSpeed(f32),
+    Pause(bool),
+    SaveState(u8),
+    LoadState(u8),
+    /// Power on another game in the bundle
+    ChangeGame(String),
+    /// Start or stop stepping back through the last few seconds
+    Rewind(bool),
+    /// Pause for frame by frame stepping, independent of the pause while the menu is open
+    DebugPause(bool),
+    /// Advance a single frame while debug paused
+    StepFrame,
+    /// Start or stop recording the game to a video file
+    ToggleRecording,
+    /// Write the SRAM now if the game changed it, e.g. before quitting. Answers on the sender when done.
+    FlushSram(Sender<()>),
+    #[cfg(feature = "netplay")]
+    Netplay(crate::netplay::NetplayCommand),
+}
+
+/// State shared between the emulator thread and the rest of the application
+pub struct SharedEmulatorState {
+    pub inputs: RwLock<[JoypadState; MAX_PLAYERS]>,
+    /// The buttons whose turbo buttons are held
+    pub turbo: RwLock<[JoypadState; MAX_PLAYERS]>,
+    netplay_active: AtomicBool,
+    paused: AtomicBool,
+    /// The bits of the effective speed (f32)
+    speed: AtomicU32,
+    room_name: RwLock<Option<String>>,
+    /// The frame and the hash of the state at that frame, while the state hash is shown
+    state_hash: RwLock<Option<(u32, u64)>>,
+    frame: AtomicU32,
+    /// The joypads the game saw on the last frame, while the input overlay is shown
+    joypads: RwLock<Option<[JoypadState; MAX_PLAYERS]>>,
+    /// How many times the game has been reset this session
+    resets: AtomicU32,
+    started_at: Instant,
+    /// A video recording is in progress (or being finalized)
+    recording: AtomicBool,
+}
+
+impl Default for SharedEmulatorState {
+    fn default() -> Self {
+        Self {
+            inputs: Default::default(),
+            turbo: Default::default(),
+            netplay_active: Default::default(),
+            paused: Default::default(),
+            speed: AtomicU32::new(1.0_f32.to_bits()),
+            room_name: Default::default(),
+            state_hash: Default::default(),
+            frame: Default::default(),
+            joypads: Default::default(),
+            resets: Default::default(),
+            started_at: Instant::now(),
+            recording: Default::default(),
+        }
+    }
+}
+
+impl SharedEmulatorState {
+    /// The speed the emulation is actually running at, including any netplay throttling
+    pub fn speed(&self) -> f32 {
+        f32::from_bits(self.speed.load(Ordering::Relaxed))
+    }
+
+    /// True while a netplay session is in control of the emulation
+    pub fn netplay_active(&self) -> bool {
+        self.netplay_active.load(Ordering::Relaxed)
+    }
+
+    /// True while a video is being recorded, or the recording is being written to disk
+    pub fn recording(&self) -> bool {
+        self.recording.load(Ordering::Relaxed)
+    }
+
+    /// True if the emulation is paused (a pause request is ignored during netplay)
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// The code of the private netplay game being hosted or joined
+    pub fn room_name(&self) -> Option<String> {
+        self.room_name.read().unwrap().clone()
+    }
+
+    /// The last frame and the hash of the state at that frame, if the state hash is shown
+    pub fn state_hash(&self) -> Option<(u32, u64)> {
+        *self.state_hash.read().unwrap()
+    }
+
+    /// The joypads the game saw on the last frame (local and remote players), if the input overlay is shown
+    pub fn joypads(&self) -> Option<[JoypadState; MAX_PLAYERS]> {
+        *self.joypads.read().unwrap()
+    }
+
+    /// The frame the emulator is at
+    pub fn frame(&self) -> u32 {
+        self.frame.load(Ordering::Relaxed)
+    }
+
+    /// How many times the game has been reset this session
+    pub fn resets(&self) -> u32 {
+        self.resets.load(Ordering::Relaxed)
+    }
+
+    /// How long the emulator has been running (in real time)
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+}
+
+pub type SharedState = Arc<SharedEmulatorState>;
+
+/// What decides when the emulator advances to the next frame
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum TimingMode {
+    /// Advance as fast as the audio is consumed
+    #[default]
+    Audio,
+    /// Advance once every time a frame has been presented
+    Video,
+    /// Advance as fast as possible
+    Unlocked,
+}
+
+impl std::fmt::Display for TimingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimingMode::Audio => write!(f, "Lock to audio"),
+            TimingMode::Video => write!(f, "Lock to video"),
+            TimingMode::Unlocked => write!(f, "Unlocked"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct EmulationSettings {
+    /// Emulation speed in percent when slow motion is active
+    #[serde(default = "EmulationSettings::default_slow_motion_speed")]
+    pub slow_motion_speed: u8,
+    #[serde(default = "Default::default")]
+    pub timing_mode: TimingMode,
+    #[serde(default = "EmulationSettings::default_pause_in_menu")]
+    pub pause_in_menu: bool,
+    /// Show the emulation speed when it's not 1x
+    #[serde(default = "EmulationSettings::default_show_speed")]
+    pub show_speed: bool,
+    /// Ask before quitting (e.g. with the window close button) during netplay
+    #[serde(default = "EmulationSettings::default_confirm_quit_in_netplay")]
+    pub confirm_quit_in_netplay: bool,
+    /// Ask before quitting from the menu when it's navigated with a gamepad, so mashing a button can't quit
+    #[serde(default = "EmulationSettings::default_confirm_quit_on_gamepad")]
+    pub confirm_quit_on_gamepad: bool,
+    /// Run single-player at this rate instead of the region's native one
+    #[serde(default = "Default::default")]
+    pub fps_override: Option<u16>,
+    /// Show a hash of the emulator state, refreshed every `STATE_HASH_INTERVAL` frames
+    #[serde(default = "Default::default")]
+    pub show_state_hash: bool,
+    /// Show what both players are pressing, e.g. for streaming netplay matches
+    #[serde(default = "Default::default")]
+    pub show_inputs: bool,
+    /// Show the frame, uptime and reset count, handy for bug reports
+    #[serde(default = "Default::default")]
+    pub show_stats: bool,
+    /// Show the emulator and render frame rates and how full the audio buffer is
+    #[serde(default = "Default::default")]
+    pub show_performance: bool,
+    /// Never advance faster than the region's frame rate (times the speed), even if the audio doesn't hold the emulation back
+    #[serde(default = "Default::default")]
+    pub real_time_clamp: bool,
+    /// Keep the last few seconds of single-player play to rewind through while the rewind key is held
+    #[serde(default = "EmulationSettings::default_rewind")]
+    pub rewind: bool,
+    /// Frames between the rewind snapshots
+    #[serde(default = "EmulationSettings::default_rewind_interval")]
+    pub rewind_interval: u8,
+    /// How far back (in seconds) rewinding can go
+    #[serde(default = "EmulationSettings::default_rewind_seconds")]
+    pub rewind_seconds: u8,
+}
+
+impl Default for EmulationSettings {
+    fn default() -> Self {
+        Self {
+            slow_motion_speed: Self::default_slow_motion_speed(),
+            timing_mode: TimingMode::default(),
+            pause_in_menu: Self::default_pause_in_menu(),
+            show_speed: Self::default_show_speed(),
+            confirm_quit_in_netplay: Self::default_confirm_quit_in_netplay(),
+            confirm_quit_on_gamepad: Self::default_confirm_quit_on_gamepad(),
+            fps_override: None,
+            show_state_hash: false,
+            show_stats: false,
+            show_performance: false,
+            show_inputs: false,
+            real_time_clamp: false,
+            rewind: Self::default_rewind(),
+            rewind_interval: Self::default_rewind_interval(),
+            rewind_seconds: Self::default_rewind_seconds(),
+        }
+    }
+}
+
+impl EmulationSettings {
+    fn default_slow_motion_speed() -> u8 {
+        50
+    }
+
+    fn default_pause_in_menu() -> bool {
+        true
+    }
+
+    fn default_show_speed() -> bool {
+        true
+    }
+
+    fn default_confirm_quit_in_netplay() -> bool {
+        true
+    }
+
+    fn default_confirm_quit_on_gamepad() -> bool {
+        true
+    }
+
+    fn default_rewind() -> bool {
+        true
+    }
+
+    fn default_rewind_interval() -> u8 {
+        6
+    }
+
+    fn default_rewind_seconds() -> u8 {
+        10
+    }
+
+    pub fn rewind_interval(&self) -> u32 {
+        self.rewind_interval.clamp(1, 30) as u32
+    }
+
+    /// How many rewind snapshots to keep
+    pub fn rewind_capacity(&self) -> usize {
+        (self.rewind_seconds.clamp(1, 60) as usize * 60).div_ceil(self.rewind_interval() as usize)
+    }
+
+    /// If the game should pause while the menu is open, the bundle can decide this for the player
+    pub fn pause_in_menu(&self) -> bool {
+        Bundle::current()
+            .config
+            .pause_in_menu
+            .unwrap_or(self.pause_in_menu)
+    }
+
+    pub fn slow_motion_factor(&self) -> f32 {
+        self.slow_motion_speed.clamp(25, 75) as f32 / 100.0
+    }
+
+    pub fn fps_override(&self) -> Option<u16> {
+        self.fps_override.map(|fps| fps.clamp(30, 240))
+    }
+
+    /// How much faster (or slower) than the native rate of `region` to run when the FPS is overridden
+    pub fn fps_factor(&self, region: &NesRegion) -> f32 {
+        self.fps_override()
+            .map(|fps| fps as f32 / region.to_fps())
+            .unwrap_or(1.0)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::Slider::new(&mut self.slow_motion_speed, 25..=75)
+                .step_by(5.0)
+                .suffix("%")
+                .text("Slow motion speed"),
+        );
+        egui::ComboBox::from_label("Timing")
+            .selected_text(self.timing_mode.to_string())
+            .show_ui(ui, |ui| {
+                for timing_mode in [TimingMode::Audio, TimingMode::Video, TimingMode::Unlocked] {
+                    let text = timing_mode.to_string();
+                    ui.selectable_value(&mut self.timing_mode, timing_mode, text);
+                }
+            })
+            .response
+            .on_hover_text("Netplay always uses its own timing");
+        if self.timing_mode != TimingMode::Unlocked {
+            ui.checkbox(&mut self.real_time_clamp, "Never run faster than real time")
+                .on_hover_text(
+                    "Keeps the game at the right speed even if the audio device isn't pacing it",
+                );
+        }
+        if Bundle::current().config.pause_in_menu.is_none() {
+            ui.checkbox(&mut self.pause_in_menu, "Pause when the menu is open")
+                .on_hover_text("Netplay games keep running");
+        }
+        ui.checkbox(&mut self.show_speed, "Show emulation speed");
+        ui.checkbox(&mut self.show_state_hash, "Show state hash")
+            .on_hover_text("A hash of the complete emulator state, identical runs have identical hashes at the same frame");
+        ui.checkbox(&mut self.show_inputs, "Show controller inputs")
+            .on_hover_text("What each player is pressing, including the other player in netplay");
+        ui.checkbox(&mut self.show_stats, "Show emulator stats")
+            .on_hover_text(
+            "The current frame, how long the game has been running and how many times it was reset",
+        );
+        ui.checkbox(&mut self.show_performance, "Show performance")
+            .on_hover_text("The emulator and render frame rates and how full the audio buffer is");
+        let mut override_fps = self.fps_override.is_some();
+        ui.checkbox(&mut override_fps, "Override FPS")
+            .on_hover_text("Not used during netplay");
+        if override_fps {
+            let mut fps = self.fps_override().unwrap_or(60);
+            ui.add(egui::Slider::new(&mut fps, 30..=240).suffix(" FPS"));
+            self.fps_override = Some(fps);
+        } else {
+            self.fps_override = None;
+        }
+        if cfg!(feature = "netplay") {
+            ui.checkbox(
+                &mut self.confirm_quit_in_netplay,
+                "Confirm before quitting during netplay",
+            );
+        }
+        ui.checkbox(
+            &mut self.confirm_quit_on_gamepad,
+            "Confirm before quitting with a gamepad",
+        );
+        ui.checkbox(&mut self.rewind, "Rewind")
+            .on_hover_text("Hold the rewind key to go back in time, not used during netplay");
+        if self.rewind {
+            ui.add(
+                egui::Slider::new(&mut self.rewind_seconds, 1..=60)
+                    .suffix(" s")
+                    .text("Rewind length"),
+            );
+            ui.add(
+                egui::Slider::new(&mut self.rewind_interval, 1..=30)
+                    .text("Frames between rewind snapshots"),
+            )
+            .on_hover_text("Fewer frames rewind more smoothly but use more memory and CPU");
+        }
+    }
 }
+
 pub struct Emulator {}
 pub const SAMPLE_RATE: f32 = 44_100.0;
 
+#[cfg(not(feature = "netplay"))]
+type NesState = LocalNesState;
+#[cfg(feature = "netplay")]
+type NesState = crate::netplay::NetplayStateHandler;
+
 impl Emulator {
     pub fn new() -> Result<Self> {
         Ok(Self {})
     }
 
+    /// Where save state `slot` is stored, the extra games in a collection get a directory each
+    fn save_state_path(slot: u8) -> PathBuf {
+        let bundle = Bundle::current();
+        let game = bundle.selected_game();
+        let mut path = bundle.settings_path.join("states");
+        if !std::ptr::eq(game, &bundle.games[0]) {
+            path = path.join(&game.name);
+        }
+        path.join(format!("slot_{}.state", slot + 1))
+    }
+
+    fn write_save_state(slot: u8, state: &LocalNesState) -> Result<()> {
+        let path = Self::save_state_path(slot);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, state.to_bytes()?)?;
+        log::info!("Saved state to {path:?}");
+        Ok(())
+    }
+
+    /// Read save state `slot` into `state`, returns false if the slot is empty
+    fn read_save_state(slot: u8, state: &mut LocalNesState) -> Result<bool> {
+        let path = Self::save_state_path(slot);
+        if !path.exists() {
+            return Ok(false);
+        }
+        state.load_bytes(&fs::read(&path)?)?;
+        log::info!("Loaded state from {path:?}");
+        Ok(true)
+    }
+
+    /// Write the SRAM of the selected game to its file, once it's there the copy in the settings is dropped
+    fn write_sram(sram: &[u8]) -> Result<()> {
+        let path = &Bundle::current().selected_game().sram_path;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, sram)?;
+        log::debug!("Saved SRAM to {path:?}");
+        if Settings::current().save_state.is_some() {
+            Settings::current_mut().save_state = None;
+        }
+        Ok(())
+    }
+
+    /// Write the SRAM to disk if the game changed it since `last_sram`
+    fn flush_sram(nes_state: &impl NesStateHandler, last_sram: &mut Vec<u8>) {
+        if let Some(sram) = nes_state.save_sram() {
+            if sram != last_sram.as_slice() {
+                match Self::write_sram(sram) {
+                    Ok(()) => *last_sram = sram.to_vec(),
+                    Err(e) => log::error!("Could not save SRAM: {e:?}"),
+                }
+            }
+        }
+    }
+
+    /// Wait until the next frame should be produced, yielding to the runtime while waiting.
+    /// With a `frame_duration` the frames are clamped to real time, returns when the next one is due.
+    async fn pace_frame(
+        timing_mode: TimingMode,
+        frame_buffer: &VideoBufferPool,
+        frame_duration: Option<Duration>,
+        next_frame_at: Option<Instant>,
+    ) -> Option<Instant> {
+        if timing_mode == TimingMode::Video {
+            // Wait for the last frame to be presented before producing the next one
+            while !frame_buffer.is_empty() {
+                tokio::time::sleep(Duration::from_micros(500)).await;
+            }
+        }
+
+        let frame_duration = frame_duration?;
+        let now = Instant::now();
+        // Start over instead of catching up if we fell more than a frame behind
+        let due = next_frame_at
+            .filter(|due| now.saturating_duration_since(*due) < frame_duration)
+            .unwrap_or(now);
+        tokio::time::sleep_until(due.into()).await;
+        Some(due + frame_duration)
+    }
+
+    /// Write the recording to disk on a thread of its own, muxing the video and audio can take a while
+    fn finish_recording(recorder: VideoRecorder, shared_state: SharedState) {
+        MainGui::show_toast("Saving recording...");
+        std::thread::spawn(move || {
+            match recorder.finish() {
+                Ok(path) => MainGui::show_toast(format!(
+                    "Saved recording {}",
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                )),
+                Err(e) => {
+                    log::error!("Could not save recording: {e:?}");
+                    MainGui::show_toast("Could not save recording");
+                }
+            }
+            shared_state.recording.store(false, Ordering::Relaxed);
+        });
+    }
+
+    /// Power on the selected game
+    fn start_nes_state() -> Result<NesState> {
+        #[cfg(not(feature = "netplay"))]
+        {
+            let rom = &Bundle::current().selected_game().rom;
+            let region = Settings::current_mut().get_nes_region().clone();
+            LocalNesState::start_rom(rom, true, &region)
+        }
+
+        #[cfg(feature = "netplay")]
+        crate::netplay::NetplayStateHandler::new()
+    }
+
     pub async fn start_thread(
         &self,
         audio_tx: AudioSender,
-        inputs: Arc<RwLock<[JoypadState; MAX_PLAYERS]>>,
+        shared_state: SharedState,
         frame_buffer: VideoBufferPool,
     ) -> Result<(EmulatorGui, Sender<EmulatorCommand>)> {
-        #[cfg(not(feature = "netplay"))]
-        let nes_state = crate::emulation::LocalNesState::start_rom(
-            &crate::bundle::Bundle::current().rom,
-            true,
-            Settings::current_mut().get_nes_region(),
-        )?;
-
-        #[cfg(feature = "netplay")]
-        let nes_state = crate::netplay::NetplayStateHandler::new()?;
-
-        let nes_state = Arc::new(Mutex::new(nes_state));
+        let nes_state = Arc::new(Mutex::new(Self::start_nes_state()?));
         let (command_tx, command_rx) = channel();
         let audio_buffer = AudioBufferPool::new();
 
         tokio::task::spawn({
             let nes_state = nes_state.clone();
             async move {
+                let mut speed = 1.0;
+                // The speed last given to the emulator, including any FPS override
+                let mut applied_speed = None;
+                let mut netplay_active = false;
+                let mut pause_requested = false;
+                let mut debug_paused = false;
+                let mut step_requested = false;
+                // When the next frame is due if the emulation is clamped to real time
+                let mut next_frame_at: Option<Instant> = None;
+                let mut rewind_buffer = RewindBuffer::default();
+                // The SRAM as it was last written to disk
+                let mut last_sram: Vec<u8> = Vec::new();
+                let mut sram_flushed_at = Instant::now();
+                let mut rewinding = false;
+                let recorder: Arc<Mutex<Option<VideoRecorder>>> = Default::default();
                 loop {
                     for command in command_rx.try_iter() {
                         let mut nes_state = nes_state.lock().unwrap();
                         match command {
-                            EmulatorCommand::Reset(hard) => nes_state.reset(hard),
-                            EmulatorCommand::SetSpeed(speed) => nes_state.set_speed(speed),
+                            EmulatorCommand::Reset(hard) => {
+                                nes_state.reset(hard);
+                                shared_state.resets.fetch_add(1, Ordering::Relaxed);
+                                // Also covers region changes, they reset the console
+                                rewind_buffer.clear();
+                            }
+                            EmulatorCommand::Rewind(rewind) => rewinding = rewind,
+                            EmulatorCommand::SetSpeed(new_speed) => speed = new_speed,
+                            EmulatorCommand::Pause(pause) => pause_requested = pause,
+                            EmulatorCommand::DebugPause(pause) => debug_paused = pause,
+                            EmulatorCommand::StepFrame => step_requested = debug_paused,
+                            EmulatorCommand::FlushSram(done) => {
+                                Self::flush_sram(&*nes_state, &mut last_sram);
+                                let _ = done.send(());
+                            }
+                            EmulatorCommand::ToggleRecording => {
+                                let mut recorder = recorder.lock().unwrap();
+                                match recorder.take() {
+                                    Some(recorder) => {
+                                        Self::finish_recording(recorder, shared_state.clone())
+                                    }
+                                    None if !shared_state.recording() => {
+                                        let region =
+                                            Settings::current_mut().get_nes_region().clone();
+                                        match VideoRecorder::start(
+                                            region.to_fps(),
+                                            region.target_sample_rate(),
+                                        ) {
+                                            Ok(new_recorder) => {
+                                                *recorder = Some(new_recorder);
+                                                shared_state
+                                                    .recording
+                                                    .store(true, Ordering::Relaxed);
+                                                MainGui::show_toast("Recording started");
+                                            }
+                                            Err(e) => {
+                                                log::error!("Could not start recording: {e:?}");
+                                                MainGui::show_toast("Could not start recording");
+                                            }
+                                        }
+                                    }
+                                    // The last recording is still being written
+                                    None => {}
+                                }
+                            }
+                            EmulatorCommand::SaveState(slot) => {
+                                let saved = nes_state
+                                    .save_state()
+                                    .map(|state| Self::write_save_state(slot, &state));
+                                MainGui::show_toast(match saved {
+                                    Some(Ok(())) => format!("Saved to slot {}", slot + 1),
+                                    Some(Err(e)) => {
+                                        log::error!("Could not save slot {}: {e:?}", slot + 1);
+                                        format!("Could not save to slot {}", slot + 1)
+                                    }
+                                    None => "Can't save during netplay".to_string(),
+                                });
+                            }
+                            EmulatorCommand::LoadState(slot) => {
+                                // Start from the current state so there is something to load the slot into
+                                let loaded = nes_state.save_state().map(|mut state| {
+                                    Self::read_save_state(slot, &mut state)
+                                        .map(|found| found.then(|| nes_state.load_state(&state)))
+                                });
+                                MainGui::show_toast(match loaded {
+                                    Some(Ok(Some(true))) => {
+                                        // The snapshot has the speed it was saved with
+                                        applied_speed = None;
+                                        rewind_buffer.clear();
+                                        format!("Loaded slot {}", slot + 1)
+                                    }
+                                    Some(Ok(None)) => format!("Slot {} is empty", slot + 1),
+                                    Some(Err(e)) => {
+                                        log::error!("Could not load slot {}: {e:?}", slot + 1);
+                                        format!("Could not load slot {}", slot + 1)
+                                    }
+                                    None | Some(Ok(Some(false))) => {
+                                        log::warn!(
+                                            "Not loading slot {} during netplay, it would desync",
+                                            slot + 1
+                                        );
+                                        "Can't load during netplay".to_string()
+                                    }
+                                });
+                            }
+                            EmulatorCommand::ChangeGame(name) => {
+                                if nes_state.netplay_active() {
+                                    MainGui::show_toast("Can't change game during netplay");
+                                    continue;
+                                }
+                                // Save the game that is being left before switching
+                                if let Some(sram) = nes_state.save_sram() {
+                                    if let Err(e) = Self::write_sram(sram) {
+                                        log::error!("Could not save SRAM: {e:?}");
+                                    }
+                                }
+                                if Settings::current_mut().select_game(&name) {
+                                    log::info!("Changing game to {name}");
+                                    match Self::start_nes_state() {
+                                        Ok(new_nes_state) => {
+                                            *nes_state = new_nes_state;
+                                            rewind_buffer.clear();
+                                            last_sram.clear();
+                                            applied_speed = None;
+                                            next_frame_at = None;
+                                        }
+                                        Err(e) => {
+                                            log::error!("Could not start {name}: {e:?}");
+                                            MainGui::show_toast(format!("Could not start {name}"));
+                                        }
+                                    }
+                                }
+                            }
+                            #[cfg(feature = "netplay")]
+                            EmulatorCommand::Netplay(command) => nes_state.handle_command(command),
+                        }
+                    }
+
+                    // The netplay session is in charge of the speed while it's active
+                    if !netplay_active {
+                        let target_speed = {
+                            let settings = Settings::current();
+                            speed * settings.emulation.fps_factor(&settings.nes_region())
+                        };
+                        if applied_speed != Some(target_speed) {
+                            nes_state.lock().unwrap().set_speed(target_speed);
+                            applied_speed = Some(target_speed);
                         }
                     }
 
+                    // Netplay can't be paused, the session needs to keep advancing
+                    let paused = (pause_requested || debug_paused)
+                        && !nes_state.lock().unwrap().netplay_active();
+                    shared_state.paused.store(paused, Ordering::Relaxed);
+                    if paused {
+                        if std::mem::take(&mut step_requested) {
+                            // The audio device is paused, so the frame is stepped without sound
+                            let joypad_state = *shared_state.inputs.read().unwrap();
+                            let mut nes_state = nes_state.lock().unwrap();
+                            nes_state.advance(
+                                joypad_state,
+                                &mut NESBuffers {
+                                    video: frame_buffer.push_ref().as_deref_mut().ok(),
+                                    audio: None,
+                                },
+                            );
+                            shared_state
+                                .frame
+                                .store(nes_state.frame(), Ordering::Relaxed);
+                        } else {
+                            std::thread::sleep(Duration::from_millis(10));
+                        }
+                        continue;
+                    }
+
+                    if rewinding && !netplay_active {
+                        let mut nes_state = nes_state.lock().unwrap();
+                        if rewind_buffer.rewind(&mut *nes_state) {
+                            // Show where we rewound to, without any sound
+                            nes_state.advance(
+                                Default::default(),
+                                &mut NESBuffers {
+                                    video: frame_buffer.push_ref().as_deref_mut().ok(),
+                                    audio: None,
+                                },
+                            );
+                        }
+                        drop(nes_state);
+                        std::thread::sleep(Duration::from_secs_f32(1.0 / 60.0));
+                        continue;
+                    }
+
+                    // Netplay is paced by the session
+                    let timing_mode = if netplay_active {
+                        TimingMode::Audio
+                    } else {
+                        Settings::current().emulation.timing_mode
+                    };
+                    let real_time_clamp = !netplay_active
+                        && timing_mode != TimingMode::Unlocked
+                        && Settings::current().emulation.real_time_clamp;
+                    let frame_duration = real_time_clamp.then(|| {
+                        let fps = Settings::current().nes_region().to_fps()
+                            * nes_state.lock().unwrap().speed();
+                        Duration::from_secs_f32(1.0 / fps)
+                    });
+                    next_frame_at =
+                        Self::pace_frame(timing_mode, &frame_buffer, frame_duration, next_frame_at)
+                            .await;
+
                     // Run advance and audio pushing in parallel
                     let _ = tokio::join!(
                         tokio::spawn({
@@ -89,7 +759,12 @@ impl Emulator {
                                 puffin::profile_scope!("push audio");
                                 audio_buffer.pop_with(|audio_buffer| {
                                     for s in audio_buffer.drain(..) {
-                                        let _ = audio_tx.send(s);
+                                        if timing_mode == TimingMode::Audio {
+                                            // Blocks until there is room, which is what locks the emulation to the audio
+                                            let _ = audio_tx.send(s);
+                                        } else {
+                                            let _ = audio_tx.try_send(s);
+                                        }
                                     }
                                 });
                             }
@@ -97,27 +772,130 @@ impl Emulator {
                         tokio::spawn({
                             let frame_buffer = frame_buffer.clone();
                             let nes_state = nes_state.clone();
-                            let joypad_state = *inputs.read().unwrap();
+                            let joypad_state = *shared_state.inputs.read().unwrap();
+                            // Macros are off during netplay, there is no way to tell if the other player is fine with them
+                            let joypad_state = if netplay_active {
+                                joypad_state
+                            } else {
+                                Macros::current().apply(joypad_state)
+                            };
+                            let turbo = *shared_state.turbo.read().unwrap();
+                            let auto_fire = Settings::current().input.auto_fire;
+                            // Cheats would desync a netplay game
+                            let cheats = if netplay_active {
+                                Vec::new()
+                            } else {
+                                Settings::current().cheats.clone()
+                            };
                             let audio_buffer = audio_buffer.clone();
+                            let recorder = recorder.clone();
                             async move {
+                                let mut nes_state = nes_state.lock().unwrap();
+                                nes_state.apply_cheats(&cheats);
+                                let frame = nes_state.frame();
+                                // Turbo is folded into the local input, so netplay peers get the same presses
+                                let joypad_state: [JoypadState; MAX_PLAYERS] =
+                                    std::array::from_fn(|player| {
+                                        auto_fire.apply_turbo(
+                                            auto_fire.apply(joypad_state[player], frame),
+                                            turbo[player],
+                                            frame,
+                                        )
+                                    });
                                 log::trace!("Advance NES with joypad state {:?}", joypad_state);
-                                nes_state.lock().unwrap().advance(
-                                    joypad_state,
-                                    &mut NESBuffers {
-                                        video: frame_buffer.push_ref().as_deref_mut().ok(),
-                                        audio: audio_buffer.push_ref().as_deref_mut().ok(),
-                                    },
-                                );
+                                let mut video = frame_buffer.push_ref();
+                                let mut audio = audio_buffer.push_ref();
+                                let mut buffers = NESBuffers {
+                                    video: video.as_deref_mut().ok(),
+                                    audio: audio.as_deref_mut().ok(),
+                                };
+                                match &mut *recorder.lock().unwrap() {
+                                    // The recording gets every frame, even the ones the window doesn't have room for
+                                    Some(recorder) => recorder.record(
+                                        |buffers| nes_state.advance(joypad_state, buffers),
+                                        buffers,
+                                    ),
+                                    None => nes_state.advance(joypad_state, &mut buffers),
+                                }
                             }
                         })
                     );
-                    use base64::engine::general_purpose::STANDARD_NO_PAD as b64;
-                    use base64::Engine;
-                    Settings::current_mut().save_state = nes_state
-                        .lock()
-                        .unwrap()
-                        .save_sram()
-                        .map(|sram| b64.encode(sram));
+                    {
+                        let mut recorder = recorder.lock().unwrap();
+                        if recorder.as_ref().is_some_and(|recorder| recorder.failed) {
+                            MainGui::show_toast("Recording failed");
+                            if let Some(recorder) = recorder.take() {
+                                Self::finish_recording(recorder, shared_state.clone());
+                            }
+                        }
+                    }
+
+                    let mut nes_state = nes_state.lock().unwrap();
+                    if netplay_active != nes_state.netplay_active() {
+                        netplay_active = nes_state.netplay_active();
+                        if !netplay_active {
+                            // Back to local play, restore the requested speed
+                            applied_speed = None;
+                        }
+                        shared_state
+                            .netplay_active
+                            .store(netplay_active, Ordering::Relaxed);
+                        crate::crash_report::set_context(
+                            "Netplay active",
+                            netplay_active.to_string(),
+                        );
+                    }
+                    shared_state
+                        .speed
+                        .store(nes_state.speed().to_bits(), Ordering::Relaxed);
+                    shared_state
+                        .frame
+                        .store(nes_state.frame(), Ordering::Relaxed);
+                    let room_name = nes_state.room_name();
+                    if *shared_state.room_name.read().unwrap() != room_name {
+                        crate::crash_report::set_context(
+                            "Netplay room",
+                            room_name.clone().unwrap_or("-".to_string()),
+                        );
+                        *shared_state.room_name.write().unwrap() = room_name;
+                    }
+
+                    *shared_state.joypads.write().unwrap() = Settings::current()
+                        .emulation
+                        .show_inputs
+                        .then(|| nes_state.joypads());
+
+                    if Settings::current().emulation.show_state_hash {
+                        let frame = nes_state.frame();
+                        let mut state_hash = shared_state.state_hash.write().unwrap();
+                        // Several frames can pass at once (e.g. when netplay catches up), hash once per interval
+                        if !state_hash.is_some_and(|(hashed, _)| {
+                            hashed / STATE_HASH_INTERVAL == frame / STATE_HASH_INTERVAL
+                        }) {
+                            *state_hash = nes_state.state_hash().map(|hash| (frame, hash));
+                        }
+                    } else {
+                        *shared_state.state_hash.write().unwrap() = None;
+                    }
+
+                    if let Some(high_score) = &Bundle::current().config.high_score {
+                        high_score.update(&*nes_state);
+                    }
+
+                    // Rewinding would fight the rollbacks of a netplay session
+                    {
+                        let settings = Settings::current();
+                        if settings.emulation.rewind && !netplay_active {
+                            rewind_buffer.record(&*nes_state, &settings.emulation);
+                        } else {
+                            rewind_buffer.clear();
+                        }
+                    }
+
+                    if sram_flushed_at.elapsed() >= SRAM_FLUSH_INTERVAL {
+                        sram_flushed_at = Instant::now();
+                        Self::flush_sram(&*nes_state, &mut last_sram);
+                    }
                 }
             }
         });
@@ -129,28 +907,106 @@ pub trait NesStateHandler {
     fn advance(&mut self, joypad_state: [JoypadState; MAX_PLAYERS], buffers: &mut NESBuffers);
     fn reset(&mut self, hard: bool);
     fn set_speed(&mut self, speed: f32);
+    fn speed(&self) -> f32;
     fn save_sram(&self) -> Option<&[u8]>;
-    #[cfg(feature = "netplay")]
+    /// A snapshot of the emulator for a save state, `None` if saving isn't possible right now (e.g. during netplay)
+    fn save_state(&self) -> Option<LocalNesState>;
+    /// Restore a snapshot from `save_state`, returns false if loading isn't possible right now
+    fn load_state(&mut self, state: &LocalNesState) -> bool;
     fn frame(&self) -> u32;
+    /// A hash of the complete machine state (CPU, RAM, PPU...), identical for identical runs
+    fn state_hash(&self) -> Option<u64>;
+    /// Read a byte from the CPU address space without side effects
+    fn peek(&self, addr: u16) -> Option<u8>;
+    /// Apply the enabled cheats before the next frame
+    fn apply_cheats(&mut self, _cheats: &[Cheat]) {}
+    fn netplay_active(&self) -> bool {
+        false
+    }
+    fn room_name(&self) -> Option<String> {
+        None
+    }
+    /// The joypad bytes that were written to the NES on the last frame, for all players (also the remote one in netplay)
+    fn joypads(&self) -> [JoypadState; MAX_PLAYERS];
 }
 
 #[derive(Clone, Serialize, Deserialize, Hash, Debug, PartialEq)]
 pub enum NesRegion {
+    /// Use the region from the ROM header
+    Auto,
     Pal,
     Ntsc,
     Dendy,
 }
 
 impl NesRegion {
+    fn _detected() -> &'static RwLock<Option<NesRegion>> {
+        static MEM: OnceLock<RwLock<Option<NesRegion>>> = OnceLock::new();
+        MEM.get_or_init(|| RwLock::new(None))
+    }
+
+    /// Remember the region of the loaded ROM, it's what `Auto` resolves to
+    pub fn set_detected(region: NesRegion) {
+        *Self::_detected().write().unwrap() = Some(region);
+    }
+
+    /// The actual region, `Auto` is resolved to the region of the loaded ROM (NTSC until a ROM is loaded)
+    pub fn resolved(&self) -> NesRegion {
+        match self {
+            NesRegion::Auto => Self::_detected()
+                .read()
+                .unwrap()
+                .clone()
+                .unwrap_or(NesRegion::Ntsc),
+            region => region.clone(),
+        }
+    }
+
     pub fn to_fps(&self) -> f32 {
+        match self.resolved() {
+            NesRegion::Pal | NesRegion::Dendy => 50.006_977,
+            _ => 60.098_812,
+        }
+    }
+
+    /// The rate the emulated audio is resampled to before it's sent to the audio device
+    pub fn target_sample_rate(&self) -> f32 {
+        match self.resolved() {
+            // Downsample a tiny bit extra to match the most common screen refresh rate (60hz)
+            NesRegion::Ntsc => SAMPLE_RATE * (self.to_fps() / 60.0),
+            _ => SAMPLE_RATE,
+        }
+    }
+
+    /// The number of audio samples produced per frame (~735 for NTSC and ~882 for PAL/Dendy)
+    pub fn samples_per_frame(&self) -> f32 {
+        match self {
+            // The ROM might not be loaded yet, so assume the region with the most samples per frame
+            NesRegion::Auto => NesRegion::Pal.samples_per_frame(),
+            region => region.target_sample_rate() / region.to_fps(),
+        }
+    }
+}
+
+impl std::fmt::Display for NesRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            NesRegion::Pal => 50.006_977,
-            NesRegion::Ntsc => 60.098_812,
-            NesRegion::Dendy => 50.006_977,
+            NesRegion::Auto => write!(f, "Auto (from ROM)"),
+            region => write!(f, "{region:?}"),
         }
     }
 }
 
+/// Clock the PPU and/or APU as another region than the CPU.
+/// Only needed for a few multi-region or hacked carts that depend on those hardware quirks.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct RegionOverrides {
+    #[serde(default = "Default::default")]
+    pub ppu: Option<NesRegion>,
+    #[serde(default = "Default::default")]
+    pub apu: Option<NesRegion>,
+}
+
 pub struct NESBuffers<'a> {
     pub audio: Option<&'a mut NESAudioFrame>,
     pub video: Option<&'a mut NESVideoFrame>,
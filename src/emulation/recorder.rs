@@ -0,0 +1,164 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+
+use crate::bundle::Bundle;
+
+use super::{NESAudioFrame, NESBuffers, NESVideoFrame, NES_HEIGHT, NES_WIDTH};
+
+/// Records the emulator output to a video file using an `ffmpeg` executable on the path.
+/// The frames are piped to ffmpeg as they are emulated while the sound goes to a temporary file,
+/// the two are muxed together into an mp4 when the recording stops.
+pub struct VideoRecorder {
+    path: PathBuf,
+    video_path: PathBuf,
+    audio_path: PathBuf,
+    ffmpeg: Child,
+    video_in: ChildStdin,
+    audio_out: BufWriter<File>,
+    sample_rate: f32,
+    /// Audio samples per frame in the recording, the emulator produces fewer or more when the speed is changed
+    samples_per_frame: f32,
+    /// The fraction of a sample carried over to the next frame
+    sample_remainder: f32,
+    video: NESVideoFrame,
+    audio: NESAudioFrame,
+    /// Writing to ffmpeg failed and the recording should be stopped
+    pub failed: bool,
+}
+
+impl VideoRecorder {
+    pub fn start(fps: f32, sample_rate: f32) -> Result<Self> {
+        let dir = Bundle::current().settings_path.join("recordings");
+        fs::create_dir_all(&dir)?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = dir.join(format!("recording_{timestamp}.mp4"));
+        let video_path = dir.join(format!(".recording_{timestamp}.video.mkv"));
+        let audio_path = dir.join(format!(".recording_{timestamp}.audio.raw"));
+
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-loglevel",
+                "error",
+                "-f",
+                "rawvideo",
+                "-pixel_format",
+                "rgba",
+            ])
+            .args(["-video_size", &format!("{NES_WIDTH}x{NES_HEIGHT}")])
+            .args(["-framerate", &fps.to_string(), "-i", "-"])
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", "-crf", "18"])
+            .arg(&video_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .context("Could not start ffmpeg, is it installed?")?;
+        let video_in = ffmpeg.stdin.take().context("No stdin for ffmpeg")?;
+        log::info!("Recording to {path:?}");
+
+        Ok(Self {
+            path,
+            video_path,
+            audio_out: BufWriter::new(File::create(&audio_path)?),
+            audio_path,
+            ffmpeg,
+            video_in,
+            sample_rate,
+            samples_per_frame: sample_rate / fps,
+            sample_remainder: 0.0,
+            video: NESVideoFrame::new(),
+            audio: NESAudioFrame::new(),
+            failed: false,
+        })
+    }
+
+    /// Run `advance` into the recorder and pass the frame on to `buffers`
+    pub fn record(&mut self, advance: impl FnOnce(&mut NESBuffers), buffers: NESBuffers) {
+        self.audio.clear();
+        advance(&mut NESBuffers {
+            video: Some(&mut self.video),
+            audio: Some(&mut self.audio),
+        });
+        if let Some(video) = buffers.video {
+            video.copy_from_slice(&self.video);
+        }
+        if let Some(audio) = buffers.audio {
+            audio.extend_from_slice(&self.audio);
+        }
+
+        if !self.failed {
+            if let Err(e) = self.write_frame() {
+                log::error!("Could not record frame: {e:?}");
+                self.failed = true;
+            }
+        }
+    }
+
+    fn write_frame(&mut self) -> Result<()> {
+        self.video_in.write_all(&self.video)?;
+
+        // Stretch the audio of the frame to the length of a frame in the recording, to keep it in sync with the video
+        let wanted = self.samples_per_frame + self.sample_remainder;
+        let samples = wanted.floor() as usize;
+        self.sample_remainder = wanted - samples as f32;
+        for i in 0..samples {
+            let sample = if self.audio.is_empty() {
+                0.0
+            } else {
+                self.audio[i * self.audio.len() / samples]
+            };
+            self.audio_out.write_all(&sample.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Finalize the recording, returns the path of the video file
+    pub fn finish(self) -> Result<PathBuf> {
+        let Self {
+            path,
+            video_path,
+            audio_path,
+            mut ffmpeg,
+            video_in,
+            mut audio_out,
+            sample_rate,
+            ..
+        } = self;
+        drop(video_in);
+        audio_out.flush()?;
+        drop(audio_out);
+        let encoded = ffmpeg.wait()?;
+
+        let muxed = encoded.success()
+            && Command::new("ffmpeg")
+                .args(["-y", "-loglevel", "error", "-i"])
+                .arg(&video_path)
+                .args([
+                    "-f",
+                    "f32le",
+                    "-ar",
+                    &(sample_rate.round() as u32).to_string(),
+                ])
+                .args(["-ac", "1", "-i"])
+                .arg(&audio_path)
+                .args(["-c:v", "copy", "-c:a", "aac", "-shortest"])
+                .arg(&path)
+                .stdin(Stdio::null())
+                .status()?
+                .success();
+        let _ = fs::remove_file(&video_path);
+        let _ = fs::remove_file(&audio_path);
+        if !muxed {
+            anyhow::bail!("ffmpeg could not encode {path:?}");
+        }
+        log::info!("Saved recording to {path:?}");
+        Ok(path)
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::VecDeque;
+
+use super::{EmulationSettings, NesStateHandler};
+
+/// Snapshots of the last few seconds of single-player play, newest last
+#[derive(Default)]
+pub struct RewindBuffer {
+    snapshots: VecDeque<Vec<u8>>,
+    frames_since_snapshot: u32,
+}
+
+impl RewindBuffer {
+    /// Take a snapshot if it's time for one, the oldest one is dropped when the buffer is full
+    pub fn record(&mut self, nes_state: &impl NesStateHandler, settings: &EmulationSettings) {
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot < settings.rewind_interval() {
+            return;
+        }
+        self.frames_since_snapshot = 0;
+        let Some(snapshot) = nes_state.save_state().and_then(|state| {
+            state
+                .to_bytes()
+                .inspect_err(|e| log::warn!("Could not take a rewind snapshot: {e:?}"))
+                .ok()
+        }) else {
+            return;
+        };
+        while self.snapshots.len() >= settings.rewind_capacity() {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Step back to the previous snapshot, returns false if there is nothing left to rewind
+    pub fn rewind(&mut self, nes_state: &mut impl NesStateHandler) -> bool {
+        let Some(snapshot) = self.snapshots.pop_back() else {
+            return false;
+        };
+        self.frames_since_snapshot = 0;
+        // Load the snapshot into a copy of the current state
+        let Some(mut state) = nes_state.save_state() else {
+            return false;
+        };
+        state.load_bytes(&snapshot).is_ok() && nes_state.load_state(&state)
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.frames_since_snapshot = 0;
+    }
+}
@@ -4,25 +4,31 @@ use anyhow::Result;
 
 use tetanes_core::{
     apu::filter::FilterChain,
+    cart,
     common::{NesRegion, Regional, Reset, ResetKind},
-    control_deck::{Config, ControlDeck, HeadlessMode, MapperRevisionsConfig},
+    control_deck::{self, Config, ControlDeck, HeadlessMode, MapperRevisionsConfig},
     cpu::Cpu,
     fs,
     input::{FourPlayer, Joypad, Player},
-    mem::RamState,
+    mem::{Access, RamState, Read},
     video::VideoFilter,
 };
 
-use super::{NESBuffers, NesStateHandler, NTSC_PAL, SAMPLE_RATE};
+use super::{cheats::Cheat, NESBuffers, NesStateHandler};
 use crate::{
     bundle::Bundle,
     input::JoypadState,
     settings::{Settings, MAX_PLAYERS},
+    video::palette::Palette,
 };
 
 #[derive(Clone)]
 pub struct TetanesNesState {
     control_deck: ControlDeck,
+    speed: f32,
+    joypads: [JoypadState; MAX_PLAYERS],
+    /// The Game Genie codes given to tetanes
+    genie_codes: Vec<String>,
 }
 
 trait ToTetanesRegion {
@@ -31,26 +37,122 @@ trait ToTetanesRegion {
 
 impl ToTetanesRegion for crate::emulation::NesRegion {
     fn to_tetanes_region(&self) -> NesRegion {
-        match self {
+        match self.resolved() {
             crate::emulation::NesRegion::Pal => NesRegion::Pal,
-            crate::emulation::NesRegion::Ntsc => NesRegion::Ntsc,
             crate::emulation::NesRegion::Dendy => NesRegion::Dendy,
+            _ => NesRegion::Ntsc,
         }
     }
 }
 
+fn from_tetanes_region(region: NesRegion) -> crate::emulation::NesRegion {
+    match region {
+        NesRegion::Pal => crate::emulation::NesRegion::Pal,
+        NesRegion::Dendy => crate::emulation::NesRegion::Dendy,
+        _ => crate::emulation::NesRegion::Ntsc,
+    }
+}
+
 impl TetanesNesState {
     pub fn start_rom(
         rom: &[u8],
         load_sram: bool,
         region: &crate::emulation::NesRegion,
     ) -> Result<Self> {
+        Self::power_on(rom, load_sram, region, RamState::Random)
+    }
+
+    /// A clean power-on state that is identical on every machine: no SRAM and zeroed RAM.
+    /// Netplay peers must start from the exact same state or they will desync right away.
+    #[cfg(feature = "netplay")]
+    pub fn start_rom_for_netplay(rom: &[u8], region: &crate::emulation::NesRegion) -> Result<Self> {
+        let state = Self::power_on(rom, false, region, RamState::AllZeros)?;
+        log::info!(
+            "Netplay power-on state fingerprint: {:x}",
+            state.fingerprint()?
+        );
+        Ok(state)
+    }
+
+    /// Fill the work RAM with pseudo random bytes from `seed`, like a real power-on but reproducible.
+    /// Peers using the same seed end up in the same state.
+    #[cfg(feature = "netplay")]
+    pub fn seed_ram(&mut self, seed: u64) {
+        use rand::{Rng, SeedableRng};
+        use tetanes_core::mem::Write;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let bus = &mut self.control_deck.cpu_mut().bus;
+        for addr in 0x0000..0x0800 {
+            bus.write(addr, rng.gen(), Access::Dummy);
+        }
+    }
+
+    /// The complete machine state as bytes, for save states kept on disk
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self.control_deck.cpu())
+            .map_err(|err| fs::Error::SerializationFailed(err.to_string()))?)
+    }
+
+    /// Restore the machine state from `to_bytes`
+    pub fn load_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        let cpu = bincode::deserialize(bytes)
+            .map_err(|err| fs::Error::DeserializationFailed(err.to_string()))?;
+        self.control_deck.load_cpu(cpu);
+        // Give the Game Genie codes to the loaded machine again
+        self.genie_codes.clear();
+        Ok(())
+    }
+
+    /// A hash of the complete machine state, useful for comparing states between peers or runs.
+    /// MD5 rather than `DefaultHasher` so that it's the same on every machine and build.
+    pub fn fingerprint(&self) -> Result<u64> {
+        let state = bincode::serialize(self.control_deck.cpu())
+            .map_err(|err| fs::Error::SerializationFailed(err.to_string()))?;
+        let digest = md5::compute(state);
+        Ok(u64::from_be_bytes(
+            digest.0[..8]
+                .try_into()
+                .expect("an MD5 digest to be 16 bytes"),
+        ))
+    }
+
+    /// The SRAM of the selected game, falling back to the one older versions kept in the settings
+    fn read_sram() -> Option<Vec<u8>> {
+        let path = &Bundle::current().selected_game().sram_path;
+        match std::fs::read(path) {
+            Ok(sram) => return Some(sram),
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                log::warn!("Could not read SRAM from {path:?}: {e:?}");
+            }
+            Err(_) => {}
+        }
+
+        use base64::engine::general_purpose::STANDARD_NO_PAD as b64;
+        use base64::Engine;
+        let b64_encoded_sram = Settings::current().save_state.clone()?;
+        b64.decode(b64_encoded_sram)
+            .inspect_err(|err| log::warn!("Failed to base64 decode sram: {err:?}"))
+            .ok()
+    }
+
+    fn power_on(
+        rom: &[u8],
+        load_sram: bool,
+        region: &crate::emulation::NesRegion,
+        ram_state: RamState,
+    ) -> Result<Self> {
+        let region_setting = region;
         let region = region.to_tetanes_region();
         let config = Config {
             filter: VideoFilter::Pixellate,
             region,
-            ram_state: RamState::Random,
-            four_player: FourPlayer::Disabled,
+            ram_state,
+            four_player: if Bundle::current().config.four_player {
+                FourPlayer::FourScore
+            } else {
+                FourPlayer::Disabled
+            },
             zapper: false,
             genie_codes: vec![],
             concurrent_dpad: false,
@@ -64,32 +166,53 @@ impl TetanesNesState {
         log::debug!("Starting ROM with configuration {config:?}");
         let mut control_deck = ControlDeck::with_config(config);
         //control_deck.set_cycle_accurate(false); //TODO: Add as a bundle config?
-        control_deck.load_rom(Bundle::current().config.name.clone(), &mut Cursor::new(rom))?;
+        let loaded_rom = control_deck
+            .load_rom(Bundle::current().config.name.clone(), &mut Cursor::new(rom))
+            .map_err(|e| match e {
+                control_deck::Error::Cart(cart::Error::UnimplementedMapper(mapper)) => {
+                    anyhow::anyhow!("Mapper {mapper} is not supported yet")
+                }
+                e => e.into(),
+            })?;
+        crate::emulation::NesRegion::set_detected(from_tetanes_region(loaded_rom.region));
 
         if load_sram {
             if let Some(true) = control_deck.cart_battery_backed() {
-                if let Some(b64_encoded_sram) = &Settings::current().save_state {
-                    use base64::engine::general_purpose::STANDARD_NO_PAD as b64;
-                    use base64::Engine;
-                    match b64.decode(b64_encoded_sram) {
-                        Ok(sram) => {
-                            log::info!("Loading SRAM save state");
-                            control_deck.cpu_mut().bus.load_sram(sram.into());
-                        }
-                        Err(err) => {
-                            log::warn!("Failed to base64 decode sram: {err:?}");
-                        }
-                    }
+                if let Some(sram) = Self::read_sram() {
+                    log::info!("Loading SRAM save state");
+                    control_deck.cpu_mut().bus.load_sram(sram.into());
                 }
             }
         }
 
-        control_deck.set_region(region);
-        let mut s = Self { control_deck };
+        // Resolve the region again now that the ROM has been detected (in case it's `Auto`)
+        Self::set_region(&mut control_deck, region_setting.to_tetanes_region());
+        let mut s = Self {
+            control_deck,
+            speed: 1.0,
+            joypads: Default::default(),
+            genie_codes: Vec::new(),
+        };
         s.set_speed(1.0); // Trigger the correct sample rate
         Ok(s)
     }
 
+    /// Sets the region of the whole console, then applies any PPU/APU overrides from the bundle
+    fn set_region(control_deck: &mut ControlDeck, region: NesRegion) {
+        control_deck.set_region(region);
+
+        let overrides = &Bundle::current().config.region_overrides;
+        let bus = &mut control_deck.cpu_mut().bus;
+        if let Some(ppu_region) = &overrides.ppu {
+            log::info!("Overriding the PPU region with {ppu_region:?}");
+            bus.ppu.set_region(ppu_region.to_tetanes_region());
+        }
+        if let Some(apu_region) = &overrides.apu {
+            log::info!("Overriding the APU region with {apu_region:?}");
+            bus.apu.set_region(apu_region.to_tetanes_region());
+        }
+    }
+
     pub fn clock_frame_into(&mut self, buffers: &mut NESBuffers) -> Result<usize> {
         #[cfg(feature = "debug")]
         puffin::profile_function!();
@@ -101,6 +224,7 @@ impl TetanesNesState {
         if let Some(video) = &mut buffers.video {
             #[cfg(feature = "debug")]
             puffin::profile_scope!("copy buffers");
+            let palette = Palette::active();
             self.control_deck
                 .cpu()
                 .bus
@@ -109,10 +233,9 @@ impl TetanesNesState {
                 .iter()
                 .enumerate()
                 .for_each(|(idx, &palette_index)| {
-                    let palette_index = palette_index as usize * 3;
                     let pixel_index = idx * 4;
                     video[pixel_index..pixel_index + 3]
-                        .clone_from_slice(&NTSC_PAL[palette_index..palette_index + 3]);
+                        .clone_from_slice(palette.rgb(palette_index as usize));
                 });
         }
         if let Some(audio) = &mut buffers.audio {
@@ -164,12 +287,9 @@ impl TetanesNesState {
 impl NesStateHandler for TetanesNesState {
     fn set_speed(&mut self, speed: f32) {
         let speed = speed.max(0.005);
+        self.speed = speed;
         let apu = &mut self.control_deck.cpu_mut().bus.apu;
-        let target_sample_rate = match apu.region {
-            // Downsample a tiny bit extra to match the most common screen refresh rate (60hz)
-            NesRegion::Ntsc => SAMPLE_RATE * (crate::emulation::NesRegion::Ntsc.to_fps() / 60.0),
-            _ => SAMPLE_RATE,
-        };
+        let target_sample_rate = from_tetanes_region(apu.region).target_sample_rate();
 
         let new_sample_rate = target_sample_rate * (1.0 / speed);
         let new_sample_period = Cpu::region_clock_rate(apu.region) / new_sample_rate;
@@ -181,14 +301,27 @@ impl NesStateHandler for TetanesNesState {
         }
     }
 
+    fn speed(&self) -> f32 {
+        self.speed
+    }
+
     fn advance(&mut self, joypad_state: [JoypadState; MAX_PLAYERS], buffers: &mut NESBuffers) {
-        *self.control_deck.joypad_mut(Player::One) = Joypad::from_bytes((*joypad_state[0]).into());
-        *self.control_deck.joypad_mut(Player::Two) = Joypad::from_bytes((*joypad_state[1]).into());
+        for (player, state) in [Player::One, Player::Two, Player::Three, Player::Four]
+            .into_iter()
+            .zip(joypad_state)
+        {
+            *self.control_deck.joypad_mut(player) = Joypad::from_bytes((*state).into());
+        }
+        self.joypads = joypad_state;
 
         self.clock_frame_ahead_into(buffers)
             .expect("NES to clock a frame");
     }
 
+    fn joypads(&self) -> [JoypadState; MAX_PLAYERS] {
+        self.joypads
+    }
+
     fn save_sram(&self) -> Option<&[u8]> {
         if let Some(true) = self.control_deck.cart_battery_backed() {
             Some(self.control_deck.sram())
@@ -197,11 +330,29 @@ impl NesStateHandler for TetanesNesState {
         }
     }
 
-    #[cfg(feature = "netplay")]
+    fn save_state(&self) -> Option<TetanesNesState> {
+        Some(self.clone())
+    }
+
+    fn load_state(&mut self, state: &TetanesNesState) -> bool {
+        *self = state.clone();
+        true
+    }
+
     fn frame(&self) -> u32 {
         self.control_deck.frame_number()
     }
 
+    fn state_hash(&self) -> Option<u64> {
+        self.fingerprint()
+            .inspect_err(|e| log::warn!("Could not hash the state: {e:?}"))
+            .ok()
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        Some(self.control_deck.cpu().bus.peek(addr, Access::Dummy))
+    }
+
     fn reset(&mut self, hard: bool) {
         let kind = if hard {
             ResetKind::Hard
@@ -209,8 +360,87 @@ impl NesStateHandler for TetanesNesState {
             ResetKind::Soft
         };
         //Set the region in case it has been changed since last start/reset
-        self.control_deck
-            .set_region(Settings::current_mut().get_nes_region().to_tetanes_region());
+        let region = Settings::current_mut().get_nes_region().to_tetanes_region();
+        Self::set_region(&mut self.control_deck, region);
         self.control_deck.reset(kind);
+        // Apply the Game Genie codes again after the reset
+        self.genie_codes.clear();
+    }
+
+    fn apply_cheats(&mut self, cheats: &[Cheat]) {
+        use tetanes_core::mem::Write;
+
+        // Game Genie codes patch what is read from the cartridge, tetanes takes care of that
+        let genie_codes: Vec<String> = cheats
+            .iter()
+            .filter(|cheat| cheat.enabled && cheat.is_game_genie())
+            .map(|cheat| cheat.code.clone())
+            .collect();
+        if genie_codes != self.genie_codes {
+            for code in &self.genie_codes {
+                self.control_deck.remove_genie_code(code);
+            }
+            for code in &genie_codes {
+                if let Err(e) = self.control_deck.add_genie_code(code.clone()) {
+                    log::warn!("Could not apply Game Genie code {code}: {e:?}");
+                }
+            }
+            self.genie_codes = genie_codes;
+        }
+
+        // Raw codes are written to the RAM every frame
+        let bus = &mut self.control_deck.cpu_mut().bus;
+        for patch in cheats
+            .iter()
+            .filter(|cheat| cheat.enabled && !cheat.is_game_genie())
+            .filter_map(|cheat| cheat.patch().ok())
+        {
+            bus.write(patch.address, patch.value, Access::Dummy);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn power_on() -> TetanesNesState {
+        TetanesNesState::power_on(
+            include_bytes!("../../config/rom.nes"),
+            false,
+            &crate::emulation::NesRegion::Ntsc,
+            RamState::AllZeros,
+        )
+        .expect("the bundled ROM to start")
+    }
+
+    #[test]
+    fn an_unsupported_mapper_is_reported_by_number() {
+        let mut rom = include_bytes!("../../config/rom.nes").to_vec();
+        // Mapper 255 in the iNES header
+        rom[6] |= 0xF0;
+        rom[7] |= 0xF0;
+        let error = TetanesNesState::power_on(
+            &rom,
+            false,
+            &crate::emulation::NesRegion::Ntsc,
+            RamState::AllZeros,
+        )
+        .err()
+        .expect("mapper 255 to be unsupported");
+        assert_eq!(error.to_string(), "Mapper 255 is not supported yet");
+    }
+
+    #[test]
+    fn fingerprint_is_stable_and_follows_the_state() {
+        let state = power_on();
+        let fingerprint = state.fingerprint().unwrap();
+        assert_eq!(fingerprint, state.fingerprint().unwrap());
+        // Two zeroed power-ons are identical, like the ones of two netplay peers
+        assert_eq!(fingerprint, power_on().fingerprint().unwrap());
+
+        let mut changed = state.clone();
+        changed.control_deck.clock_frame().unwrap();
+        assert_ne!(fingerprint, changed.fingerprint().unwrap());
     }
 }
@@ -1,9 +1,95 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    sync::{OnceLock, RwLock},
+};
 
 use egui::{
-    Color32, Context, CursorIcon, FontId, Id, KeyboardShortcut, RichText, Rounding, Sense,
-    TextStyle, Ui, Vec2, Widget, WidgetInfo, WidgetText, WidgetType,
+    epaint::Shadow, Color32, Context, CursorIcon, FontId, Id, KeyboardShortcut, RichText, Rounding,
+    Sense, Stroke, Style, TextStyle, Theme, Ui, Vec2, Visuals, Widget, WidgetInfo, WidgetText,
+    WidgetType,
 };
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct AccessibilitySettings {
+    #[serde(default = "Default::default")]
+    pub high_contrast: bool,
+    #[serde(default = "Default::default")]
+    pub large_text: bool,
+}
+
+impl AccessibilitySettings {
+    const LARGE_TEXT_SCALE: f32 = 1.4;
+
+    fn _applied() -> &'static RwLock<Option<AccessibilitySettings>> {
+        static MEM: OnceLock<RwLock<Option<AccessibilitySettings>>> = OnceLock::new();
+        MEM.get_or_init(|| RwLock::new(None))
+    }
+
+    /// The accessibility settings that are currently applied to the gui
+    pub fn applied() -> AccessibilitySettings {
+        Self::_applied().read().unwrap().unwrap_or_default()
+    }
+
+    pub fn text_scale(&self) -> f32 {
+        if self.large_text {
+            Self::LARGE_TEXT_SCALE
+        } else {
+            1.0
+        }
+    }
+
+    /// Applies the settings to the egui context (unless they are already applied)
+    pub fn apply(&self, ctx: &Context) {
+        if *Self::_applied().read().unwrap() == Some(*self) {
+            return;
+        }
+        log::debug!("Applying accessibility settings {self:?}");
+        *Self::_applied().write().unwrap() = Some(*self);
+
+        let mut style = Style {
+            visuals: self.visuals(),
+            ..Default::default()
+        };
+        let text_scale = self.text_scale();
+        for font_id in style.text_styles.values_mut() {
+            font_id.size *= text_scale;
+        }
+        ctx.set_style_of(Theme::Dark, style);
+    }
+
+    pub fn visuals(&self) -> Visuals {
+        const BORDER_RADIUS: f32 = 1.3;
+
+        let mut visuals = Visuals {
+            window_rounding: egui::Rounding::same(BORDER_RADIUS),
+            window_shadow: Shadow::NONE,
+            slider_trailing_fill: true,
+            handle_shape: egui::style::HandleShape::Rect { aspect_ratio: 0.6 },
+            ..Default::default()
+        };
+        if self.high_contrast {
+            visuals.override_text_color = Some(Color32::WHITE);
+            visuals.window_fill = Color32::BLACK;
+            visuals.panel_fill = Color32::BLACK;
+            visuals.extreme_bg_color = Color32::BLACK;
+            visuals.window_stroke = Stroke::new(2.0, Color32::WHITE);
+            visuals.widgets.noninteractive.fg_stroke = Stroke::new(1.0, Color32::WHITE);
+            visuals.widgets.inactive.bg_stroke = Stroke::new(1.0, Color32::WHITE);
+            visuals.widgets.hovered.bg_stroke = Stroke::new(2.0, Color32::YELLOW);
+            visuals.widgets.active.bg_stroke = Stroke::new(2.0, Color32::YELLOW);
+            visuals.selection.stroke = Stroke::new(2.0, Color32::YELLOW);
+        }
+        visuals
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.high_contrast, "High contrast menus");
+        ui.checkbox(&mut self.large_text, "Larger text");
+    }
+}
 
 #[derive(Clone)]
 struct MenuButtonGroup {
@@ -19,12 +105,19 @@ impl MenuButtonGroup {
     }
 }
 
-const ESC_SHORTCUT: KeyboardShortcut = egui::KeyboardShortcut {
-    modifiers: egui::Modifiers::NONE,
-    logical_key: egui::Key::Escape,
-};
+/// True (once) when the menu key (Escape unless configured otherwise) or the gamepad menu button is pressed
 pub fn esc_pressed(ctx: &Context) -> bool {
-    ctx.input_mut(|i| i.consume_shortcut(&ESC_SHORTCUT))
+    let shortcut = KeyboardShortcut::new(
+        egui::Modifiers::NONE,
+        Settings::current().hotkeys.menu_egui_key(),
+    );
+    ctx.input_mut(|i| i.consume_shortcut(&shortcut))
+}
+
+/// True while the menu key or the gamepad menu button is held
+pub fn esc_down(ctx: &Context) -> bool {
+    let key = Settings::current().hotkeys.menu_egui_key();
+    ctx.input(|i| i.key_down(key))
 }
 // A widget that keeps track of focus between each other.
 pub struct MenuButton {
@@ -34,6 +127,8 @@ pub struct MenuButton {
 impl MenuButton {
     pub const ACTIVE_COLOR: Color32 = Color32::WHITE;
     pub const INACTIVE_COLOR: Color32 = Color32::from_rgb(96, 96, 96);
+    const HIGH_CONTRAST_INACTIVE_COLOR: Color32 = Color32::from_rgb(200, 200, 200);
+    const FOCUS_OUTLINE_COLOR: Color32 = Color32::YELLOW;
     const HOVER_BG: Color32 = Color32::from_rgba_premultiplied(16, 16, 16, 10);
     const GROUP_KEY: &'static str = "MENU_BTN_GROUP_KEY";
 
@@ -48,7 +143,19 @@ impl MenuButton {
         RichText::new(text)
             .color(color)
             .strong()
-            .font(FontId::monospace(30.0))
+            .font(FontId::monospace(Self::font_size()))
+    }
+
+    pub fn font_size() -> f32 {
+        30.0 * AccessibilitySettings::applied().text_scale()
+    }
+
+    fn inactive_color() -> Color32 {
+        if AccessibilitySettings::applied().high_contrast {
+            Self::HIGH_CONTRAST_INACTIVE_COLOR
+        } else {
+            Self::INACTIVE_COLOR
+        }
     }
 }
 
@@ -102,13 +209,20 @@ impl Widget for MenuButton {
                     Color32::TRANSPARENT
                 },
             );
+            if response.has_focus() && AccessibilitySettings::applied().high_contrast {
+                ui.painter().rect_stroke(
+                    rect.expand(5.0),
+                    Rounding::default(),
+                    Stroke::new(3.0, Self::FOCUS_OUTLINE_COLOR),
+                );
+            }
             ui.painter().galley(
                 text_pos,
                 galley,
                 if response.has_focus() {
                     Self::ACTIVE_COLOR
                 } else {
-                    Self::INACTIVE_COLOR
+                    Self::inactive_color()
                 },
             );
         }
@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+
+use super::{JoypadButton, JoypadState};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct AutoFire {
+    pub enabled: bool,
+    /// Presses per second
+    pub rate: u8,
+}
+
+impl Default for AutoFire {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rate: 10,
+        }
+    }
+}
+
+/// Whether a button pressed `rate` times per second is down on `frame`
+fn pulse(rate: u8, frame: u32) -> bool {
+    // Based on a nominal 60 fps, one press is half a period down and half a period up
+    let period = (60 / rate.max(1) as u32).max(2);
+    frame % period < period / 2
+}
+
+/// Rapid-fire for the face buttons while they are held, and for the turbo buttons.
+/// It's driven by the emulated frame so it behaves the same for every netplay peer.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct AutoFireSettings {
+    pub a: AutoFire,
+    pub b: AutoFire,
+    /// Presses per second of the turbo buttons
+    #[serde(default = "AutoFireSettings::default_turbo_rate")]
+    pub turbo_rate: u8,
+}
+
+impl Default for AutoFireSettings {
+    fn default() -> Self {
+        Self {
+            a: Default::default(),
+            b: Default::default(),
+            turbo_rate: Self::default_turbo_rate(),
+        }
+    }
+}
+
+impl AutoFireSettings {
+    fn default_turbo_rate() -> u8 {
+        15
+    }
+
+    /// Press the buttons whose turbo buttons are held (`turbo`) on and off
+    pub fn apply_turbo(
+        &self,
+        joypad_state: JoypadState,
+        turbo: JoypadState,
+        frame: u32,
+    ) -> JoypadState {
+        if pulse(self.turbo_rate.clamp(2, 30), frame) {
+            JoypadState(*joypad_state | *turbo)
+        } else {
+            joypad_state
+        }
+    }
+
+    pub fn apply(&self, joypad_state: JoypadState, frame: u32) -> JoypadState {
+        let mut state = *joypad_state;
+        for (button, auto_fire) in [(JoypadButton::A, self.a), (JoypadButton::B, self.b)] {
+            if auto_fire.enabled && !pulse(auto_fire.rate, frame) {
+                state &= !(button as u8);
+            }
+        }
+        JoypadState(state)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("auto-fire-grid")
+            .num_columns(2)
+            .spacing([10.0, 4.0])
+            .show(ui, |ui| {
+                for (button, auto_fire) in [
+                    (JoypadButton::A, &mut self.a),
+                    (JoypadButton::B, &mut self.b),
+                ] {
+                    ui.checkbox(&mut auto_fire.enabled, format!("Auto-fire {button}"));
+                    ui.add_enabled(
+                        auto_fire.enabled,
+                        egui::Slider::new(&mut auto_fire.rate, 2..=30).suffix("/s"),
+                    );
+                    ui.end_row();
+                }
+                ui.label("Turbo buttons");
+                ui.add(egui::Slider::new(&mut self.turbo_rate, 2..=30).suffix("/s"));
+                ui.end_row();
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::socd::{SocdResolution, SocdResolver};
+
+    const A: u8 = JoypadButton::A as u8;
+    const B: u8 = JoypadButton::B as u8;
+    const LEFT: u8 = JoypadButton::Left as u8;
+    const RIGHT: u8 = JoypadButton::Right as u8;
+    const UP: u8 = JoypadButton::Up as u8;
+    const DOWN: u8 = JoypadButton::Down as u8;
+
+    /// Feeds (held, turbo) per frame through the same steps as the emulator and returns what the NES sees
+    fn run(
+        auto_fire: AutoFireSettings,
+        resolution: SocdResolution,
+        frames: &[(u8, u8)],
+    ) -> Vec<u8> {
+        let mut resolver = SocdResolver::default();
+        frames
+            .iter()
+            .enumerate()
+            .map(|(frame, (held, turbo))| {
+                let frame = frame as u32;
+                let state = resolver.resolve(resolution, JoypadState(*held));
+                *auto_fire.apply_turbo(auto_fire.apply(state, frame), JoypadState(*turbo), frame)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn auto_fire_pulses_held_buttons() {
+        let auto_fire = AutoFireSettings {
+            a: AutoFire {
+                enabled: true,
+                rate: 10,
+            },
+            ..Default::default()
+        };
+        // 10 presses per second is 3 frames down and 3 frames up, B has no auto-fire
+        let frames = [(A | B, 0); 12];
+        assert_eq!(
+            run(auto_fire, SocdResolution::Off, &frames),
+            [A | B, A | B, A | B, B, B, B, A | B, A | B, A | B, B, B, B]
+        );
+        // Nothing held, nothing pressed
+        assert_eq!(run(auto_fire, SocdResolution::Off, &[(0, 0); 6]), [0; 6]);
+    }
+
+    #[test]
+    fn turbo_buttons_press_on_and_off() {
+        let auto_fire = AutoFireSettings {
+            turbo_rate: 15,
+            ..Default::default()
+        };
+        // 15 presses per second is 2 frames down and 2 frames up, a held A is left alone
+        let frames = [(A, B); 8];
+        assert_eq!(
+            run(auto_fire, SocdResolution::Off, &frames),
+            [A | B, A | B, A, A, A | B, A | B, A, A]
+        );
+    }
+
+    #[test]
+    fn socd_resolutions() {
+        let auto_fire = AutoFireSettings::default();
+        let frames = [
+            (LEFT, 0),
+            (LEFT | RIGHT, 0),
+            (RIGHT, 0),
+            (LEFT | RIGHT, 0),
+            (UP | DOWN, 0),
+            (0, 0),
+        ];
+        assert_eq!(
+            run(auto_fire, SocdResolution::Off, &frames),
+            [LEFT, LEFT | RIGHT, RIGHT, LEFT | RIGHT, UP | DOWN, 0]
+        );
+        assert_eq!(
+            run(auto_fire, SocdResolution::Neutral, &frames),
+            [LEFT, 0, RIGHT, 0, 0, 0]
+        );
+        assert_eq!(
+            run(auto_fire, SocdResolution::LastInputWins, &frames),
+            [LEFT, RIGHT, RIGHT, LEFT, DOWN, 0]
+        );
+        assert_eq!(
+            run(auto_fire, SocdResolution::UpPriority, &frames),
+            [LEFT, 0, RIGHT, 0, UP, 0]
+        );
+    }
+}
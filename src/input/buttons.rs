@@ -27,6 +27,118 @@ pub enum GamepadButton {
     Paddle3,
     Paddle4,
     Touchpad,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// The analog triggers, reported as `GamepadButton::LeftTrigger`/`RightTrigger` when pulled past a threshold
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GamepadTrigger {
+    Left,
+    Right,
+}
+
+impl GamepadTrigger {
+    pub fn to_gamepad_button(self) -> GamepadButton {
+        match self {
+            GamepadTrigger::Left => GamepadButton::LeftTrigger,
+            GamepadTrigger::Right => GamepadButton::RightTrigger,
+        }
+    }
+}
+
+/// The direction of a joystick hat (POV), which some arcade sticks use instead of d-pad buttons
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct HatDirection {
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl HatDirection {
+    /// The d-pad buttons and whether they are pressed in this direction (all released when centered)
+    pub fn dpad_buttons(&self) -> [(GamepadButton, bool); 4] {
+        [
+            (GamepadButton::DPadUp, self.up),
+            (GamepadButton::DPadDown, self.down),
+            (GamepadButton::DPadLeft, self.left),
+            (GamepadButton::DPadRight, self.right),
+        ]
+    }
+}
+
+/// The family of a gamepad, used to show the labels that are printed on its buttons
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ControllerType {
+    Xbox,
+    PlayStation,
+    Nintendo,
+    #[default]
+    Generic,
+}
+
+impl ControllerType {
+    /// Best-effort guess based on the controller name reported by SDL
+    pub fn from_name(name: &str) -> Self {
+        let name = name.to_lowercase();
+        if name.contains("xbox") || name.contains("xinput") {
+            ControllerType::Xbox
+        } else if name.contains("ps3")
+            || name.contains("ps4")
+            || name.contains("ps5")
+            || name.contains("playstation")
+            || name.contains("dualshock")
+            || name.contains("dualsense")
+        {
+            ControllerType::PlayStation
+        } else if name.contains("nintendo") || name.contains("switch") || name.contains("joy-con") {
+            ControllerType::Nintendo
+        } else {
+            ControllerType::Generic
+        }
+    }
+}
+
+impl GamepadButton {
+    /// The name of the button as printed on a controller of the given type
+    pub fn label(&self, controller_type: ControllerType) -> String {
+        use GamepadButton::*;
+        let label = match (controller_type, self) {
+            (ControllerType::PlayStation, A) => "Cross",
+            (ControllerType::PlayStation, B) => "Circle",
+            (ControllerType::PlayStation, X) => "Square",
+            (ControllerType::PlayStation, Y) => "Triangle",
+            (ControllerType::PlayStation, Back) => "Share",
+            (ControllerType::PlayStation, Start) => "Options",
+            (ControllerType::PlayStation, LeftShoulder) => "L1",
+            (ControllerType::PlayStation, RightShoulder) => "R1",
+            (ControllerType::PlayStation, LeftTrigger) => "L2",
+            (ControllerType::PlayStation, RightTrigger) => "R2",
+            (ControllerType::PlayStation, LeftStick) => "L3",
+            (ControllerType::PlayStation, RightStick) => "R3",
+
+            (ControllerType::Xbox, Back) => "View",
+            (ControllerType::Xbox, Start) => "Menu",
+            (ControllerType::Xbox, LeftShoulder) => "LB",
+            (ControllerType::Xbox, RightShoulder) => "RB",
+            (ControllerType::Xbox, LeftTrigger) => "LT",
+            (ControllerType::Xbox, RightTrigger) => "RT",
+            (ControllerType::Xbox, LeftStick) => "LS",
+            (ControllerType::Xbox, RightStick) => "RS",
+
+            // SDL reports Nintendo controllers by their labels, so A, B, X and Y already match
+            (ControllerType::Nintendo, Back) => "−",
+            (ControllerType::Nintendo, Start) => "+",
+            (ControllerType::Nintendo, LeftShoulder) => "L",
+            (ControllerType::Nintendo, RightShoulder) => "R",
+            (ControllerType::Nintendo, LeftTrigger) => "ZL",
+            (ControllerType::Nintendo, RightTrigger) => "ZR",
+            (ControllerType::Nintendo, Misc1) => "Capture",
+            _ => return self.to_string(),
+        };
+        label.to_string()
+    }
 }
 
 impl std::fmt::Display for GamepadButton {
@@ -42,6 +154,8 @@ impl std::fmt::Display for GamepadButton {
             GamepadButton::RightStick => write!(f, "Stick Right"),
             GamepadButton::LeftShoulder => write!(f, "Shoulder Left"),
             GamepadButton::RightShoulder => write!(f, "Shoulder Right"),
+            GamepadButton::LeftTrigger => write!(f, "Trigger Left"),
+            GamepadButton::RightTrigger => write!(f, "Trigger Right"),
             GamepadButton::DPadUp => write!(f, "Up"),
             GamepadButton::DPadDown => write!(f, "Down"),
             GamepadButton::DPadLeft => write!(f, "Left"),
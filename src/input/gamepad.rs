@@ -1,4 +1,8 @@
-use super::{buttons::GamepadButton, InputId, JoypadMapping, JoypadState};
+use super::{
+    buttons::{ControllerType, GamepadButton, GamepadTrigger, HatDirection},
+    settings::TriggerThresholds,
+    InputId, JoypadMapping, JoypadState,
+};
 use std::collections::HashSet;
 
 pub type JoypadGamepadMapping = JoypadMapping<GamepadButton>;
@@ -7,12 +11,36 @@ pub trait GamepadState {
     fn is_connected(&self) -> bool;
     fn get_pressed_buttons(&self) -> &HashSet<GamepadButton>;
     fn toogle_button(&mut self, button: &GamepadButton, on: bool);
+    /// Update the trigger position, `thresholds` decide how far from its resting position it needs to be pulled to count as pressed
+    fn update_trigger(
+        &mut self,
+        trigger: GamepadTrigger,
+        value: i16,
+        thresholds: &TriggerThresholds,
+    );
+    /// Update the direction of the hat, pressing the d-pad buttons it points at
+    fn update_hat(&mut self, direction: HatDirection);
+    fn controller_type(&self) -> ControllerType;
 }
 
 pub trait Gamepads {
     fn advance(&mut self, gamepad_event: &GamepadEvent);
-    fn get_joypad(&mut self, id: &InputId, mapping: &JoypadGamepadMapping) -> JoypadState;
+    /// The joypad state of the gamepad, `ignored` buttons are left out
+    fn get_joypad(
+        &mut self,
+        id: &InputId,
+        mapping: &JoypadGamepadMapping,
+        ignored: &[GamepadButton],
+    ) -> JoypadState;
+    /// The buttons whose turbo buttons are held
+    fn get_turbo(
+        &self,
+        id: &InputId,
+        mapping: &JoypadGamepadMapping,
+        ignored: &[GamepadButton],
+    ) -> JoypadState;
     fn get_gamepad_by_input_id(&self, id: &InputId) -> Option<&dyn GamepadState>;
+    fn get_gamepads(&self) -> Vec<&dyn GamepadState>;
 }
 
 #[derive(Clone, Debug)]
@@ -28,8 +56,122 @@ pub enum GamepadEvent {
         which: InputId,
         button: GamepadButton,
     },
+    TriggerMotion {
+        which: InputId,
+        trigger: GamepadTrigger,
+        value: i16,
+    },
+    HatMotion {
+        which: InputId,
+        direction: HatDirection,
+    },
+    /// A joystick was added, only joysticks without a controller mapping are set up from this
+    JoystickAdded {
+        which: InputId,
+    },
+    /// A raw joystick button, only used for joysticks without a controller mapping
+    JoystickButton {
+        which: InputId,
+        button: GamepadButton,
+        pressed: bool,
+    },
 }
 
 pub trait ToGamepadEvent {
     fn to_gamepad_event(&self) -> Option<GamepadEvent>;
 }
+
+/// The pressed buttons of a gamepad. Some pads report the d-pad both as buttons and as a hat,
+/// a d-pad button stays pressed while either of them holds it so it doesn't toggle twice.
+#[derive(Debug, Default)]
+pub struct PressedButtons {
+    buttons: HashSet<GamepadButton>,
+    hat: HatDirection,
+    pressed: HashSet<GamepadButton>,
+}
+
+impl PressedButtons {
+    pub fn get(&self) -> &HashSet<GamepadButton> {
+        &self.pressed
+    }
+
+    pub fn contains(&self, button: &GamepadButton) -> bool {
+        self.pressed.contains(button)
+    }
+
+    pub fn toggle_button(&mut self, button: &GamepadButton, pressed: bool) {
+        if pressed {
+            self.buttons.insert(*button);
+        } else {
+            self.buttons.remove(button);
+        }
+        self.update();
+    }
+
+    pub fn set_hat(&mut self, direction: HatDirection) {
+        self.hat = direction;
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let hat = self
+            .hat
+            .dpad_buttons()
+            .into_iter()
+            .filter_map(|(button, pressed)| pressed.then_some(button));
+        self.pressed = self.buttons.iter().copied().chain(hat).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP: HatDirection = HatDirection {
+        up: true,
+        down: false,
+        left: false,
+        right: false,
+    };
+
+    #[test]
+    fn hat_presses_the_dpad() {
+        let mut pressed = PressedButtons::default();
+        pressed.set_hat(HatDirection { right: true, ..UP });
+        assert_eq!(
+            pressed.get(),
+            &HashSet::from([GamepadButton::DPadUp, GamepadButton::DPadRight])
+        );
+
+        pressed.set_hat(HatDirection::default());
+        assert!(pressed.get().is_empty());
+    }
+
+    #[test]
+    fn hat_and_dpad_reporting_the_same_press_toggle_once() {
+        let mut pressed = PressedButtons::default();
+        let mut up_changes = 0;
+        let mut was_up = false;
+        let mut step = |pressed: &PressedButtons| {
+            let up = pressed.contains(&GamepadButton::DPadUp);
+            if up != was_up {
+                up_changes += 1;
+                was_up = up;
+            }
+        };
+
+        // The pad reports the press as a d-pad button and a hat, and then the release the other way around
+        pressed.toggle_button(&GamepadButton::DPadUp, true);
+        step(&pressed);
+        pressed.set_hat(UP);
+        step(&pressed);
+        pressed.set_hat(HatDirection::default());
+        step(&pressed);
+        assert!(pressed.contains(&GamepadButton::DPadUp));
+        pressed.toggle_button(&GamepadButton::DPadUp, false);
+        step(&pressed);
+
+        assert!(!pressed.contains(&GamepadButton::DPadUp));
+        assert_eq!(up_changes, 2);
+    }
+}
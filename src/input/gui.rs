@@ -1,12 +1,20 @@
 use crate::{
+    bundle::Bundle,
     input::{JoypadButton, JoypadState},
-    main_view::gui::{GuiComponent, GuiEvent},
-    settings::Settings,
+    main_view::gui::{GuiComponent, GuiEvent, MainGui, MainMenuState},
+    settings::{Settings, MAX_PLAYERS},
 };
 use egui::{Color32, Grid, RichText, Ui};
 use serde::Deserialize;
 
-use super::{settings::InputSettings, InputConfiguration, Inputs, MapRequest};
+use super::{
+    buttons::{ControllerType, GamepadButton},
+    keyboard::{shared_keyboard_warning, JoypadKeyboardMapping},
+    macros::{InputMacro, MacroMode, Macros},
+    settings::{InputSettings, TriggerThresholds, NO_INPUT_ID},
+    socd::SocdResolution,
+    InputConfiguration, InputConfigurationKind, InputId, Inputs, MapRequest, MappedButton,
+};
 
 #[derive(Deserialize, Debug)]
 pub struct InputButtonsVoca {
@@ -42,23 +50,153 @@ impl Default for InputButtonsVoca {
 pub struct InputsGui {
     pub inputs: Inputs,
     mapping_request: Option<MapRequest>,
+    /// The macro (device and index) waiting for a key or button to trigger it
+    macro_trigger_request: Option<(InputId, usize)>,
+    /// The macro (device and index) that is being recorded
+    recording_macro: Option<(InputId, usize)>,
 }
 
 impl InputsGui {
     pub fn new(inputs: Inputs) -> Self {
         Self {
             mapping_request: None,
+            macro_trigger_request: None,
+            recording_macro: None,
             inputs,
         }
     }
 
+    /// Recorded button sequences that play with a single key or button
+    fn macros_ui(
+        ui: &mut Ui,
+        input_configuration: &mut InputConfiguration,
+        player: usize,
+        macro_trigger_request: &mut Option<(InputId, usize)>,
+        recording_macro: &mut Option<(InputId, usize)>,
+    ) {
+        egui::CollapsingHeader::new(format!("Player {} macros", player + 1))
+            .id_salt(format!("macros-{}", player))
+            .show(ui, |ui| {
+                let input_id = input_configuration.id.clone();
+                let mut removed = None;
+                for (index, input_macro) in input_configuration.macros.iter_mut().enumerate() {
+                    let this_macro = Some((input_id.clone(), index));
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut input_macro.name).desired_width(100.0),
+                        );
+
+                        let trigger_text = if *macro_trigger_request == this_macro {
+                            "Press a key or button".to_string()
+                        } else {
+                            input_macro
+                                .trigger
+                                .map(|trigger| trigger.to_string())
+                                .unwrap_or("No trigger".to_string())
+                        };
+                        if ui
+                            .button(trigger_text)
+                            .on_hover_text("The key or button that plays (and stops) the macro")
+                            .clicked()
+                        {
+                            *macro_trigger_request = this_macro.clone();
+                        }
+
+                        egui::ComboBox::from_id_salt(format!("macro-mode-{player}-{index}"))
+                            .selected_text(format!("{:?}", input_macro.mode))
+                            .show_ui(ui, |ui| {
+                                for mode in [MacroMode::Combine, MacroMode::Override] {
+                                    ui.selectable_value(
+                                        &mut input_macro.mode,
+                                        mode,
+                                        format!("{mode:?}"),
+                                    );
+                                }
+                            })
+                            .response
+                            .on_hover_text(
+                                "Combine presses the macro on top of your buttons, override replaces them",
+                            );
+
+                        if ui
+                            .button("Record")
+                            .on_hover_text("Closes the menu and records until it's opened again")
+                            .clicked()
+                        {
+                            Macros::current().start_recording(player);
+                            *recording_macro = this_macro.clone();
+                            MainGui::set_main_menu_state(MainMenuState::Closed);
+                            MainGui::show_toast("Recording macro, open the menu to stop");
+                        }
+                        ui.label(format!("{} frames", input_macro.frames.len()));
+                        if ui.button("Remove").clicked() {
+                            removed = Some(index);
+                        }
+                    });
+                }
+                if let Some(index) = removed {
+                    input_configuration.macros.remove(index);
+                    *macro_trigger_request = None;
+                }
+                if ui.button("Add macro").clicked() {
+                    input_configuration.macros.push(InputMacro {
+                        name: format!("Macro {}", input_configuration.macros.len() + 1),
+                        trigger: None,
+                        mode: MacroMode::default(),
+                        frames: Vec::new(),
+                    });
+                }
+            })
+            .header_response
+            .on_hover_text("Not used during netplay");
+    }
+
+    /// Assign a trigger to the macro waiting for one and store a finished recording
+    fn update_macros(&mut self, input_settings: &mut InputSettings) {
+        if let Some((input_id, index)) = self.macro_trigger_request.clone() {
+            if let Some(input_configuration) = input_settings.configurations.get_mut(&input_id) {
+                if let Some(trigger) = self.inputs.held_trigger(input_configuration) {
+                    if let Some(input_macro) = input_configuration.macros.get_mut(index) {
+                        input_macro.trigger = Some(trigger);
+                    }
+                    self.macro_trigger_request = None;
+                }
+            }
+        }
+
+        if let Some((input_id, index)) = self.recording_macro.clone() {
+            let mut macros = Macros::current();
+            if !macros.is_recording() {
+                if let Some(frames) = macros.take_recording() {
+                    if let Some(input_macro) = input_settings
+                        .configurations
+                        .get_mut(&input_id)
+                        .and_then(|input_configuration| input_configuration.macros.get_mut(index))
+                    {
+                        MainGui::show_toast(format!(
+                            "Recorded {} frames to {}",
+                            frames.len(),
+                            input_macro.name
+                        ));
+                        input_macro.frames = frames;
+                    }
+                }
+                self.recording_macro = None;
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn key_map_ui(
         ui: &mut Ui,
         joypad_state: JoypadState,
+        turbo: JoypadState,
         available_configurations: &[InputConfiguration],
         input_settings: &mut InputSettings,
         player: usize,
         mapping_request: &mut Option<MapRequest>,
+        controller_type: ControllerType,
+        pressed_buttons: &[GamepadButton],
     ) {
         ui.label(format!("Player {}", player + 1));
         let selected_text = input_settings
@@ -78,24 +216,239 @@ impl InputsGui {
                 }
             });
 
+        egui::CollapsingHeader::new("Additional devices")
+            .id_salt(format!("additional-devices-{}", player))
+            .show(ui, |ui| {
+                for input_configuration in available_configurations
+                    .iter()
+                    .filter(|conf| conf.id != input_settings.selected[player])
+                {
+                    let additional = &mut input_settings.additional[player];
+                    let mut enabled = additional.contains(&input_configuration.id);
+                    if ui
+                        .checkbox(&mut enabled, &input_configuration.name)
+                        .changed()
+                    {
+                        if enabled {
+                            additional.push(input_configuration.id.clone());
+                        } else {
+                            additional.retain(|id| *id != input_configuration.id);
+                        }
+                    }
+                }
+            })
+            .header_response
+            .on_hover_text("Devices that also control this player");
+
+        let default_thresholds = input_settings.trigger_thresholds;
         let input_configuration = input_settings.get_selected_configuration_mut(player);
+        if input_configuration.id == NO_INPUT_ID {
+            // There is nothing to map for a player without an input device
+            return;
+        }
         Grid::new(format!("joypadmap_grid_{}", player))
             .num_columns(2)
             .striped(true)
             .show(ui, |ui| {
-                use JoypadButton::*;
-                [Up, Down, Left, Right, Select, Start, B, A]
+                MappedButton::ALL.iter().for_each(|&button| {
+                    Self::button_map_ui(
+                        mapping_request,
+                        ui,
+                        input_configuration,
+                        joypad_state,
+                        turbo,
+                        button,
+                        controller_type,
+                    );
+                });
+            });
+
+        if let InputConfigurationKind::Gamepad(_) = input_configuration.kind {
+            Self::diagnostics_ui(
+                ui,
+                input_configuration,
+                player,
+                controller_type,
+                pressed_buttons,
+                default_thresholds,
+            );
+        }
+    }
+
+    /// Warns about keys bound for several players and keyboard ghosting when players share the keyboard
+    fn shared_keyboard_ui(
+        ui: &mut Ui,
+        input_settings: &mut InputSettings,
+        available_configurations: &[InputConfiguration],
+        players: usize,
+        pressed_keys: usize,
+    ) {
+        for player in 0..players {
+            for other_player in (player + 1)..players {
+                Self::shared_keys_ui(
+                    ui,
+                    input_settings,
+                    available_configurations,
+                    player,
+                    other_player,
+                );
+            }
+        }
+
+        if pressed_keys >= 6 {
+            ui.label(
+                RichText::new(format!(
+                    "{pressed_keys} keys held. Many keyboards can't register this many keys at once, if presses go missing try keys further apart or a gamepad."
+                ))
+                .small(),
+            );
+        }
+    }
+
+    fn shared_keys_ui(
+        ui: &mut Ui,
+        input_settings: &mut InputSettings,
+        available_configurations: &[InputConfiguration],
+        player: usize,
+        other_player: usize,
+    ) {
+        let (selected, other_selected) = (
+            input_settings.get_selected_configuration(player),
+            input_settings.get_selected_configuration(other_player),
+        );
+        if selected.id == other_selected.id
+            && selected.id != NO_INPUT_ID
+            && matches!(selected.kind, InputConfigurationKind::Keyboard(_))
+        {
+            ui.colored_label(
+                Color32::YELLOW,
+                format!(
+                    "⚠ Players {} and {} both use {}, every key controls both",
+                    player + 1,
+                    other_player + 1,
+                    selected.name
+                ),
+            );
+            // Another keyboard configuration no player has selected
+            let unused_keyboard = available_configurations.iter().find(|conf| {
+                conf.id != NO_INPUT_ID
+                    && matches!(conf.kind, InputConfigurationKind::Keyboard(_))
+                    && !input_settings.selected.contains(&conf.id)
+            });
+            if let Some(unused_keyboard) = unused_keyboard {
+                if ui
+                    .button(format!(
+                        "Use {} for player {}",
+                        unused_keyboard.name,
+                        other_player + 1
+                    ))
+                    .clicked()
+                {
+                    input_settings.selected[other_player].clone_from(&unused_keyboard.id);
+                }
+            }
+            return;
+        }
+
+        let warning = shared_keyboard_warning(
+            &input_settings.get_keyboard_mappings(player),
+            &input_settings.get_keyboard_mappings(other_player),
+        );
+        if let Some(warning) = warning {
+            ui.colored_label(
+                Color32::YELLOW,
+                format!(
+                    "⚠ Players {} and {} {warning}",
+                    player + 1,
+                    other_player + 1
+                ),
+            );
+            let split_layout_hint =
+                "Player 1 on WASD, F, G, 1, 2 and player 2 on the arrows, comma, period, 9, 0";
+            let both_on_keyboard = [player, other_player].iter().all(|&player| {
+                matches!(
+                    input_settings.get_selected_configuration(player).kind,
+                    InputConfigurationKind::Keyboard(_)
+                )
+            });
+            if (player, other_player) == (0, 1)
+                && both_on_keyboard
+                && ui
+                    .button("Use split keyboard layout")
+                    .on_hover_text(split_layout_hint)
+                    .clicked()
+            {
+                for player in 0..2 {
+                    input_settings.get_selected_configuration_mut(player).kind =
+                        InputConfigurationKind::Keyboard(JoypadKeyboardMapping::split_layout(
+                            player,
+                        ));
+                }
+            }
+        }
+    }
+
+    /// Live button state of the gamepad, with the option to ignore buttons that are stuck
+    fn diagnostics_ui(
+        ui: &mut Ui,
+        input_configuration: &mut InputConfiguration,
+        player: usize,
+        controller_type: ControllerType,
+        pressed_buttons: &[GamepadButton],
+        default_thresholds: TriggerThresholds,
+    ) {
+        egui::CollapsingHeader::new("Diagnostics")
+            .id_salt(format!("diagnostics-{}", player))
+            .show(ui, |ui| {
+                let mut own_thresholds = input_configuration.trigger_thresholds.is_some();
+                ui.checkbox(&mut own_thresholds, "Own trigger thresholds")
+                    .on_hover_text("For a gamepad with triggers that need different sensitivity");
+                if own_thresholds {
+                    input_configuration
+                        .trigger_thresholds
+                        .get_or_insert(default_thresholds)
+                        .ui(ui);
+                } else {
+                    input_configuration.trigger_thresholds = None;
+                }
+
+                let ignored = &mut input_configuration.ignored;
+                let mut buttons: Vec<GamepadButton> = pressed_buttons
                     .iter()
-                    .for_each(|&button| {
-                        Self::button_map_ui(
-                            mapping_request,
-                            ui,
-                            input_configuration,
-                            joypad_state,
-                            button,
-                        );
+                    .chain(ignored.iter())
+                    .copied()
+                    .collect();
+                buttons.sort_by_key(|button| *button as u8);
+                buttons.dedup();
+                if buttons.is_empty() {
+                    ui.label("No buttons are pressed");
+                }
+                Grid::new(format!("diagnostics_grid_{}", player))
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for button in buttons {
+                            let mut text = RichText::new(button.label(controller_type));
+                            if pressed_buttons.contains(&button) {
+                                text = text.color(Color32::from_rgb(255, 255, 255));
+                            }
+                            ui.label(text);
+                            let mut ignore = ignored.contains(&button);
+                            if ui.checkbox(&mut ignore, "Ignore").changed() {
+                                if ignore {
+                                    ignored.push(button);
+                                } else {
+                                    ignored.retain(|b| *b != button);
+                                }
+                            }
+                            ui.end_row();
+                        }
                     });
-            });
+            })
+            .header_response
+            .on_hover_text(
+                "Buttons the controller reports as pressed right now, ignore any that are stuck",
+            );
     }
 
     fn button_map_ui(
@@ -103,10 +456,17 @@ impl InputsGui {
         ui: &mut Ui,
         input_configuration: &mut InputConfiguration,
         joypad_state: JoypadState,
-        button: JoypadButton,
+        turbo: JoypadState,
+        button: MappedButton,
+        controller_type: ControllerType,
     ) {
+        let pressed = match button {
+            MappedButton::Joypad(button) => joypad_state.is_pressed(button),
+            MappedButton::TurboA => turbo.is_pressed(JoypadButton::A),
+            MappedButton::TurboB => turbo.is_pressed(JoypadButton::B),
+        };
         let mut text = RichText::new(format!("{button}"));
-        if joypad_state.is_pressed(button) {
+        if pressed {
             text = text.color(Color32::from_rgb(255, 255, 255));
         }
         ui.label(text);
@@ -128,7 +488,7 @@ impl InputsGui {
                         mapping.lookup(&button).map(|v| format!("{v}"))
                     }
                     crate::input::InputConfigurationKind::Gamepad(mapping) => {
-                        mapping.lookup(&button).map(|v| format!("{v}"))
+                        mapping.lookup(&button).map(|v| v.label(controller_type))
                     }
                 }
                 .unwrap_or_else(|| "-".to_string());
@@ -145,6 +505,57 @@ impl InputsGui {
     }
 }
 
+impl InputsGui {
+    /// A reference card of what controls what for each player, including any remapping
+    pub fn controls_ui(&self, ui: &mut Ui) {
+        let (menu_key, menu_button) = {
+            let hotkeys = &Settings::current().hotkeys;
+            (hotkeys.menu_key, hotkeys.menu_button)
+        };
+        let input_settings = &Settings::current().input;
+        ui.horizontal(|ui| {
+            for player in 0..Bundle::current().config.players() {
+                let input_configuration = input_settings.get_selected_configuration(player);
+                let controller_type = self.inputs.controller_type(input_configuration);
+                ui.vertical(|ui| {
+                    ui.heading(format!("Player {}", player + 1));
+                    ui.label(&input_configuration.name);
+                    Grid::new(format!("controls_grid_{}", player))
+                        .num_columns(2)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for button in MappedButton::ALL {
+                                let mapped_to = match &input_configuration.kind {
+                                    InputConfigurationKind::Keyboard(mapping) => {
+                                        mapping.get(&button).map(|key| key.to_string())
+                                    }
+                                    InputConfigurationKind::Gamepad(mapping) => {
+                                        mapping.get(&button).map(|gamepad_button| {
+                                            gamepad_button.label(controller_type)
+                                        })
+                                    }
+                                };
+                                // Unmapped turbo buttons are just noise on the card
+                                if mapped_to.is_none() && !matches!(button, MappedButton::Joypad(_))
+                                {
+                                    continue;
+                                }
+                                ui.label(button.to_string());
+                                ui.label(
+                                    RichText::new(mapped_to.unwrap_or_else(|| "-".to_string()))
+                                        .strong(),
+                                );
+                                ui.end_row();
+                            }
+                        });
+                });
+            }
+        });
+        ui.add_space(10.0);
+        ui.label(format!("Menu: {menu_key} (or {menu_button} on a gamepad)"));
+    }
+}
+
 impl GuiComponent for InputsGui {
     fn handle_event(&mut self, gui_event: &GuiEvent) {
         self.inputs.advance(gui_event);
@@ -162,33 +573,83 @@ impl GuiComponent for InputsGui {
 
         available_configurations.sort_by(|a, b| a.id.cmp(&b.id));
 
-        let joypad_0 = instance.get_joypad(0);
-        let joypad_1 = instance.get_joypad(1);
-        ui.horizontal(|ui| {
-            ui.vertical(|ui| {
-                Self::key_map_ui(
-                    ui,
-                    joypad_0,
-                    available_configurations,
-                    input_settings,
-                    0,
-                    &mut self.mapping_request,
-                );
-            });
-            ui.vertical(|ui| {
-                Self::key_map_ui(
-                    ui,
-                    joypad_1,
-                    available_configurations,
-                    input_settings,
-                    1,
-                    &mut self.mapping_request,
-                );
-            });
+        let players = Bundle::current().config.players();
+        let turbo = instance.turbo;
+        let controller_types: [ControllerType; MAX_PLAYERS] = std::array::from_fn(|player| {
+            instance.controller_type(input_settings.get_selected_configuration(player))
+        });
+        let pressed_buttons: [Vec<GamepadButton>; MAX_PLAYERS] = std::array::from_fn(|player| {
+            instance.pressed_gamepad_buttons(input_settings.get_selected_configuration(player))
         });
+        // Two players side by side, players 3 and 4 (with a Four Score) go below
+        for first_player in (0..players).step_by(2) {
+            ui.horizontal(|ui| {
+                for player in first_player..(first_player + 2).min(players) {
+                    ui.vertical(|ui| {
+                        Self::key_map_ui(
+                            ui,
+                            instance.get_joypad(player),
+                            turbo[player],
+                            available_configurations,
+                            input_settings,
+                            player,
+                            &mut self.mapping_request,
+                            controller_types[player],
+                            &pressed_buttons[player],
+                        );
+                    });
+                }
+            });
+        }
 
         self.inputs
             .remap_configuration(&mut self.mapping_request, input_settings);
+        self.update_macros(input_settings);
+
+        ui.add_space(10.0);
+        for player in 0..players {
+            Self::macros_ui(
+                ui,
+                input_settings.get_selected_configuration_mut(player),
+                player,
+                &mut self.macro_trigger_request,
+                &mut self.recording_macro,
+            );
+        }
+
+        Self::shared_keyboard_ui(
+            ui,
+            input_settings,
+            available_configurations,
+            players,
+            self.inputs.pressed_key_count(),
+        );
+
+        ui.add_space(10.0);
+        input_settings.auto_fire.ui(ui);
+
+        egui::ComboBox::from_label("Opposite directions")
+            .selected_text(input_settings.socd_resolution.to_string())
+            .show_ui(ui, |ui| {
+                for socd_resolution in SocdResolution::ALL {
+                    ui.selectable_value(
+                        &mut input_settings.socd_resolution,
+                        socd_resolution,
+                        socd_resolution.to_string(),
+                    );
+                }
+            })
+            .response
+            .on_hover_text("What the game sees when opposite directions are held at the same time");
+
+        input_settings.trigger_thresholds.ui(ui);
+        ui.checkbox(&mut input_settings.hat_as_dpad, "Use joystick hat as D-pad")
+            .on_hover_text("For arcade sticks that report their directions as a hat (POV)");
+        ui.checkbox(
+            &mut input_settings.gamepad_in_background,
+            "Gamepads work when the window isn't focused",
+        )
+        .on_hover_text("Useful when overlay software steals the focus");
     }
 
     fn name(&self) -> Option<&str> {
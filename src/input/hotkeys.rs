@@ -0,0 +1,472 @@
+use serde::{Deserialize, Serialize};
+
+use crate::emulation::SAVE_SLOTS;
+
+use super::{
+    buttons::GamepadButton,
+    keys::{KeyCode, Modifiers},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    SlowMotion,
+    SpeedIndicator,
+    StateHash,
+    Stats,
+    /// Show the frame rates and how full the audio buffer is
+    Performance,
+    SaveState,
+    LoadState,
+    NextSaveSlot,
+    PreviousSaveSlot,
+    /// Save straight to a slot (0 based)
+    SaveSlot(u8),
+    /// Load straight from a slot (0 based)
+    LoadSlot(u8),
+    /// Step back in time while held
+    Rewind,
+    /// Run faster while held
+    FastForward,
+    /// Pause or resume for frame by frame stepping
+    Pause,
+    StepFrame,
+    Screenshot,
+    VolumeDown,
+    VolumeUp,
+    /// Mute or go back to the volume from before muting
+    Mute,
+}
+
+/// How the menu key (or the menu button on a gamepad) needs to be pressed to open the menu
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum MenuOpenMethod {
+    #[default]
+    Tap,
+    Hold,
+    DoubleTap,
+}
+
+impl MenuOpenMethod {
+    pub const ALL: [MenuOpenMethod; 3] = [
+        MenuOpenMethod::Tap,
+        MenuOpenMethod::Hold,
+        MenuOpenMethod::DoubleTap,
+    ];
+}
+
+impl std::fmt::Display for MenuOpenMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MenuOpenMethod::Tap => write!(f, "Tap"),
+            MenuOpenMethod::Hold => write!(f, "Hold"),
+            MenuOpenMethod::DoubleTap => write!(f, "Double tap"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
+    /// The "windows" key on PC and "command" key on Mac
+    Logo,
+}
+
+impl Modifier {
+    fn to_modifiers(self) -> Modifiers {
+        match self {
+            Modifier::Shift => Modifiers::SHIFT,
+            Modifier::Ctrl => Modifiers::CTRL,
+            Modifier::Alt => Modifiers::ALT,
+            Modifier::Logo => Modifiers::LOGO,
+        }
+    }
+}
+
+/// A key together with the modifiers that need to be held
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub key: KeyCode,
+    #[serde(default = "Default::default")]
+    pub modifiers: Vec<Modifier>,
+}
+
+impl KeyBinding {
+    fn new(key: KeyCode, modifiers: &[Modifier]) -> Self {
+        Self {
+            key,
+            modifiers: modifiers.to_vec(),
+        }
+    }
+
+    pub fn matches(&self, modifiers: Modifiers, key_code: KeyCode) -> bool {
+        self.key == key_code
+            && self
+                .modifiers
+                .iter()
+                .all(|modifier| modifiers.contains(modifier.to_modifiers()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct HotkeySettings {
+    #[serde(default = "HotkeySettings::default_slow_motion")]
+    pub slow_motion: Option<KeyCode>,
+    #[serde(default = "HotkeySettings::default_speed_indicator")]
+    pub speed_indicator: Option<KeyCode>,
+    #[serde(default = "HotkeySettings::default_state_hash")]
+    pub state_hash: Option<KeyCode>,
+    #[serde(default = "HotkeySettings::default_stats")]
+    pub stats: Option<KeyCode>,
+    #[serde(default = "HotkeySettings::default_performance")]
+    pub performance: Option<KeyCode>,
+    /// Save to the selected save state slot
+    #[serde(default = "HotkeySettings::default_save_state")]
+    pub save_state: Option<KeyCode>,
+    /// Load the selected save state slot
+    #[serde(default = "HotkeySettings::default_load_state")]
+    pub load_state: Option<KeyCode>,
+    #[serde(default = "HotkeySettings::default_next_save_slot")]
+    pub next_save_slot: Option<KeyCode>,
+    #[serde(default = "HotkeySettings::default_previous_save_slot")]
+    pub previous_save_slot: Option<KeyCode>,
+    /// Quick save to slot 1, 2 and so on, F1 to F4 by default.
+    /// Other hotkeys win over these, so F3 toggles the performance overlay unless that is rebound.
+    #[serde(default = "HotkeySettings::default_save_slot_keys")]
+    pub save_slot_keys: Vec<KeyCode>,
+    /// Quick load from slot 1, 2 and so on, F5 to F8 by default
+    #[serde(default = "HotkeySettings::default_load_slot_keys")]
+    pub load_slot_keys: Vec<KeyCode>,
+    /// Rewind while held
+    #[serde(default = "HotkeySettings::default_rewind")]
+    pub rewind: Option<KeyCode>,
+    /// Fast-forward while held
+    #[serde(default = "HotkeySettings::default_fast_forward")]
+    pub fast_forward: Option<KeyCode>,
+    /// Pause the game to step through it one frame at a time
+    #[serde(default = "HotkeySettings::default_pause")]
+    pub pause: Option<KeyCode>,
+    /// Advance one frame while paused
+    #[serde(default = "HotkeySettings::default_step_frame")]
+    pub step_frame: Option<KeyCode>,
+    /// Save the current frame as a PNG
+    #[serde(default = "HotkeySettings::default_screenshot")]
+    pub screenshot: Option<KeyCode>,
+    #[serde(default = "HotkeySettings::default_volume_down")]
+    pub volume_down: Option<KeyCode>,
+    #[serde(default = "HotkeySettings::default_volume_up")]
+    pub volume_up: Option<KeyCode>,
+    #[serde(default = "HotkeySettings::default_mute")]
+    pub mute: Option<KeyCode>,
+    /// Gamepad buttons that fast-forward while held together
+    #[serde(default = "HotkeySettings::default_fast_forward_combo")]
+    pub fast_forward_combo: Vec<GamepadButton>,
+    #[serde(default = "HotkeySettings::default_fullscreen")]
+    pub fullscreen: Vec<KeyBinding>,
+    /// The key that opens the menu and goes back in it
+    #[serde(default = "HotkeySettings::default_menu_key")]
+    pub menu_key: KeyCode,
+    /// The gamepad button that acts as the menu key, it never reaches the game
+    #[serde(default = "HotkeySettings::default_menu_button")]
+    pub menu_button: GamepadButton,
+    #[serde(default = "Default::default")]
+    pub menu_open: MenuOpenMethod,
+
+    /// Reopen the menu on the page it was closed from instead of the main menu
+    #[serde(default = "Default::default")]
+    pub menu_reopen_last: bool,
+
+    /// Gamepad buttons that open the menu when held together, for controllers without a menu button.
+    /// Off by default, the buttons are kept from the game while they are all held.
+    #[serde(default = "HotkeySettings::default_menu_combo")]
+    pub menu_combo: Vec<GamepadButton>,
+    /// How long the menu combo needs to be held (in ms) before the menu opens
+    #[serde(default = "HotkeySettings::default_menu_combo_hold")]
+    pub menu_combo_hold: u16,
+}
+
+impl Default for HotkeySettings {
+    fn default() -> Self {
+        Self {
+            slow_motion: Self::default_slow_motion(),
+            speed_indicator: Self::default_speed_indicator(),
+            state_hash: Self::default_state_hash(),
+            stats: Self::default_stats(),
+            performance: Self::default_performance(),
+            save_state: Self::default_save_state(),
+            load_state: Self::default_load_state(),
+            next_save_slot: Self::default_next_save_slot(),
+            previous_save_slot: Self::default_previous_save_slot(),
+            save_slot_keys: Self::default_save_slot_keys(),
+            load_slot_keys: Self::default_load_slot_keys(),
+            rewind: Self::default_rewind(),
+            fast_forward: Self::default_fast_forward(),
+            pause: Self::default_pause(),
+            step_frame: Self::default_step_frame(),
+            screenshot: Self::default_screenshot(),
+            volume_down: Self::default_volume_down(),
+            volume_up: Self::default_volume_up(),
+            mute: Self::default_mute(),
+            fast_forward_combo: Self::default_fast_forward_combo(),
+            fullscreen: Self::default_fullscreen(),
+            menu_key: Self::default_menu_key(),
+            menu_button: Self::default_menu_button(),
+            menu_open: MenuOpenMethod::default(),
+            menu_reopen_last: false,
+            menu_combo: Self::default_menu_combo(),
+            menu_combo_hold: Self::default_menu_combo_hold(),
+        }
+    }
+}
+
+impl HotkeySettings {
+    fn default_slow_motion() -> Option<KeyCode> {
+        Some(KeyCode::Backquote)
+    }
+
+    fn default_speed_indicator() -> Option<KeyCode> {
+        Some(KeyCode::F9)
+    }
+
+    fn default_state_hash() -> Option<KeyCode> {
+        Some(KeyCode::F10)
+    }
+
+    fn default_stats() -> Option<KeyCode> {
+        Some(KeyCode::Home)
+    }
+
+    fn default_performance() -> Option<KeyCode> {
+        Some(KeyCode::F3)
+    }
+
+    fn default_save_state() -> Option<KeyCode> {
+        Some(KeyCode::PageUp)
+    }
+
+    fn default_load_state() -> Option<KeyCode> {
+        Some(KeyCode::PageDown)
+    }
+
+    fn default_save_slot_keys() -> Vec<KeyCode> {
+        vec![KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4]
+    }
+
+    fn default_load_slot_keys() -> Vec<KeyCode> {
+        vec![KeyCode::F5, KeyCode::F6, KeyCode::F7, KeyCode::F8]
+    }
+
+    fn default_next_save_slot() -> Option<KeyCode> {
+        Some(KeyCode::BracketRight)
+    }
+
+    fn default_previous_save_slot() -> Option<KeyCode> {
+        Some(KeyCode::BracketLeft)
+    }
+
+    fn default_rewind() -> Option<KeyCode> {
+        Some(KeyCode::Backspace)
+    }
+
+    fn default_fast_forward() -> Option<KeyCode> {
+        Some(KeyCode::Tab)
+    }
+
+    fn default_pause() -> Option<KeyCode> {
+        Some(KeyCode::KeyP)
+    }
+
+    fn default_step_frame() -> Option<KeyCode> {
+        Some(KeyCode::KeyO)
+    }
+
+    fn default_screenshot() -> Option<KeyCode> {
+        Some(KeyCode::F12)
+    }
+
+    fn default_volume_down() -> Option<KeyCode> {
+        Some(KeyCode::Minus)
+    }
+
+    fn default_volume_up() -> Option<KeyCode> {
+        Some(KeyCode::Equal)
+    }
+
+    fn default_mute() -> Option<KeyCode> {
+        Some(KeyCode::KeyM)
+    }
+
+    fn default_fast_forward_combo() -> Vec<GamepadButton> {
+        vec![GamepadButton::Back, GamepadButton::RightShoulder]
+    }
+
+    fn default_fullscreen() -> Vec<KeyBinding> {
+        if cfg!(target_os = "macos") {
+            vec![
+                KeyBinding::new(KeyCode::KeyF, &[Modifier::Logo]),
+                KeyBinding::new(KeyCode::Enter, &[Modifier::Logo]),
+            ]
+        } else {
+            vec![
+                KeyBinding::new(KeyCode::Enter, &[Modifier::Alt]),
+                KeyBinding::new(KeyCode::F11, &[]),
+            ]
+        }
+    }
+
+    pub fn is_fullscreen(&self, modifiers: Modifiers, key_code: KeyCode) -> bool {
+        self.fullscreen
+            .iter()
+            .any(|binding| binding.matches(modifiers, key_code))
+    }
+
+    fn default_menu_key() -> KeyCode {
+        KeyCode::Escape
+    }
+
+    fn default_menu_button() -> GamepadButton {
+        GamepadButton::Guide
+    }
+
+    /// The menu key as egui sees it, Escape if the configured key is unknown to egui
+    pub fn menu_egui_key(&self) -> egui::Key {
+        self.menu_key.to_egui_key().unwrap_or(egui::Key::Escape)
+    }
+
+    /// How to open the menu with the configured key and button, e.g. "Press Escape or Guide for menu"
+    pub fn menu_hint(&self) -> String {
+        let action = match self.menu_open {
+            MenuOpenMethod::Tap => "Press",
+            MenuOpenMethod::Hold => "Hold",
+            MenuOpenMethod::DoubleTap => "Double tap",
+        };
+        format!(
+            "{action} {} or {} for menu",
+            self.menu_key, self.menu_button
+        )
+    }
+
+    fn default_menu_combo() -> Vec<GamepadButton> {
+        // Opt-in, e.g. Start + Back, most gamepads have a menu button
+        vec![]
+    }
+
+    fn default_menu_combo_hold() -> u16 {
+        500
+    }
+
+    /// The hotkey bound to `key_code`, if any. The single key hotkeys win over the slot keys.
+    pub fn hotkey_for(&self, key_code: KeyCode) -> Option<Hotkey> {
+        [
+            (Hotkey::Screenshot, self.screenshot),
+            (Hotkey::SlowMotion, self.slow_motion),
+            (Hotkey::SpeedIndicator, self.speed_indicator),
+            (Hotkey::StateHash, self.state_hash),
+            (Hotkey::Stats, self.stats),
+            (Hotkey::Performance, self.performance),
+            (Hotkey::SaveState, self.save_state),
+            (Hotkey::LoadState, self.load_state),
+            (Hotkey::NextSaveSlot, self.next_save_slot),
+            (Hotkey::PreviousSaveSlot, self.previous_save_slot),
+            (Hotkey::Rewind, self.rewind),
+            (Hotkey::FastForward, self.fast_forward),
+            (Hotkey::Pause, self.pause),
+            (Hotkey::StepFrame, self.step_frame),
+            (Hotkey::VolumeDown, self.volume_down),
+            (Hotkey::VolumeUp, self.volume_up),
+            (Hotkey::Mute, self.mute),
+        ]
+        .into_iter()
+        .find_map(|(hotkey, key)| (key == Some(key_code)).then_some(hotkey))
+        .or_else(|| {
+            let slot = |keys: &[KeyCode]| keys.iter().position(|key| *key == key_code);
+            slot(&self.save_slot_keys)
+                .map(|slot| Hotkey::SaveSlot(slot as u8))
+                .or_else(|| slot(&self.load_slot_keys).map(|slot| Hotkey::LoadSlot(slot as u8)))
+        })
+        .filter(|hotkey| match hotkey {
+            Hotkey::SaveSlot(slot) | Hotkey::LoadSlot(slot) => *slot < SAVE_SLOTS,
+            _ => true,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn menu_hint_follows_the_bindings() {
+        let mut hotkeys = HotkeySettings::default();
+        assert_eq!(hotkeys.menu_hint(), "Press Escape or Guide for menu");
+
+        hotkeys.menu_key = KeyCode::KeyM;
+        hotkeys.menu_button = GamepadButton::Start;
+        hotkeys.menu_open = MenuOpenMethod::Hold;
+        assert_eq!(hotkeys.menu_hint(), "Hold M or Start for menu");
+
+        hotkeys.menu_open = MenuOpenMethod::DoubleTap;
+        assert_eq!(hotkeys.menu_hint(), "Double tap M or Start for menu");
+    }
+
+    #[test]
+    fn default_slot_keys_save_and_load_their_slot() {
+        let hotkeys = HotkeySettings::default();
+        assert_eq!(hotkeys.hotkey_for(KeyCode::F1), Some(Hotkey::SaveSlot(0)));
+        assert_eq!(hotkeys.hotkey_for(KeyCode::F3), Some(Hotkey::Performance));
+        assert_eq!(hotkeys.hotkey_for(KeyCode::F4), Some(Hotkey::SaveSlot(3)));
+        assert_eq!(hotkeys.hotkey_for(KeyCode::F5), Some(Hotkey::LoadSlot(0)));
+        assert_eq!(hotkeys.hotkey_for(KeyCode::F8), Some(Hotkey::LoadSlot(3)));
+        // The selected slot keys are still there
+        assert_eq!(hotkeys.hotkey_for(KeyCode::PageUp), Some(Hotkey::SaveState));
+        assert_eq!(
+            hotkeys.hotkey_for(KeyCode::PageDown),
+            Some(Hotkey::LoadState)
+        );
+    }
+
+    #[test]
+    fn default_hotkeys_leave_the_split_layouts_alone() {
+        use crate::input::{keyboard::JoypadKeyboardMapping, MappedButton};
+
+        let hotkeys = HotkeySettings::default();
+        for player in 0..2 {
+            let mapping = JoypadKeyboardMapping::split_layout(player);
+            for key in MappedButton::ALL
+                .iter()
+                .filter_map(|button| mapping.get(button))
+            {
+                assert_eq!(hotkeys.hotkey_for(*key), None, "{key:?} is a hotkey");
+            }
+        }
+    }
+
+    #[test]
+    fn rebinding_the_performance_overlay_frees_the_slot_key() {
+        let hotkeys = HotkeySettings {
+            performance: Some(KeyCode::Insert),
+            ..Default::default()
+        };
+        assert_eq!(hotkeys.hotkey_for(KeyCode::F3), Some(Hotkey::SaveSlot(2)));
+        assert_eq!(
+            hotkeys.hotkey_for(KeyCode::Insert),
+            Some(Hotkey::Performance)
+        );
+    }
+
+    #[test]
+    fn slot_keys_can_be_rebound() {
+        let hotkeys = HotkeySettings {
+            save_slot_keys: vec![KeyCode::Digit1, KeyCode::Digit2],
+            load_slot_keys: vec![],
+            ..Default::default()
+        };
+        assert_eq!(
+            hotkeys.hotkey_for(KeyCode::Digit2),
+            Some(Hotkey::SaveSlot(1))
+        );
+        assert_eq!(hotkeys.hotkey_for(KeyCode::F1), None);
+        assert_eq!(hotkeys.hotkey_for(KeyCode::F5), None);
+    }
+}
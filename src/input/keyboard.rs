@@ -1,4 +1,4 @@
-use super::{JoypadMapping, JoypadState, KeyCode, KeyEvent};
+use super::{JoypadMapping, JoypadState, KeyCode, KeyEvent, MappedButton};
 use std::collections::HashSet;
 
 pub type JoypadKeyboardMapping = JoypadMapping<KeyCode>;
@@ -13,13 +13,13 @@ impl Keyboards {
             pressed_keys: HashSet::new(),
         }
     }
-    pub fn advance(&mut self, key_event: &KeyEvent) {
+    pub fn advance(&mut self, key_event: &KeyEvent, menu_key: KeyCode) {
         match key_event {
-            // NOTE: Ignore the escape key as it is used for main menu navigation
-            KeyEvent::Pressed(key) if *key != KeyCode::Escape => {
+            // NOTE: Ignore the menu key as it is used for main menu navigation
+            KeyEvent::Pressed(key) if *key != menu_key => {
                 self.pressed_keys.insert(*key);
             }
-            KeyEvent::Released(key) if *key != KeyCode::Escape => {
+            KeyEvent::Released(key) if *key != menu_key => {
                 self.pressed_keys.remove(key);
             }
             _ => (),
@@ -29,4 +29,124 @@ impl Keyboards {
     pub fn get_joypad(&mut self, mapping: &JoypadKeyboardMapping) -> JoypadState {
         mapping.calculate_state(&self.pressed_keys)
     }
+
+    pub fn get_turbo(&self, mapping: &JoypadKeyboardMapping) -> JoypadState {
+        mapping.calculate_turbo(&self.pressed_keys)
+    }
+}
+
+/// Rough areas of a keyboard. Cheap keyboards often can't register many keys at once within an area (ghosting),
+/// so two players sharing an area will drop each other's keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCluster {
+    LeftLetters,
+    RightLetters,
+    Navigation,
+    Numpad,
+}
+
+impl std::fmt::Display for KeyCluster {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            KeyCluster::LeftLetters => "left letter keys",
+            KeyCluster::RightLetters => "right letter keys",
+            KeyCluster::Navigation => "arrow keys",
+            KeyCluster::Numpad => "numpad",
+        })
+    }
+}
+
+impl KeyCluster {
+    pub fn of(key: &KeyCode) -> Option<Self> {
+        use KeyCode::*;
+        match key {
+            Digit1 | Digit2 | Digit3 | Digit4 | Digit5 | KeyQ | KeyW | KeyE | KeyR | KeyT
+            | KeyA | KeyS | KeyD | KeyF | KeyG | KeyZ | KeyX | KeyC | KeyV | KeyB | Tab
+            | CapsLock | ShiftLeft | ControlLeft | AltLeft => Some(KeyCluster::LeftLetters),
+            Digit6 | Digit7 | Digit8 | Digit9 | Digit0 | KeyY | KeyU | KeyI | KeyO | KeyP
+            | KeyH | KeyJ | KeyK | KeyL | KeyN | KeyM | Comma | Period | Slash | Semicolon
+            | Quote | BracketLeft | BracketRight | ShiftRight | Enter => {
+                Some(KeyCluster::RightLetters)
+            }
+            ArrowUp | ArrowDown | ArrowLeft | ArrowRight | Insert | Delete | Home | End
+            | PageUp | PageDown | ControlRight | AltRight => Some(KeyCluster::Navigation),
+            Numpad0 | Numpad1 | Numpad2 | Numpad3 | Numpad4 | Numpad5 | Numpad6 | Numpad7
+            | Numpad8 | Numpad9 | NumpadAdd | NumpadSubtract | NumpadEnter | NumpadDecimal => {
+                Some(KeyCluster::Numpad)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What might go wrong when two players share the keyboard with these mappings (all the keyboard mappings of each player)
+pub fn shared_keyboard_warning(
+    p1: &[&JoypadKeyboardMapping],
+    p2: &[&JoypadKeyboardMapping],
+) -> Option<String> {
+    let keys = |mappings: &[&JoypadKeyboardMapping]| -> Vec<KeyCode> {
+        mappings
+            .iter()
+            .flat_map(|mapping| {
+                MappedButton::ALL
+                    .iter()
+                    .filter_map(|button| mapping.get(button).copied())
+            })
+            .collect()
+    };
+    let (p1_keys, p2_keys) = (keys(p1), keys(p2));
+
+    // Every press of a key bound for both players reaches both of them
+    let mut shared_keys: Vec<String> = p1_keys
+        .iter()
+        .filter(|key| p2_keys.contains(key))
+        .map(|key| key.to_string())
+        .collect();
+    shared_keys.sort();
+    shared_keys.dedup();
+    if !shared_keys.is_empty() {
+        return Some(format!("both use {}", shared_keys.join(", ")));
+    }
+    let p2_clusters: Vec<KeyCluster> = p2_keys.iter().filter_map(KeyCluster::of).collect();
+    p1_keys
+        .iter()
+        .filter_map(KeyCluster::of)
+        .find(|cluster| p2_clusters.contains(cluster))
+        .map(|cluster| {
+            format!("both use the {cluster}, some keyboards will drop keys when both play")
+        })
+}
+
+impl JoypadKeyboardMapping {
+    /// A layout that keeps the players far apart on the keyboard, to avoid ghosting
+    pub fn split_layout(player: usize) -> Self {
+        use KeyCode::*;
+        if player == 0 {
+            Self {
+                up: Some(KeyW),
+                down: Some(KeyS),
+                left: Some(KeyA),
+                right: Some(KeyD),
+                select: Some(Digit1),
+                start: Some(Digit2),
+                b: Some(KeyF),
+                a: Some(KeyG),
+                turbo_b: None,
+                turbo_a: None,
+            }
+        } else {
+            Self {
+                up: Some(ArrowUp),
+                down: Some(ArrowDown),
+                left: Some(ArrowLeft),
+                right: Some(ArrowRight),
+                select: Some(Digit9),
+                start: Some(Digit0),
+                b: Some(Comma),
+                a: Some(Period),
+                turbo_b: None,
+                turbo_a: None,
+            }
+        }
+    }
 }
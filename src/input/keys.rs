@@ -469,6 +469,18 @@ pub enum KeyCode {
     F35,
 }
 
+impl KeyCode {
+    /// The key egui sees when this key is pressed, if egui knows about it
+    pub fn to_egui_key(self) -> Option<egui::Key> {
+        match self {
+            KeyCode::BracketLeft => Some(egui::Key::OpenBracket),
+            KeyCode::BracketRight => Some(egui::Key::CloseBracket),
+            // Most names are the same, except that egui drops the "Key" prefix for letters
+            key => egui::Key::from_name(format!("{key:?}").trim_start_matches("Key")),
+        }
+    }
+}
+
 impl std::fmt::Display for KeyCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use KeyCode::*;
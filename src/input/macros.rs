@@ -0,0 +1,161 @@
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::settings::MAX_PLAYERS;
+
+use super::{buttons::GamepadButton, JoypadState, KeyCode};
+
+/// The longest macro that can be recorded, in frames
+pub const MAX_MACRO_FRAMES: usize = 600;
+
+/// The key or gamepad button that plays a macro
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum MacroTrigger {
+    Key(KeyCode),
+    Button(GamepadButton),
+}
+
+impl std::fmt::Display for MacroTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MacroTrigger::Key(key) => write!(f, "{key}"),
+            MacroTrigger::Button(button) => write!(f, "{button}"),
+        }
+    }
+}
+
+/// How a playing macro combines with what the player is pressing
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum MacroMode {
+    /// The macro's buttons are pressed on top of the held buttons
+    #[default]
+    Combine,
+    /// Only the macro's buttons reach the game while it plays
+    Override,
+}
+
+/// A recorded sequence of joypad states, one per frame
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct InputMacro {
+    pub name: String,
+    #[serde(default = "Default::default")]
+    pub trigger: Option<MacroTrigger>,
+    #[serde(default = "Default::default")]
+    pub mode: MacroMode,
+    #[serde(default = "Default::default")]
+    pub frames: Vec<u8>,
+}
+
+struct Playback {
+    frames: Vec<u8>,
+    mode: MacroMode,
+    position: usize,
+}
+
+struct Recording {
+    player: usize,
+    frames: Vec<u8>,
+}
+
+/// Records and plays back macros. It's stepped by the emulator once per emulated frame, so a macro
+/// gives the game the exact same inputs every time.
+#[derive(Default)]
+pub struct Macros {
+    playing: [Option<Playback>; MAX_PLAYERS],
+    recording: Option<Recording>,
+    finished_recording: Option<Vec<u8>>,
+}
+
+impl Macros {
+    fn _current() -> &'static Mutex<Macros> {
+        static MEM: OnceLock<Mutex<Macros>> = OnceLock::new();
+        MEM.get_or_init(Default::default)
+    }
+
+    pub fn current<'a>() -> MutexGuard<'a, Macros> {
+        Self::_current().lock().unwrap()
+    }
+
+    /// Start playing `input_macro` for `player`, or stop it if it's already playing
+    pub fn toggle(&mut self, player: usize, input_macro: &InputMacro) {
+        let playing = &mut self.playing[player];
+        if playing
+            .as_ref()
+            .is_some_and(|playback| playback.frames == input_macro.frames)
+        {
+            *playing = None;
+        } else if !input_macro.frames.is_empty() {
+            *playing = Some(Playback {
+                frames: input_macro.frames.clone(),
+                mode: input_macro.mode,
+                position: 0,
+            });
+        }
+    }
+
+    pub fn cancel(&mut self) {
+        self.playing = Default::default();
+    }
+
+    pub fn start_recording(&mut self, player: usize) {
+        self.cancel();
+        self.finished_recording = None;
+        self.recording = Some(Recording {
+            player,
+            frames: Vec::new(),
+        });
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            let mut frames = recording.frames;
+            // Drop the silence after the last press
+            while frames.last() == Some(&0) {
+                frames.pop();
+            }
+            self.finished_recording = Some(frames);
+        }
+    }
+
+    /// The frames of the last recording, once it has stopped
+    pub fn take_recording(&mut self) -> Option<Vec<u8>> {
+        self.finished_recording.take()
+    }
+
+    /// Record and apply the macros to the inputs of the next frame
+    pub fn apply(&mut self, joypads: [JoypadState; MAX_PLAYERS]) -> [JoypadState; MAX_PLAYERS] {
+        if let Some(recording) = &mut self.recording {
+            let state = *joypads[recording.player];
+            // Start recording at the first press
+            if state != 0 || !recording.frames.is_empty() {
+                recording.frames.push(state);
+            }
+            if recording.frames.len() >= MAX_MACRO_FRAMES {
+                self.stop_recording();
+            }
+        }
+
+        std::array::from_fn(|player| {
+            let live = *joypads[player];
+            let playing = &mut self.playing[player];
+            let Some(playback) = playing else {
+                return JoypadState(live);
+            };
+            let state = playback.frames[playback.position];
+            let state = match playback.mode {
+                MacroMode::Combine => live | state,
+                MacroMode::Override => state,
+            };
+            playback.position += 1;
+            if playback.position >= playback.frames.len() {
+                *playing = None;
+            }
+            JoypadState(state)
+        })
+    }
+}
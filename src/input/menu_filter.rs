@@ -0,0 +1,129 @@
+use crate::settings::MAX_PLAYERS;
+
+use super::JoypadState;
+
+/// Decides which of the held buttons reach the game around the menu.
+/// Nothing reaches the game while the menu is showing, and buttons used in the menu (e.g. to close it)
+/// don't register in the game until they have been released.
+/// Buttons held all the way through the menu are still held when the game resumes, so they don't
+/// register as a release and a new press.
+#[derive(Debug)]
+pub enum MenuFilter {
+    /// The menu is showing, these buttons have been held since before it opened
+    Showing([JoypadState; MAX_PLAYERS]),
+    /// The menu is closed, these buttons were pressed in the menu and are kept from the game until released
+    Closed([JoypadState; MAX_PLAYERS]),
+}
+
+impl Default for MenuFilter {
+    fn default() -> Self {
+        MenuFilter::Closed(Default::default())
+    }
+}
+
+impl MenuFilter {
+    pub fn filter(
+        &mut self,
+        joypads: [JoypadState; MAX_PLAYERS],
+        menu_visible: bool,
+    ) -> [JoypadState; MAX_PLAYERS] {
+        // Buttons are forgotten as soon as they are released
+        let still_held = |buttons: [JoypadState; MAX_PLAYERS]| -> [JoypadState; MAX_PLAYERS] {
+            std::array::from_fn(|player| JoypadState(*buttons[player] & *joypads[player]))
+        };
+        let without = |buttons: [JoypadState; MAX_PLAYERS]| -> [JoypadState; MAX_PLAYERS] {
+            std::array::from_fn(|player| JoypadState(*joypads[player] & !*buttons[player]))
+        };
+        *self = match (&*self, menu_visible) {
+            // Buttons still kept from the game don't count as held through the menu
+            (MenuFilter::Closed(held_in_menu), true) => {
+                MenuFilter::Showing(without(still_held(*held_in_menu)))
+            }
+            (MenuFilter::Showing(held_through_menu), true) => {
+                MenuFilter::Showing(still_held(*held_through_menu))
+            }
+            (MenuFilter::Showing(held_through_menu), false) => {
+                MenuFilter::Closed(without(still_held(*held_through_menu)))
+            }
+            (MenuFilter::Closed(held_in_menu), false) => {
+                MenuFilter::Closed(still_held(*held_in_menu))
+            }
+        };
+        match self {
+            MenuFilter::Showing(_) => Default::default(),
+            MenuFilter::Closed(held_in_menu) => without(*held_in_menu),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::JoypadButton;
+
+    const A: u8 = JoypadButton::A as u8;
+    const B: u8 = JoypadButton::B as u8;
+    const RIGHT: u8 = JoypadButton::Right as u8;
+    const START: u8 = JoypadButton::Start as u8;
+
+    /// Player 1 holds `pressed`, returns what reaches the game
+    fn step(filter: &mut MenuFilter, pressed: u8, menu_visible: bool) -> u8 {
+        let mut joypads: [JoypadState; MAX_PLAYERS] = Default::default();
+        joypads[0] = JoypadState(pressed);
+        *filter.filter(joypads, menu_visible)[0]
+    }
+
+    #[test]
+    fn button_closing_the_menu_waits_for_release() {
+        let mut filter = MenuFilter::default();
+        assert_eq!(step(&mut filter, 0, true), 0);
+        // A is pressed to pick "close" in the menu and still held when the menu closes
+        assert_eq!(step(&mut filter, A, true), 0);
+        assert_eq!(step(&mut filter, A, false), 0);
+        assert_eq!(step(&mut filter, A | START, false), START);
+        // Once released it works as usual
+        assert_eq!(step(&mut filter, START, false), START);
+        assert_eq!(step(&mut filter, A | START, false), A | START);
+    }
+
+    #[test]
+    fn open_hold_close_release() {
+        let mut filter = MenuFilter::default();
+        // Running right when the menu opens
+        assert_eq!(step(&mut filter, RIGHT, false), RIGHT);
+        assert_eq!(step(&mut filter, RIGHT, true), 0);
+        // B is pressed to navigate in the menu while right is still held
+        assert_eq!(step(&mut filter, RIGHT | B, true), 0);
+        // Right was held through the menu and keeps going, B waits for its release
+        assert_eq!(step(&mut filter, RIGHT | B, false), RIGHT);
+        assert_eq!(step(&mut filter, RIGHT, false), RIGHT);
+        assert_eq!(step(&mut filter, RIGHT | B, false), RIGHT | B);
+        assert_eq!(step(&mut filter, 0, false), 0);
+    }
+
+    #[test]
+    fn released_in_the_menu_is_not_held_through_it() {
+        let mut filter = MenuFilter::default();
+        assert_eq!(step(&mut filter, RIGHT, false), RIGHT);
+        assert_eq!(step(&mut filter, RIGHT, true), 0);
+        // Let go of right and press it again in the menu, now it's a menu press
+        assert_eq!(step(&mut filter, 0, true), 0);
+        assert_eq!(step(&mut filter, RIGHT, true), 0);
+        assert_eq!(step(&mut filter, RIGHT, false), 0);
+        assert_eq!(step(&mut filter, 0, false), 0);
+        assert_eq!(step(&mut filter, RIGHT, false), RIGHT);
+    }
+
+    #[test]
+    fn button_kept_from_the_game_stays_kept_through_the_menu() {
+        let mut filter = MenuFilter::default();
+        assert_eq!(step(&mut filter, 0, true), 0);
+        assert_eq!(step(&mut filter, A, true), 0);
+        assert_eq!(step(&mut filter, A, false), 0);
+        // The menu opens again before A was released, it must not sneak through when it closes
+        assert_eq!(step(&mut filter, A, true), 0);
+        assert_eq!(step(&mut filter, A, false), 0);
+        assert_eq!(step(&mut filter, 0, false), 0);
+        assert_eq!(step(&mut filter, A, false), A);
+    }
+}
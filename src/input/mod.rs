@@ -1,10 +1,13 @@
 use self::{
-    buttons::GamepadButton,
+    buttons::{ControllerType, GamepadButton},
     gamepad::{Gamepads, JoypadGamepadMapping},
     keyboard::{JoypadKeyboardMapping, Keyboards},
     keys::{KeyCode, Modifiers},
+    macros::{InputMacro, MacroTrigger, Macros},
+    menu_filter::MenuFilter,
     sdl2_impl::Sdl2Gamepads,
-    settings::InputSettings,
+    settings::{InputSettings, TriggerThresholds},
+    socd::SocdResolver,
 };
 use crate::{
     bundle::Bundle,
@@ -14,13 +17,18 @@ use crate::{
 use serde::{Deserialize, Serialize};
 use std::{collections::HashSet, fmt::Debug, ops::Deref};
 
+pub mod auto_fire;
 pub mod buttons;
 pub mod gamepad;
 pub mod gui;
+pub mod hotkeys;
 pub mod keyboard;
 pub mod keys;
+pub mod macros;
+pub mod menu_filter;
 pub mod sdl2_impl;
 pub mod settings;
+pub mod socd;
 
 type GamepadImpl = Sdl2Gamepads;
 
@@ -63,6 +71,51 @@ impl std::fmt::Display for JoypadButton {
     }
 }
 
+/// Anything a key or gamepad button can be mapped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MappedButton {
+    Joypad(JoypadButton),
+    /// Presses A on and off while held
+    TurboA,
+    /// Presses B on and off while held
+    TurboB,
+}
+
+impl MappedButton {
+    pub const ALL: [MappedButton; 10] = {
+        use JoypadButton::*;
+        use MappedButton::*;
+        [
+            Joypad(Up),
+            Joypad(Down),
+            Joypad(Left),
+            Joypad(Right),
+            Joypad(Select),
+            Joypad(Start),
+            Joypad(B),
+            Joypad(A),
+            TurboB,
+            TurboA,
+        ]
+    };
+}
+
+impl From<JoypadButton> for MappedButton {
+    fn from(button: JoypadButton) -> Self {
+        MappedButton::Joypad(button)
+    }
+}
+
+impl std::fmt::Display for MappedButton {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MappedButton::Joypad(button) => write!(f, "{button}"),
+            MappedButton::TurboA => write!(f, "Turbo {}", JoypadButton::A),
+            MappedButton::TurboB => write!(f, "Turbo {}", JoypadButton::B),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Eq)]
 pub struct JoypadMapping<KeyType> {
     pub up: Option<KeyType>,
@@ -75,24 +128,70 @@ pub struct JoypadMapping<KeyType> {
 
     pub b: Option<KeyType>,
     pub a: Option<KeyType>,
+
+    #[serde(default = "Default::default")]
+    pub turbo_b: Option<KeyType>,
+    #[serde(default = "Default::default")]
+    pub turbo_a: Option<KeyType>,
+}
+
+impl<KeyType> Default for JoypadMapping<KeyType> {
+    fn default() -> Self {
+        Self {
+            up: None,
+            down: None,
+            left: None,
+            right: None,
+            select: None,
+            start: None,
+            b: None,
+            a: None,
+            turbo_b: None,
+            turbo_a: None,
+        }
+    }
 }
 
 impl<KeyType> JoypadMapping<KeyType>
 where
     KeyType: PartialEq + Debug,
 {
-    pub fn lookup(&mut self, button: &JoypadButton) -> &mut Option<KeyType> {
+    pub fn get(&self, button: &MappedButton) -> Option<&KeyType> {
+        use MappedButton::*;
         match button {
-            JoypadButton::Up => &mut self.up,
-            JoypadButton::Down => &mut self.down,
-            JoypadButton::Left => &mut self.left,
-            JoypadButton::Right => &mut self.right,
+            Joypad(JoypadButton::Up) => &self.up,
+            Joypad(JoypadButton::Down) => &self.down,
+            Joypad(JoypadButton::Left) => &self.left,
+            Joypad(JoypadButton::Right) => &self.right,
+
+            Joypad(JoypadButton::Select) => &self.select,
+            Joypad(JoypadButton::Start) => &self.start,
 
-            JoypadButton::Select => &mut self.select,
-            JoypadButton::Start => &mut self.start,
+            Joypad(JoypadButton::B) => &self.b,
+            Joypad(JoypadButton::A) => &self.a,
 
-            JoypadButton::B => &mut self.b,
-            JoypadButton::A => &mut self.a,
+            TurboB => &self.turbo_b,
+            TurboA => &self.turbo_a,
+        }
+        .as_ref()
+    }
+
+    pub fn lookup(&mut self, button: &MappedButton) -> &mut Option<KeyType> {
+        use MappedButton::*;
+        match button {
+            Joypad(JoypadButton::Up) => &mut self.up,
+            Joypad(JoypadButton::Down) => &mut self.down,
+            Joypad(JoypadButton::Left) => &mut self.left,
+            Joypad(JoypadButton::Right) => &mut self.right,
+
+            Joypad(JoypadButton::Select) => &mut self.select,
+            Joypad(JoypadButton::Start) => &mut self.start,
+
+            Joypad(JoypadButton::B) => &mut self.b,
+            Joypad(JoypadButton::A) => &mut self.a,
+
+            TurboB => &mut self.turbo_b,
+            TurboA => &mut self.turbo_a,
         }
     }
 
@@ -126,9 +225,23 @@ where
             acc
         }))
     }
+
+    /// The buttons whose turbo buttons are held
+    fn calculate_turbo(&self, keys: &HashSet<KeyType>) -> JoypadState {
+        let held =
+            |turbo_key: &Option<KeyType>| keys.iter().any(|key| turbo_key.as_ref() == Some(key));
+        let mut state = 0;
+        if held(&self.turbo_b) {
+            state |= JoypadButton::B as u8;
+        }
+        if held(&self.turbo_a) {
+            state |= JoypadButton::A as u8;
+        }
+        JoypadState(state)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct JoypadState(pub u8);
 
 impl Deref for JoypadState {
@@ -155,6 +268,14 @@ pub struct InputConfiguration {
     pub id: InputId,
     pub name: String,
     pub kind: InputConfigurationKind,
+    /// Gamepad buttons (and triggers) that never reach the game, for controllers with stuck buttons
+    #[serde(default = "Default::default")]
+    pub ignored: Vec<GamepadButton>,
+    /// Trigger thresholds for just this gamepad, instead of the ones in the input settings
+    #[serde(default = "Default::default")]
+    pub trigger_thresholds: Option<TriggerThresholds>,
+    #[serde(default = "Default::default")]
+    pub macros: Vec<InputMacro>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
@@ -165,45 +286,163 @@ pub enum InputConfigurationKind {
 #[derive(Debug)]
 pub struct MapRequest {
     input_id: InputId,
-    button: JoypadButton,
+    button: MappedButton,
 }
 
-pub struct Inputs {
+pub struct Inputs<G = GamepadImpl> {
     keyboards: Keyboards,
-    gamepads: GamepadImpl,
+    gamepads: G,
     pub joypads: [JoypadState; MAX_PLAYERS],
+    /// The buttons whose turbo buttons are held, they are pressed on and off by the emulator
+    pub turbo: [JoypadState; MAX_PLAYERS],
+    socd_resolvers: [SocdResolver; MAX_PLAYERS],
+    menu_filter: MenuFilter,
+    /// Macro triggers that are held, a macro only starts (or stops) when its trigger is pressed
+    held_macro_triggers: HashSet<MacroTrigger>,
+    /// The key that opens the menu, it's kept from the game
+    menu_key: KeyCode,
+    /// The gamepad button that opens the menu, it's kept from the game
+    menu_button: GamepadButton,
+    /// Gamepad buttons that open the menu when held together, they are kept from the game while all are held
+    menu_combo: Vec<GamepadButton>,
 }
 
-impl Inputs {
-    pub fn new(gamepads: GamepadImpl) -> Self {
+impl<G: Gamepads> Inputs<G> {
+    pub fn new(gamepads: G) -> Self {
         let keyboards = Keyboards::new();
 
         Self {
             keyboards,
             gamepads,
-            joypads: [JoypadState(0), JoypadState(0)],
+            joypads: Default::default(),
+            turbo: Default::default(),
+            socd_resolvers: Default::default(),
+            menu_filter: Default::default(),
+            held_macro_triggers: HashSet::new(),
+            menu_key: KeyCode::Escape,
+            menu_button: GamepadButton::Guide,
+            menu_combo: Vec::new(),
+        }
+    }
+
+    /// The joypad states that should reach the game, see `MenuFilter`
+    pub fn game_joypads(&mut self, menu_visible: bool) -> [JoypadState; MAX_PLAYERS] {
+        if menu_visible {
+            // Opening the menu ends a recording and stops any playing macros
+            let mut macros = Macros::current();
+            macros.stop_recording();
+            macros.cancel();
+        }
+        self.menu_filter.filter(self.joypads, menu_visible)
+    }
+
+    /// The turbo buttons that should reach the game, none while the menu is showing
+    pub fn game_turbo(&self, menu_visible: bool) -> [JoypadState; MAX_PLAYERS] {
+        if menu_visible {
+            Default::default()
+        } else {
+            self.turbo
         }
     }
 
     pub fn advance(&mut self, event: &GuiEvent) {
+        {
+            let hotkeys = &Settings::current().hotkeys;
+            self.menu_key = hotkeys.menu_key;
+            self.menu_button = hotkeys.menu_button;
+            self.menu_combo.clone_from(&hotkeys.menu_combo);
+        }
+        self.handle_event(event);
+        let input_settings = &mut Settings::current_mut().input;
+        input_settings.reset_selected_disconnected_inputs(self);
+        input_settings.reset_shared_gamepad(self);
+        self.update_joypads(input_settings);
+        self.trigger_macros(input_settings);
+    }
+
+    fn handle_event(&mut self, event: &GuiEvent) {
         match event {
             GuiEvent::Keyboard(key_event) => {
-                self.keyboards.advance(key_event);
+                self.keyboards.advance(key_event, self.menu_key);
             }
             GuiEvent::Gamepad(gamepad_event) => {
                 self.gamepads.advance(gamepad_event);
             }
         }
-        let input_settings = &mut Settings::current_mut().input;
-        input_settings.reset_selected_disconnected_inputs(self);
+    }
 
-        let pad1 =
-            self.get_joypad_for_input_configuration(input_settings.get_selected_configuration(0));
-        let pad2 =
-            self.get_joypad_for_input_configuration(input_settings.get_selected_configuration(1));
+    /// Work out what the players are pressing from the held keys and buttons
+    fn update_joypads(&mut self, input_settings: &InputSettings) {
+        let socd_resolution = input_settings.socd_resolution;
+        self.joypads = std::array::from_fn(|player| {
+            let mut state = *self.get_joypad_for_input_configuration(
+                input_settings.get_selected_configuration(player),
+            );
+            for input_conf in input_settings.get_additional_configurations(player) {
+                if self.is_connected(input_conf) {
+                    state |= *self.get_joypad_for_input_configuration(input_conf);
+                }
+            }
+            self.socd_resolvers[player].resolve(socd_resolution, JoypadState(state))
+        });
+        self.turbo = std::array::from_fn(|player| {
+            let mut state = *self.get_turbo_for_input_configuration(
+                input_settings.get_selected_configuration(player),
+            );
+            for input_conf in input_settings.get_additional_configurations(player) {
+                if self.is_connected(input_conf) {
+                    state |= *self.get_turbo_for_input_configuration(input_conf);
+                }
+            }
+            JoypadState(state)
+        });
+    }
 
-        self.joypads[0] = pad1;
-        self.joypads[1] = pad2;
+    /// Start or stop the macros whose triggers were just pressed
+    fn trigger_macros(&mut self, input_settings: &InputSettings) {
+        let mut held_triggers = HashSet::new();
+        for player in 0..MAX_PLAYERS {
+            let input_conf = input_settings.get_selected_configuration(player);
+            for input_macro in &input_conf.macros {
+                let Some(trigger) = input_macro.trigger else {
+                    continue;
+                };
+                if self.is_trigger_held(input_conf, &trigger) {
+                    if !self.held_macro_triggers.contains(&trigger) {
+                        Macros::current().toggle(player, input_macro);
+                    }
+                    held_triggers.insert(trigger);
+                }
+            }
+        }
+        self.held_macro_triggers = held_triggers;
+    }
+
+    fn is_trigger_held(&self, input_conf: &InputConfiguration, trigger: &MacroTrigger) -> bool {
+        match trigger {
+            MacroTrigger::Key(key) => self.keyboards.pressed_keys.contains(key),
+            MacroTrigger::Button(button) => self
+                .gamepads
+                .get_gamepad_by_input_id(&input_conf.id)
+                .is_some_and(|gp| gp.get_pressed_buttons().contains(button)),
+        }
+    }
+
+    /// The first key or button held on the device behind the configuration, for choosing a macro trigger
+    pub fn held_trigger(&self, input_conf: &InputConfiguration) -> Option<MacroTrigger> {
+        match &input_conf.kind {
+            InputConfigurationKind::Keyboard(_) => self
+                .keyboards
+                .pressed_keys
+                .iter()
+                .next()
+                .map(|key| MacroTrigger::Key(*key)),
+            InputConfigurationKind::Gamepad(_) => self
+                .pressed_gamepad_buttons(input_conf)
+                .into_iter()
+                .find(|button| !input_conf.ignored.contains(button) && *button != self.menu_button)
+                .map(MacroTrigger::Button),
+        }
     }
 
     pub fn get_joypad(&self, player: usize) -> JoypadState {
@@ -225,11 +464,87 @@ impl Inputs {
         match &input_conf.kind {
             InputConfigurationKind::Keyboard(mapping) => self.keyboards.get_joypad(mapping),
             InputConfigurationKind::Gamepad(mapping) => {
-                self.gamepads.get_joypad(&input_conf.id, mapping)
+                let ignored = self.game_ignored_buttons(input_conf);
+                self.gamepads.get_joypad(&input_conf.id, mapping, &ignored)
             }
         }
     }
 
+    /// The buttons of a gamepad that never reach the game, the ignored ones and the menu button.
+    /// While the whole menu combo is held its buttons are kept from the game too, or opening the menu
+    /// would also press them in the game.
+    fn game_ignored_buttons(&self, input_conf: &InputConfiguration) -> Vec<GamepadButton> {
+        let mut ignored: Vec<GamepadButton> = input_conf
+            .ignored
+            .iter()
+            .copied()
+            .chain([self.menu_button])
+            .collect();
+        if self.gamepad_holding(&input_conf.id, &self.menu_combo) {
+            ignored.extend_from_slice(&self.menu_combo);
+        }
+        ignored
+    }
+
+    /// True if the gamepad with `input_id` is connected and holding all of `buttons`
+    fn gamepad_holding(&self, input_id: &InputId, buttons: &[GamepadButton]) -> bool {
+        !buttons.is_empty()
+            && self
+                .gamepads
+                .get_gamepad_by_input_id(input_id)
+                .is_some_and(|gamepad| {
+                    gamepad.is_connected()
+                        && buttons
+                            .iter()
+                            .all(|button| gamepad.get_pressed_buttons().contains(button))
+                })
+    }
+
+    fn get_turbo_for_input_configuration(&self, input_conf: &InputConfiguration) -> JoypadState {
+        match &input_conf.kind {
+            InputConfigurationKind::Keyboard(mapping) => self.keyboards.get_turbo(mapping),
+            InputConfigurationKind::Gamepad(mapping) => {
+                let ignored = self.game_ignored_buttons(input_conf);
+                self.gamepads.get_turbo(&input_conf.id, mapping, &ignored)
+            }
+        }
+    }
+
+    /// The buttons the gamepad behind the configuration reports as pressed, ignored or not
+    pub fn pressed_gamepad_buttons(&self, input_conf: &InputConfiguration) -> Vec<GamepadButton> {
+        let mut pressed: Vec<GamepadButton> = self
+            .gamepads
+            .get_gamepad_by_input_id(&input_conf.id)
+            .map(|gp| gp.get_pressed_buttons().iter().copied().collect())
+            .unwrap_or_default();
+        pressed.sort_by_key(|button| *button as u8);
+        pressed
+    }
+
+    /// How many keys are held on the keyboard
+    pub fn pressed_key_count(&self) -> usize {
+        self.keyboards.pressed_keys.len()
+    }
+
+    /// True if any connected gamepad is holding all of `buttons`
+    pub fn any_gamepad_holding(&self, buttons: &[GamepadButton]) -> bool {
+        !buttons.is_empty()
+            && self.gamepads.get_gamepads().iter().any(|gamepad| {
+                gamepad.is_connected()
+                    && buttons
+                        .iter()
+                        .all(|button| gamepad.get_pressed_buttons().contains(button))
+            })
+    }
+
+    /// The type of the gamepad behind the configuration, `Generic` for keyboards and unknown gamepads
+    pub fn controller_type(&self, input_conf: &InputConfiguration) -> ControllerType {
+        self.gamepads
+            .get_gamepad_by_input_id(&input_conf.id)
+            .map(|gp| gp.controller_type())
+            .unwrap_or_default()
+    }
+
     pub fn is_connected(&self, input_conf: &InputConfiguration) -> bool {
         match &input_conf.kind {
             InputConfigurationKind::Keyboard(_) => true,
@@ -254,6 +569,7 @@ impl Inputs {
             {
                 let button = &map_request.button;
                 let input_configuration_id = input_configuration.id.clone();
+                let ignored = input_configuration.ignored.clone();
                 match &mut input_configuration.kind {
                     InputConfigurationKind::Keyboard(mapping) => {
                         if let Some(code) = self.keyboards.pressed_keys.iter().next() {
@@ -266,9 +582,14 @@ impl Inputs {
                         if let Some(state) =
                             gamepads.get_gamepad_by_input_id(&input_configuration_id)
                         {
-                            if let Some(new_button) = state.get_pressed_buttons().iter().next() {
-                                //If there's any button pressed, use the first found... unless it's the reserved "Guide" button used for bringing up the main menu
-                                if !matches!(new_button, GamepadButton::Guide) {
+                            // A stuck button that is ignored would otherwise always be picked
+                            if let Some(new_button) = state
+                                .get_pressed_buttons()
+                                .iter()
+                                .find(|button| !ignored.contains(button))
+                            {
+                                //If there's any button pressed, use the first found... unless it's the menu button, it's reserved for bringing up the main menu
+                                if *new_button != self.menu_button {
                                     let _ = mapping.lookup(button).insert(*new_button);
                                     remapped = true;
                                 }
@@ -283,3 +604,189 @@ impl Inputs {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{
+        auto_fire::{AutoFire, AutoFireSettings},
+        gamepad::{GamepadEvent, GamepadState},
+        settings::NO_INPUT_ID,
+        socd::SocdResolution,
+    };
+    use std::collections::BTreeMap;
+
+    const A: u8 = JoypadButton::A as u8;
+    const LEFT: u8 = JoypadButton::Left as u8;
+    const RIGHT: u8 = JoypadButton::Right as u8;
+
+    struct NoGamepads;
+
+    impl Gamepads for NoGamepads {
+        fn advance(&mut self, _: &GamepadEvent) {}
+
+        fn get_joypad(
+            &mut self,
+            _: &InputId,
+            _: &JoypadGamepadMapping,
+            _: &[GamepadButton],
+        ) -> JoypadState {
+            JoypadState(0)
+        }
+
+        fn get_turbo(
+            &self,
+            _: &InputId,
+            _: &JoypadGamepadMapping,
+            _: &[GamepadButton],
+        ) -> JoypadState {
+            JoypadState(0)
+        }
+
+        fn get_gamepad_by_input_id(&self, _: &InputId) -> Option<&dyn GamepadState> {
+            None
+        }
+
+        fn get_gamepads(&self) -> Vec<&dyn GamepadState> {
+            Vec::new()
+        }
+    }
+
+    /// Two players on the split keyboard layout, player 1 with turbo keys, auto-fire and last input wins
+    fn input_settings() -> InputSettings {
+        let keyboard = |id: &str, mapping| {
+            (
+                id.to_string(),
+                InputConfiguration {
+                    id: id.to_string(),
+                    name: id.to_string(),
+                    kind: InputConfigurationKind::Keyboard(mapping),
+                    ignored: Vec::new(),
+                    trigger_thresholds: None,
+                    macros: Vec::new(),
+                },
+            )
+        };
+        let player_1 = JoypadKeyboardMapping {
+            turbo_a: Some(KeyCode::KeyH),
+            turbo_b: Some(KeyCode::KeyJ),
+            ..JoypadKeyboardMapping::split_layout(0)
+        };
+        InputSettings {
+            selected: ["player-1", "player-2", NO_INPUT_ID, NO_INPUT_ID].map(str::to_string),
+            additional: Default::default(),
+            configurations: BTreeMap::from([
+                keyboard(NO_INPUT_ID, Default::default()),
+                keyboard("player-1", player_1),
+                keyboard("player-2", JoypadKeyboardMapping::split_layout(1)),
+            ]),
+            default_gamepad_mapping: Default::default(),
+            auto_fire: AutoFireSettings {
+                a: AutoFire {
+                    enabled: true,
+                    rate: 12,
+                },
+                b: AutoFire {
+                    enabled: true,
+                    rate: 7,
+                },
+                turbo_rate: 20,
+            },
+            socd_resolution: SocdResolution::LastInputWins,
+            trigger_thresholds: Default::default(),
+            hat_as_dpad: true,
+            gamepad_in_background: false,
+        }
+    }
+
+    /// Holds the keys of each frame through a fresh `Inputs` and returns what the NES gets, worked out like the emulator does.
+    /// The key events of a frame can be sent in reverse, like two machines that see them in a different order.
+    fn play(frames: &[Vec<KeyCode>], reverse_events: bool) -> Vec<[u8; MAX_PLAYERS]> {
+        let input_settings = input_settings();
+        let auto_fire = input_settings.auto_fire;
+        let mut inputs = Inputs::new(NoGamepads);
+        let mut held: Vec<KeyCode> = Vec::new();
+        frames
+            .iter()
+            .enumerate()
+            .map(|(frame, keys)| {
+                let mut events: Vec<KeyEvent> = held
+                    .iter()
+                    .filter(|key| !keys.contains(*key))
+                    .map(|key| KeyEvent::Released(*key))
+                    .chain(
+                        keys.iter()
+                            .filter(|key| !held.contains(*key))
+                            .map(|key| KeyEvent::Pressed(*key)),
+                    )
+                    .collect();
+                if reverse_events {
+                    events.reverse();
+                }
+                for event in events {
+                    inputs.handle_event(&GuiEvent::Keyboard(event));
+                }
+                held.clone_from(keys);
+                inputs.update_joypads(&input_settings);
+
+                let frame = frame as u32;
+                std::array::from_fn(|player| {
+                    *auto_fire.apply_turbo(
+                        auto_fire.apply(inputs.joypads[player], frame),
+                        inputs.turbo[player],
+                        frame,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn turbo_and_socd_reach_the_nes() {
+        use KeyCode::*;
+        let frames = [
+            vec![KeyH, ArrowLeft],
+            vec![KeyH, ArrowLeft, ArrowRight],
+            vec![KeyH, ArrowLeft, ArrowRight],
+            vec![KeyH, ArrowRight],
+        ];
+        // 20 turbo presses per second is 1 frame down and 2 frames up, player 2 pressed Right last
+        assert_eq!(
+            play(&frames, false),
+            [
+                [A, LEFT, 0, 0],
+                [0, RIGHT, 0, 0],
+                [0, RIGHT, 0, 0],
+                [A, RIGHT, 0, 0]
+            ]
+        );
+    }
+
+    #[test]
+    fn the_same_keys_give_the_same_joypads_every_frame() {
+        use KeyCode::*;
+        let keys = [
+            KeyW, KeyA, KeyS, KeyD, Digit1, Digit2, KeyF, KeyG, KeyH, KeyJ, ArrowUp, ArrowDown,
+            ArrowLeft, ArrowRight, Digit9, Digit0, Comma, Period,
+        ];
+        // A new set of keys every few frames, so keys are held, released and pressed together
+        let frames: Vec<Vec<KeyCode>> = (0..600_u32)
+            .map(|frame| {
+                let bits = (frame / 3 + 1).wrapping_mul(2_654_435_761) >> 8;
+                keys.iter()
+                    .enumerate()
+                    .filter(|(idx, _)| bits & (1 << idx) != 0)
+                    .map(|(_, key)| *key)
+                    .collect()
+            })
+            .collect();
+
+        let first = play(&frames, false);
+        let second = play(&frames, true);
+        for (frame, (first, second)) in first.iter().zip(&second).enumerate() {
+            assert_eq!(first, second, "the joypads differ on frame {frame}");
+        }
+        // The keys made a difference
+        assert!(first.iter().collect::<HashSet<_>>().len() > 10);
+    }
+}
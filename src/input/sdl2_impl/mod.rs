@@ -1,26 +1,79 @@
 use super::buttons::ToGamepadButton;
-use super::{buttons::GamepadButton, InputId, JoypadState};
+use super::{
+    buttons::{ControllerType, GamepadButton, GamepadTrigger, HatDirection},
+    settings::TriggerThresholds,
+    InputId, JoypadState,
+};
 use super::{InputConfiguration, ToInputId};
+use crate::bundle::Bundle;
 use crate::input::{self, InputConfigurationKind};
 use crate::settings::Settings;
 use std::collections::{HashMap, HashSet};
 
-use sdl2::{controller::GameController, GameControllerSubsystem};
+use sdl2::{
+    controller::{Axis, GameController},
+    joystick::Joystick,
+    GameControllerSubsystem, JoystickSubsystem,
+};
 
-use super::gamepad::{GamepadEvent, GamepadState, Gamepads, JoypadGamepadMapping, ToGamepadEvent};
+use super::gamepad::{
+    GamepadEvent, GamepadState, Gamepads, JoypadGamepadMapping, PressedButtons, ToGamepadEvent,
+};
+
+/// A joystick is opened as a game controller if SDL has a mapping for it, otherwise as a plain joystick
+enum Sdl2Device {
+    Controller(GameController),
+    Joystick(Joystick),
+}
+
+impl Sdl2Device {
+    fn attached(&self) -> bool {
+        match self {
+            Sdl2Device::Controller(controller) => controller.attached(),
+            Sdl2Device::Joystick(joystick) => joystick.attached(),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Sdl2Device::Controller(controller) => controller.name(),
+            Sdl2Device::Joystick(joystick) => joystick.name(),
+        }
+    }
+
+    fn instance_id(&self) -> u32 {
+        match self {
+            Sdl2Device::Controller(controller) => controller.instance_id(),
+            Sdl2Device::Joystick(joystick) => joystick.instance_id(),
+        }
+    }
+}
 
 pub struct Sdl2GamepadState {
-    pub pressed_buttons: HashSet<GamepadButton>,
-    game_controller: GameController,
+    pressed_buttons: PressedButtons,
+    device: Sdl2Device,
+    trigger_rest: [i16; 2],
+    controller_type: ControllerType,
 }
 
 impl Sdl2GamepadState {
-    pub fn new(game_controller: GameController) -> Self {
+    fn new(device: Sdl2Device) -> Self {
+        // Some pads rest their triggers at a nonzero value. Cap it in case the trigger happens to be held when connecting.
+        let rest = |axis| match &device {
+            Sdl2Device::Controller(controller) => controller.axis(axis).clamp(0, i16::MAX / 4),
+            Sdl2Device::Joystick(_) => 0,
+        };
         Self {
-            pressed_buttons: HashSet::new(),
-            game_controller,
+            pressed_buttons: PressedButtons::default(),
+            trigger_rest: [rest(Axis::TriggerLeft), rest(Axis::TriggerRight)],
+            controller_type: ControllerType::from_name(&device.name()),
+            device,
         }
     }
+
+    fn is_joystick(&self) -> bool {
+        matches!(self.device, Sdl2Device::Joystick(_))
+    }
 }
 
 impl ToInputId for u32 {
@@ -31,56 +84,86 @@ impl ToInputId for u32 {
 
 impl GamepadState for Sdl2GamepadState {
     fn is_connected(&self) -> bool {
-        self.game_controller.attached()
+        self.device.attached()
     }
 
     fn get_pressed_buttons(&self) -> &HashSet<GamepadButton> {
-        &self.pressed_buttons
+        self.pressed_buttons.get()
     }
 
     fn toogle_button(&mut self, button: &GamepadButton, pressed: bool) {
-        if pressed {
-            self.pressed_buttons.insert(*button);
-        } else {
-            self.pressed_buttons.remove(button);
-        }
+        self.pressed_buttons.toggle_button(button, pressed);
+    }
+
+    fn update_trigger(
+        &mut self,
+        trigger: GamepadTrigger,
+        value: i16,
+        thresholds: &TriggerThresholds,
+    ) {
+        let rest = self.trigger_rest[trigger as usize] as i32;
+        let travel = (i16::MAX as i32 - rest).max(1);
+        let pulled = (value as i32 - rest) * 100 / travel;
+        let button = trigger.to_gamepad_button();
+        let was_pressed = self.pressed_buttons.contains(&button);
+        self.toogle_button(
+            &button,
+            thresholds.is_pressed(&trigger, pulled, was_pressed),
+        );
+    }
+
+    fn update_hat(&mut self, direction: HatDirection) {
+        self.pressed_buttons.set_hat(direction);
+    }
+
+    fn controller_type(&self) -> ControllerType {
+        self.controller_type
     }
 }
 pub struct Sdl2Gamepads {
     game_controller_subsystem: GameControllerSubsystem,
-    all: HashMap<InputId, Box<dyn GamepadState>>,
+    joystick_subsystem: JoystickSubsystem,
+    all: HashMap<InputId, Sdl2GamepadState>,
 }
 
 impl Gamepads for Sdl2Gamepads {
-    fn get_joypad(&mut self, id: &InputId, mapping: &JoypadGamepadMapping) -> JoypadState {
-        if let Some(state) = self.get_gamepad_by_input_id(id) {
-            mapping.calculate_state(state.get_pressed_buttons())
-        } else {
-            JoypadState(0)
-        }
+    fn get_joypad(
+        &mut self,
+        id: &InputId,
+        mapping: &JoypadGamepadMapping,
+        ignored: &[GamepadButton],
+    ) -> JoypadState {
+        self.game_buttons(id, ignored)
+            .map(|pressed_buttons| mapping.calculate_state(&pressed_buttons))
+            .unwrap_or(JoypadState(0))
+    }
+
+    fn get_turbo(
+        &self,
+        id: &InputId,
+        mapping: &JoypadGamepadMapping,
+        ignored: &[GamepadButton],
+    ) -> JoypadState {
+        self.game_buttons(id, ignored)
+            .map(|pressed_buttons| mapping.calculate_turbo(&pressed_buttons))
+            .unwrap_or(JoypadState(0))
     }
 
     fn get_gamepad_by_input_id(&self, id: &InputId) -> Option<&dyn GamepadState> {
-        self.all.get(id).map(|a| a.as_ref())
+        self.all.get(id).map(|a| a as &dyn GamepadState)
+    }
+
+    fn get_gamepads(&self) -> Vec<&dyn GamepadState> {
+        self.all.values().map(|a| a as &dyn GamepadState).collect()
     }
 
     fn advance(&mut self, gamepad_event: &GamepadEvent) {
         match gamepad_event {
-            GamepadEvent::ControllerAdded { which, .. } => {
-                if let Some(conf) = self.setup_gamepad_config(which.clone()) {
-                    let input_settings = &mut Settings::current_mut().input;
-                    // Automatically select a gamepad if it's connected and keyboard is currently selected.
-                    if let InputConfigurationKind::Keyboard(_) =
-                        input_settings.get_selected_configuration(0).kind
-                    {
-                        input_settings.selected[0] = conf.id;
-                    } else if let InputConfigurationKind::Keyboard(_) =
-                        input_settings.get_selected_configuration(1).kind
-                    {
-                        input_settings.selected[1] = conf.id;
-                    }
-                } else {
-                    log::error!("Could not setup controller {:?}", which);
+            GamepadEvent::ControllerAdded { which, .. } => self.gamepad_added(which),
+            GamepadEvent::JoystickAdded { which } => {
+                // Joysticks with a controller mapping are set up when they are added as controllers
+                if !self.is_game_controller(which) {
+                    self.gamepad_added(which);
                 }
             }
             GamepadEvent::ButtonDown { which, button, .. } => {
@@ -97,6 +180,44 @@ impl Gamepads for Sdl2Gamepads {
                     log::warn!("Button up on unmapped gamepad {:?}", which);
                 }
             }
+            GamepadEvent::TriggerMotion {
+                which,
+                trigger,
+                value,
+            } => {
+                let thresholds = {
+                    let input_settings = &Settings::current().input;
+                    input_settings
+                        .configurations
+                        .get(&Self::to_gamepad_id(which))
+                        .and_then(|conf| conf.trigger_thresholds)
+                        .unwrap_or(input_settings.trigger_thresholds)
+                };
+                if let Some(gamepad_state) = self.get_gamepad(which.clone()) {
+                    gamepad_state.update_trigger(*trigger, *value, &thresholds);
+                }
+            }
+            GamepadEvent::HatMotion { which, direction } => {
+                if !Settings::current().input.hat_as_dpad {
+                    return;
+                }
+                if let Some(gamepad_state) = self.get_gamepad(which.clone()) {
+                    gamepad_state.update_hat(*direction);
+                }
+            }
+            GamepadEvent::JoystickButton {
+                which,
+                button,
+                pressed,
+            } => {
+                // Controllers report their buttons mapped, don't press them twice
+                if let Some(gamepad_state) = self
+                    .get_gamepad(which.clone())
+                    .filter(|gamepad_state| gamepad_state.is_joystick())
+                {
+                    gamepad_state.toogle_button(button, *pressed);
+                }
+            }
         }
     }
 }
@@ -105,50 +226,95 @@ impl Sdl2Gamepads {
         format!("01-gamepad-{}", id)
     }
 
-    pub fn new(game_controller_subsystem: GameControllerSubsystem) -> Self {
+    pub fn new(
+        game_controller_subsystem: GameControllerSubsystem,
+        joystick_subsystem: JoystickSubsystem,
+    ) -> Self {
         Sdl2Gamepads {
             game_controller_subsystem,
+            joystick_subsystem,
             all: HashMap::new(),
         }
     }
 
-    fn get_gamepad(&mut self, id: InputId) -> Option<&mut Box<dyn GamepadState>> {
+    fn gamepad_added(&mut self, which: &InputId) {
+        if let Some(conf) = self.setup_gamepad_config(which.clone()) {
+            Settings::current_mut()
+                .input
+                .select_connected_gamepad(&conf.id, Bundle::current().config.players());
+        } else {
+            log::error!("Could not setup controller {:?}", which);
+        }
+    }
+
+    /// The device index of a joystick that was added
+    fn device_index(&self, input_id: &InputId) -> Option<u32> {
+        (0..self.game_controller_subsystem.num_joysticks().unwrap_or(0))
+            .find(|index| *input_id == index.to_input_id())
+    }
+
+    fn is_game_controller(&self, input_id: &InputId) -> bool {
+        self.device_index(input_id)
+            .is_some_and(|index| self.game_controller_subsystem.is_game_controller(index))
+    }
+
+    /// The pressed buttons of a gamepad that may reach the game
+    fn game_buttons(
+        &self,
+        id: &InputId,
+        ignored: &[GamepadButton],
+    ) -> Option<HashSet<GamepadButton>> {
+        self.get_gamepad_by_input_id(id).map(|state| {
+            state
+                .get_pressed_buttons()
+                .iter()
+                .filter(|button| !ignored.contains(button))
+                .copied()
+                .collect()
+        })
+    }
+
+    fn get_gamepad(&mut self, id: InputId) -> Option<&mut Sdl2GamepadState> {
         self.all.get_mut(&Self::to_gamepad_id(&id))
     }
 
+    fn open(&self, input_id: &InputId) -> Option<Sdl2GamepadState> {
+        let index = self.device_index(input_id)?;
+        let device = if self.game_controller_subsystem.is_game_controller(index) {
+            self.game_controller_subsystem
+                .open(index)
+                .map(Sdl2Device::Controller)
+        } else {
+            self.joystick_subsystem
+                .open(index)
+                .map(Sdl2Device::Joystick)
+        };
+        device
+            .inspect_err(|e| log::error!("Failed to open controller {:?}", e))
+            .ok()
+            .map(Sdl2GamepadState::new)
+    }
+
     fn setup_gamepad_config(&mut self, input_id: InputId) -> Option<InputConfiguration> {
-        if let Some(found_controller) =
-            (0..self.game_controller_subsystem.num_joysticks().unwrap_or(0)).find_map(|id| {
-                if input_id == id.to_input_id()
-                    && self.game_controller_subsystem.is_game_controller(id)
-                {
-                    match self.game_controller_subsystem.open(id) {
-                        Ok(c) => Some(c),
-                        Err(e) => {
-                            log::error!("Failed to open controller {:?}", e);
-                            None
-                        }
-                    }
-                } else {
-                    None
-                }
-            })
-        {
-            let instance_id = found_controller.instance_id().to_input_id();
+        if let Some(found_controller) = self.open(&input_id) {
+            let instance_id = found_controller.device.instance_id().to_input_id();
             let gamepad_id = Self::to_gamepad_id(&instance_id);
-            self.all.insert(
-                gamepad_id.clone(),
-                Box::new(Sdl2GamepadState::new(found_controller)),
-            );
+            let name = format!("🎮 {} ({})", found_controller.device.name(), instance_id);
+            self.all.insert(gamepad_id.clone(), found_controller);
             let input_settings = &mut Settings::current_mut().input;
             let conf = input_settings.get_or_create_config(
                 gamepad_id.clone(),
                 input::InputConfiguration {
-                    name: format!("🎮 Gamepad {}", instance_id),
+                    name: name.clone(),
                     id: gamepad_id,
                     kind: InputConfigurationKind::Gamepad(input_settings.default_gamepad_mapping),
+                    ignored: Vec::new(),
+                    trigger_thresholds: None,
+                    macros: Vec::new(),
                 },
             );
+            // The same id can be a different controller next time, so keep the name up to date
+            conf.name = name;
             Some(conf.clone())
         } else {
             None
@@ -176,11 +342,78 @@ impl ToGamepadEvent for sdl2::event::Event {
                     which: which.to_input_id(),
                     button,
                 }),
+            sdl2::event::Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => match axis {
+                Axis::TriggerLeft => Some(GamepadTrigger::Left),
+                Axis::TriggerRight => Some(GamepadTrigger::Right),
+                _ => None,
+            }
+            .map(|trigger| GamepadEvent::TriggerMotion {
+                which: which.to_input_id(),
+                trigger,
+                value: *value,
+            }),
+            sdl2::event::Event::JoyDeviceAdded { which, .. } => Some(GamepadEvent::JoystickAdded {
+                which: which.to_input_id(),
+            }),
+            sdl2::event::Event::JoyButtonDown {
+                which, button_idx, ..
+            } => joystick_button(*button_idx).map(|button| GamepadEvent::JoystickButton {
+                which: which.to_input_id(),
+                button,
+                pressed: true,
+            }),
+            sdl2::event::Event::JoyButtonUp {
+                which, button_idx, ..
+            } => joystick_button(*button_idx).map(|button| GamepadEvent::JoystickButton {
+                which: which.to_input_id(),
+                button,
+                pressed: false,
+            }),
+            // Only the first hat, arcade sticks with more hats use the others for something else
+            sdl2::event::Event::JoyHatMotion {
+                which,
+                hat_idx: 0,
+                state,
+                ..
+            } => {
+                use sdl2::joystick::HatState::*;
+                Some(GamepadEvent::HatMotion {
+                    which: which.to_input_id(),
+                    direction: HatDirection {
+                        up: matches!(state, Up | LeftUp | RightUp),
+                        down: matches!(state, Down | LeftDown | RightDown),
+                        left: matches!(state, Left | LeftUp | LeftDown),
+                        right: matches!(state, Right | RightUp | RightDown),
+                    },
+                })
+            }
             _ => None,
         }
     }
 }
 
+/// Joysticks without a controller mapping have numbered buttons, use them in the order SDL maps controller buttons.
+/// They can be remapped in the input settings.
+fn joystick_button(button_idx: u8) -> Option<GamepadButton> {
+    [
+        GamepadButton::A,
+        GamepadButton::B,
+        GamepadButton::X,
+        GamepadButton::Y,
+        GamepadButton::Back,
+        GamepadButton::Guide,
+        GamepadButton::Start,
+        GamepadButton::LeftStick,
+        GamepadButton::RightStick,
+        GamepadButton::LeftShoulder,
+        GamepadButton::RightShoulder,
+    ]
+    .get(button_idx as usize)
+    .copied()
+}
+
 impl ToGamepadButton for sdl2::controller::Button {
     fn to_gamepad_button(&self) -> Option<GamepadButton> {
         use sdl2::controller::Button::*;
@@ -209,3 +442,52 @@ impl ToGamepadButton for sdl2::controller::Button {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::{event::Event, joystick::HatState};
+
+    fn hat_event(hat_idx: u8, state: HatState) -> Option<GamepadEvent> {
+        Event::JoyHatMotion {
+            timestamp: 0,
+            which: 3,
+            hat_idx,
+            state,
+        }
+        .to_gamepad_event()
+    }
+
+    #[test]
+    fn hat_motion_becomes_a_direction() {
+        let direction = |state| match hat_event(0, state) {
+            Some(GamepadEvent::HatMotion { which, direction }) => {
+                assert_eq!(which, "3");
+                direction
+            }
+            other => panic!("expected a hat motion, got {other:?}"),
+        };
+        assert_eq!(direction(HatState::Centered), HatDirection::default());
+        assert_eq!(
+            direction(HatState::RightUp),
+            HatDirection {
+                up: true,
+                right: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            direction(HatState::LeftDown),
+            HatDirection {
+                down: true,
+                left: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn only_the_first_hat_is_used() {
+        assert!(hat_event(1, HatState::Up).is_none());
+    }
+}
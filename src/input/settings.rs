@@ -1,21 +1,118 @@
 use super::MAX_PLAYERS;
-use crate::input::{gamepad::JoypadGamepadMapping, InputConfiguration, InputId, Inputs};
-use serde::{Deserialize, Serialize};
+use crate::input::{
+    auto_fire::AutoFireSettings,
+    buttons::GamepadTrigger,
+    gamepad::{Gamepads, JoypadGamepadMapping},
+    keyboard::JoypadKeyboardMapping,
+    socd::SocdResolution,
+    InputConfiguration, InputConfigurationKind, InputId, Inputs, KeyCode, MappedButton,
+};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{collections::BTreeMap, hash::Hash};
 
+/// The configuration of a player without an input device, nothing is mapped in it
+pub const NO_INPUT_ID: &str = "00-none";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InputSettings {
+    #[serde(deserialize_with = "deserialize_selected")]
     pub selected: [InputId; MAX_PLAYERS],
+    /// Extra inputs per player, their states are combined with the selected one
+    #[serde(
+        default = "Default::default",
+        deserialize_with = "deserialize_additional"
+    )]
+    pub additional: [Vec<InputId>; MAX_PLAYERS],
+    #[serde(deserialize_with = "deserialize_configurations")]
     pub configurations: BTreeMap<InputId, InputConfiguration>,
     pub default_gamepad_mapping: JoypadGamepadMapping,
+    #[serde(default = "Default::default")]
+    pub auto_fire: AutoFireSettings,
+    #[serde(default = "Default::default")]
+    pub socd_resolution: SocdResolution,
+    #[serde(default = "Default::default")]
+    pub trigger_thresholds: TriggerThresholds,
+    /// Use joystick hats (POV) as the d-pad, for arcade sticks that report directions that way
+    #[serde(default = "InputSettings::default_hat_as_dpad")]
+    pub hat_as_dpad: bool,
+    /// Let gamepads control the game when the window isn't focused (e.g. when an overlay took the focus)
+    #[serde(default = "Default::default")]
+    pub gamepad_in_background: bool,
+}
+
+/// How far (in %) the analog triggers need to be pulled to count as a button press
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct TriggerThresholds {
+    pub left: u8,
+    pub right: u8,
+    /// How far (in %) below the threshold a pressed trigger needs to go back to be released,
+    /// so a trigger resting near the threshold doesn't chatter on and off
+    #[serde(default = "TriggerThresholds::default_hysteresis")]
+    pub hysteresis: u8,
+}
+
+impl Default for TriggerThresholds {
+    fn default() -> Self {
+        Self {
+            left: 50,
+            right: 50,
+            hysteresis: Self::default_hysteresis(),
+        }
+    }
+}
+
+impl TriggerThresholds {
+    fn default_hysteresis() -> u8 {
+        10
+    }
+
+    /// Whether a trigger pulled `pulled` % (of its travel from rest) counts as pressed, given if it was pressed before
+    pub fn is_pressed(&self, trigger: &GamepadTrigger, pulled: i32, was_pressed: bool) -> bool {
+        let threshold = self.get(trigger) as i32;
+        if was_pressed {
+            pulled > threshold - self.hysteresis.min(self.get(trigger)) as i32
+        } else {
+            pulled > threshold
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::Slider::new(&mut self.left, 10..=90)
+                .suffix("%")
+                .text("Left trigger threshold"),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.right, 10..=90)
+                .suffix("%")
+                .text("Right trigger threshold"),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.hysteresis, 0..=30)
+                .suffix("%")
+                .text("Trigger release margin"),
+        )
+        .on_hover_text("How far back a pressed trigger needs to go to be released, stops worn triggers from flickering");
+    }
+
+    pub fn get(&self, trigger: &GamepadTrigger) -> u8 {
+        match trigger {
+            GamepadTrigger::Left => self.left,
+            GamepadTrigger::Right => self.right,
+        }
+    }
 }
 
 impl InputSettings {
+    fn default_hat_as_dpad() -> bool {
+        true
+    }
+
     pub fn get_or_create_config(
         &mut self,
         id: InputId,
         default: InputConfiguration,
-    ) -> &InputConfiguration {
+    ) -> &mut InputConfiguration {
         self.configurations.entry(id).or_insert_with(|| default)
     }
 
@@ -26,27 +123,417 @@ impl InputSettings {
         self.configurations.get_mut(&self.selected[idx]).unwrap()
     }
 
-    pub(crate) fn reset_selected_disconnected_inputs(&mut self, inputs: &Inputs) {
-        let input_conf = self.get_selected_configuration(0);
-        if !inputs.is_connected(input_conf) {
-            self.selected[0].clone_from(&inputs.get_default_conf(0).id);
+    pub fn get_additional_configurations(
+        &self,
+        idx: usize,
+    ) -> impl Iterator<Item = &InputConfiguration> {
+        self.additional[idx]
+            .iter()
+            .filter(move |id| **id != self.selected[idx])
+            .filter_map(|id| self.configurations.get(id))
+    }
+
+    /// The keyboard mappings that control player `idx`, the selected one and any additional ones
+    pub fn get_keyboard_mappings(&self, idx: usize) -> Vec<&JoypadKeyboardMapping> {
+        std::iter::once(self.get_selected_configuration(idx))
+            .chain(self.get_additional_configurations(idx))
+            .filter_map(|input_configuration| match &input_configuration.kind {
+                InputConfigurationKind::Keyboard(mapping) => Some(mapping),
+                InputConfigurationKind::Gamepad(_) => None,
+            })
+            .collect()
+    }
+
+    /// If `key` controls a joypad of any player, those keys go to the game rather than to the hotkeys
+    pub fn is_joypad_key(&self, key: &KeyCode) -> bool {
+        (0..MAX_PLAYERS).any(|player| {
+            self.get_keyboard_mappings(player).iter().any(|mapping| {
+                MappedButton::ALL
+                    .iter()
+                    .any(|button| mapping.get(button) == Some(key))
+            })
+        })
+    }
+
+    pub(crate) fn reset_selected_disconnected_inputs<G: Gamepads>(&mut self, inputs: &Inputs<G>) {
+        self.release_disconnected_inputs(
+            |input_conf| inputs.is_connected(input_conf),
+            |player| inputs.get_default_conf(player).id.clone(),
+        );
+    }
+
+    fn release_disconnected_inputs(
+        &mut self,
+        is_connected: impl Fn(&InputConfiguration) -> bool,
+        default_id: impl Fn(usize) -> InputId,
+    ) {
+        for player in 0..MAX_PLAYERS {
+            if !is_connected(self.get_selected_configuration(player)) {
+                self.selected[player] = default_id(player);
+            }
+        }
+    }
+
+    /// Automatically select a gamepad that was just connected for the first of the `players` that is on a keyboard
+    pub(crate) fn select_connected_gamepad(&mut self, id: &InputId, players: usize) {
+        if self.selected.contains(id) {
+            // Already bound to a player (e.g. restored from the settings), don't bind it twice
+            return;
+        }
+        if let Some(player) = (0..players).find(|player| {
+            matches!(
+                self.get_selected_configuration(*player).kind,
+                InputConfigurationKind::Keyboard(_)
+            )
+        }) {
+            self.selected[player].clone_from(id);
         }
+    }
+
+    /// Two players can't share a gamepad, if that happens the later player lets go of it.
+    /// A shared selected gamepad goes back to the player's default configuration and a shared additional one is dropped.
+    pub(crate) fn reset_shared_gamepad<G: Gamepads>(&mut self, inputs: &Inputs<G>) {
+        self.release_shared_gamepads(|player| inputs.get_default_conf(player).id.clone());
+    }
+
+    fn release_shared_gamepads(&mut self, default_id: impl Fn(usize) -> InputId) {
+        // The gamepads of the players so far
+        let mut claimed: Vec<InputId> = Vec::new();
+        for player in 0..MAX_PLAYERS {
+            if claimed.contains(&self.selected[player]) {
+                log::warn!(
+                    "Two players were bound to gamepad {}, moving player {} back to its default input",
+                    self.selected[player],
+                    player + 1
+                );
+                self.selected[player] = default_id(player);
+            }
+            self.additional[player].retain(|id| {
+                let shared = claimed.contains(id);
+                if shared {
+                    log::warn!(
+                        "Two players were bound to gamepad {id}, removing it from player {}",
+                        player + 1
+                    );
+                }
+                !shared
+            });
 
-        let input_conf = self.get_selected_configuration(1);
-        if !inputs.is_connected(input_conf) {
-            self.selected[1].clone_from(&inputs.get_default_conf(1).id);
+            for id in std::iter::once(&self.selected[player]).chain(&self.additional[player]) {
+                let is_gamepad = matches!(
+                    self.configurations.get(id).map(|conf| &conf.kind),
+                    Some(InputConfigurationKind::Gamepad(_))
+                );
+                if is_gamepad && !claimed.contains(id) {
+                    claimed.push(id.clone());
+                }
+            }
         }
     }
 }
 
+/// Settings from before four players were supported only select inputs for two players, the rest get no input
+fn deserialize_selected<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<[InputId; MAX_PLAYERS], D::Error> {
+    let mut selected = Vec::<InputId>::deserialize(deserializer)?.into_iter();
+    Ok(std::array::from_fn(|_| {
+        selected.next().unwrap_or_else(|| NO_INPUT_ID.to_string())
+    }))
+}
+
+fn deserialize_additional<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<[Vec<InputId>; MAX_PLAYERS], D::Error> {
+    let mut additional = Vec::<Vec<InputId>>::deserialize(deserializer)?.into_iter();
+    Ok(std::array::from_fn(|_| {
+        additional.next().unwrap_or_default()
+    }))
+}
+
+/// The configurations, always including the one for players without an input device
+fn deserialize_configurations<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<InputId, InputConfiguration>, D::Error> {
+    let mut configurations = BTreeMap::<InputId, InputConfiguration>::deserialize(deserializer)?;
+    configurations
+        .entry(NO_INPUT_ID.to_string())
+        .or_insert_with(|| InputConfiguration {
+            id: NO_INPUT_ID.to_string(),
+            name: "No input".to_string(),
+            kind: InputConfigurationKind::Keyboard(Default::default()),
+            ignored: Vec::new(),
+            trigger_thresholds: None,
+            macros: Vec::new(),
+        });
+    Ok(configurations)
+}
+
 impl Hash for InputSettings {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.selected[0].hash(state);
-        self.selected[1].hash(state);
+        for selected in &self.selected {
+            selected.hash(state);
+        }
+        self.additional.hash(state);
 
         for (k, v) in &self.configurations {
             k.hash(state);
             v.hash(state);
         }
+        self.auto_fire.hash(state);
+        self.socd_resolution.hash(state);
+        self.trigger_thresholds.hash(state);
+        self.hat_as_dpad.hash(state);
+        self.gamepad_in_background.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(
+        selected: [&str; MAX_PLAYERS],
+        additional: [&[&str]; MAX_PLAYERS],
+    ) -> InputSettings {
+        let configuration = |id: &str, kind| {
+            (
+                id.to_string(),
+                InputConfiguration {
+                    id: id.to_string(),
+                    name: id.to_string(),
+                    kind,
+                    ignored: Vec::new(),
+                    trigger_thresholds: None,
+                    macros: Vec::new(),
+                },
+            )
+        };
+        InputSettings {
+            selected: selected.map(str::to_string),
+            additional: additional.map(|ids| ids.iter().map(|id| id.to_string()).collect()),
+            configurations: BTreeMap::from([
+                configuration(
+                    NO_INPUT_ID,
+                    InputConfigurationKind::Keyboard(Default::default()),
+                ),
+                configuration(
+                    "keyboard",
+                    InputConfigurationKind::Keyboard(Default::default()),
+                ),
+                configuration(
+                    "keyboard-2",
+                    InputConfigurationKind::Keyboard(Default::default()),
+                ),
+                configuration("pad-1", InputConfigurationKind::Gamepad(Default::default())),
+                configuration("pad-2", InputConfigurationKind::Gamepad(Default::default())),
+            ]),
+            default_gamepad_mapping: Default::default(),
+            auto_fire: Default::default(),
+            socd_resolution: Default::default(),
+            trigger_thresholds: Default::default(),
+            hat_as_dpad: true,
+            gamepad_in_background: false,
+        }
+    }
+
+    #[test]
+    fn later_players_let_go_of_shared_gamepads() {
+        let mut input_settings = settings(
+            ["pad-1", "pad-1", "keyboard", "pad-2"],
+            [&["pad-2"], &["pad-2", "keyboard"], &["pad-1"], &[]],
+        );
+        input_settings.release_shared_gamepads(|_| NO_INPUT_ID.to_string());
+
+        assert_eq!(
+            input_settings.selected,
+            ["pad-1", NO_INPUT_ID, "keyboard", NO_INPUT_ID]
+        );
+        assert_eq!(
+            input_settings.additional,
+            [
+                vec!["pad-2".to_string()],
+                vec!["keyboard".to_string()],
+                vec![],
+                vec![]
+            ]
+        );
+    }
+
+    #[test]
+    fn keyboards_and_separate_gamepads_are_left_alone() {
+        let mut input_settings = settings(
+            ["pad-1", "pad-2", "keyboard", "keyboard"],
+            [&["keyboard"], &[], &[], &[]],
+        );
+        input_settings.release_shared_gamepads(|_| NO_INPUT_ID.to_string());
+
+        assert_eq!(
+            input_settings.selected,
+            ["pad-1", "pad-2", "keyboard", "keyboard"]
+        );
+        assert_eq!(input_settings.additional[0], ["keyboard"]);
+    }
+
+    /// Player 1 and 2 start on a keyboard each, the others have no input
+    fn default_id(player: usize) -> InputId {
+        ["keyboard", "keyboard-2", NO_INPUT_ID, NO_INPUT_ID][player].to_string()
+    }
+
+    /// What happens when the `connected` gamepads are (still) plugged in and then `added` is plugged in
+    fn plug(input_settings: &mut InputSettings, connected: &[&str], added: Option<&str>) {
+        input_settings.release_disconnected_inputs(
+            |input_conf| {
+                matches!(input_conf.kind, InputConfigurationKind::Keyboard(_))
+                    || connected.contains(&input_conf.id.as_str())
+            },
+            default_id,
+        );
+        if let Some(added) = added {
+            input_settings.select_connected_gamepad(&added.to_string(), 2);
+        }
+        input_settings.release_shared_gamepads(default_id);
+    }
+
+    #[test]
+    fn a_reconnected_gamepad_goes_back_to_the_first_free_player() {
+        let mut input_settings = settings(
+            ["keyboard", "keyboard-2", NO_INPUT_ID, NO_INPUT_ID],
+            [&[], &[], &[], &[]],
+        );
+
+        plug(&mut input_settings, &[], Some("pad-1"));
+        plug(&mut input_settings, &["pad-1"], Some("pad-2"));
+        assert_eq!(
+            input_settings.selected,
+            ["pad-1", "pad-2", NO_INPUT_ID, NO_INPUT_ID]
+        );
+
+        // Player 1 is back on the keyboard until the gamepad is back
+        plug(&mut input_settings, &["pad-2"], None);
+        assert_eq!(
+            input_settings.selected,
+            ["keyboard", "pad-2", NO_INPUT_ID, NO_INPUT_ID]
+        );
+        plug(&mut input_settings, &["pad-2"], Some("pad-1"));
+        assert_eq!(
+            input_settings.selected,
+            ["pad-1", "pad-2", NO_INPUT_ID, NO_INPUT_ID]
+        );
+
+        // Both gone and back in the other order, the pads swap players
+        plug(&mut input_settings, &[], None);
+        assert_eq!(
+            input_settings.selected,
+            ["keyboard", "keyboard-2", NO_INPUT_ID, NO_INPUT_ID]
+        );
+        plug(&mut input_settings, &[], Some("pad-2"));
+        plug(&mut input_settings, &["pad-2"], Some("pad-1"));
+        assert_eq!(
+            input_settings.selected,
+            ["pad-2", "pad-1", NO_INPUT_ID, NO_INPUT_ID]
+        );
+    }
+
+    #[test]
+    fn a_gamepad_already_bound_to_a_player_stays_there() {
+        // Player 2 had the gamepad when the settings were saved
+        let mut input_settings = settings(
+            ["keyboard", "pad-1", NO_INPUT_ID, NO_INPUT_ID],
+            [&[], &[], &[], &[]],
+        );
+        plug(&mut input_settings, &["pad-1"], Some("pad-1"));
+        assert_eq!(
+            input_settings.selected,
+            ["keyboard", "pad-1", NO_INPUT_ID, NO_INPUT_ID]
+        );
+
+        // With both players on gamepads a third one isn't selected for anyone
+        plug(&mut input_settings, &["pad-1"], Some("pad-2"));
+        plug(&mut input_settings, &["pad-1", "pad-2"], Some("pad-3"));
+        assert_eq!(
+            input_settings.selected,
+            ["pad-2", "pad-1", NO_INPUT_ID, NO_INPUT_ID]
+        );
+    }
+
+    #[test]
+    fn only_keys_of_selected_and_additional_keyboards_are_joypad_keys() {
+        let mut input_settings = settings(
+            ["keyboard", "pad-1", "pad-2", NO_INPUT_ID],
+            [&[], &[], &[], &[]],
+        );
+        assert!(!input_settings.is_joypad_key(&KeyCode::Period));
+
+        input_settings
+            .configurations
+            .get_mut("keyboard")
+            .unwrap()
+            .kind = InputConfigurationKind::Keyboard(JoypadKeyboardMapping::split_layout(1));
+        assert!(input_settings.is_joypad_key(&KeyCode::Period));
+        assert!(!input_settings.is_joypad_key(&KeyCode::KeyO));
+
+        input_settings.selected[0] = "pad-1".to_string();
+        input_settings.additional[3] = vec!["keyboard".to_string()];
+        assert!(input_settings.is_joypad_key(&KeyCode::Period));
+    }
+
+    /// Pull a trigger through `pulls` (in %) and collect whether it's pressed after each one
+    fn pull(thresholds: &TriggerThresholds, trigger: GamepadTrigger, pulls: &[i32]) -> Vec<bool> {
+        let mut pressed = false;
+        pulls
+            .iter()
+            .map(|pulled| {
+                pressed = thresholds.is_pressed(&trigger, *pulled, pressed);
+                pressed
+            })
+            .collect()
+    }
+
+    #[test]
+    fn triggers_press_above_the_threshold_and_release_below_the_hysteresis() {
+        let thresholds = TriggerThresholds::default();
+        assert_eq!(
+            pull(
+                &thresholds,
+                GamepadTrigger::Left,
+                &[0, 45, 50, 51, 45, 41, 40, 45, 50, 51, 100, 39]
+            ),
+            [false, false, false, true, true, true, false, false, false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn triggers_resting_near_the_threshold_dont_chatter() {
+        let thresholds = TriggerThresholds {
+            left: 50,
+            right: 30,
+            hysteresis: 10,
+        };
+        assert_eq!(
+            pull(&thresholds, GamepadTrigger::Left, &[52, 48, 51, 47, 50, 49]),
+            [true; 6]
+        );
+        assert_eq!(
+            pull(
+                &thresholds,
+                GamepadTrigger::Right,
+                &[29, 31, 25, 21, 20, 29]
+            ),
+            [false, true, true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn hysteresis_larger_than_the_threshold_still_releases_at_rest() {
+        let thresholds = TriggerThresholds {
+            left: 10,
+            right: 10,
+            hysteresis: 20,
+        };
+        assert_eq!(
+            pull(&thresholds, GamepadTrigger::Left, &[11, 1, 0]),
+            [true, true, false]
+        );
     }
 }
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+
+use super::{JoypadButton, JoypadState};
+
+/// How to resolve simultaneous opposite directions (SOCD), e.g. Left+Right
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum SocdResolution {
+    /// Pass both directions on to the game
+    #[default]
+    Off,
+    /// Opposite directions cancel each other out
+    Neutral,
+    /// The most recently pressed direction wins
+    LastInputWins,
+    /// Up wins over Down, Left+Right is neutral
+    UpPriority,
+}
+
+impl SocdResolution {
+    pub const ALL: [SocdResolution; 4] = [
+        SocdResolution::Off,
+        SocdResolution::Neutral,
+        SocdResolution::LastInputWins,
+        SocdResolution::UpPriority,
+    ];
+}
+
+impl std::fmt::Display for SocdResolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocdResolution::Off => write!(f, "Off"),
+            SocdResolution::Neutral => write!(f, "Neutral"),
+            SocdResolution::LastInputWins => write!(f, "Last input wins"),
+            SocdResolution::UpPriority => write!(f, "Up priority"),
+        }
+    }
+}
+
+/// Resolves SOCD for one player. The result is what gets sent to the emulator (and netplay peers).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SocdResolver {
+    previous: JoypadState,
+    last_horizontal: Option<JoypadButton>,
+    last_vertical: Option<JoypadButton>,
+}
+
+impl SocdResolver {
+    pub fn resolve(&mut self, resolution: SocdResolution, state: JoypadState) -> JoypadState {
+        let newly_pressed = |button| state.is_pressed(button) && !self.previous.is_pressed(button);
+        for button in [JoypadButton::Left, JoypadButton::Right] {
+            if newly_pressed(button) {
+                self.last_horizontal = Some(button);
+            }
+        }
+        for button in [JoypadButton::Up, JoypadButton::Down] {
+            if newly_pressed(button) {
+                self.last_vertical = Some(button);
+            }
+        }
+        self.previous = state;
+
+        let mut resolved = *state;
+        for (a, b, last) in [
+            (
+                JoypadButton::Left,
+                JoypadButton::Right,
+                self.last_horizontal,
+            ),
+            (JoypadButton::Up, JoypadButton::Down, self.last_vertical),
+        ] {
+            if !(state.is_pressed(a) && state.is_pressed(b)) {
+                continue;
+            }
+            let winner = match resolution {
+                SocdResolution::Off => continue,
+                SocdResolution::Neutral => None,
+                SocdResolution::LastInputWins => last,
+                SocdResolution::UpPriority => (a == JoypadButton::Up).then_some(a),
+            };
+            resolved &= !(a as u8 | b as u8);
+            if let Some(winner) = winner {
+                resolved |= winner as u8;
+            }
+        }
+        JoypadState(resolved)
+    }
+}
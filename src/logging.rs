@@ -0,0 +1,246 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// How many of the most recent log lines to keep around for bug reports
+const RECENT_LINES: usize = 500;
+
+/// Logs through env_logger but lets the level be changed at runtime and keeps the most recent lines in memory
+struct Logger {
+    /// Writes everything it's given
+    output: env_logger::Logger,
+    /// The filter from the environment (RUST_LOG), used until the level is changed at runtime
+    env_filter: env_logger::Logger,
+}
+
+fn level_overridden() -> &'static AtomicBool {
+    static MEM: OnceLock<AtomicBool> = OnceLock::new();
+    MEM.get_or_init(|| AtomicBool::new(false))
+}
+
+fn recent_lines() -> &'static Mutex<VecDeque<String>> {
+    static MEM: OnceLock<Mutex<VecDeque<String>>> = OnceLock::new();
+    MEM.get_or_init(|| Mutex::new(VecDeque::with_capacity(RECENT_LINES)))
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if level_overridden().load(Ordering::Relaxed) {
+            metadata.level() <= log::max_level()
+        } else {
+            self.env_filter.enabled(metadata)
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        let enabled = if level_overridden().load(Ordering::Relaxed) {
+            record.level() <= log::max_level()
+        } else {
+            self.env_filter.matches(record)
+        };
+        if !enabled {
+            return;
+        }
+        self.output.log(record);
+
+        let mut recent_lines = recent_lines().lock().unwrap();
+        if recent_lines.len() == RECENT_LINES {
+            recent_lines.pop_front();
+        }
+        recent_lines.push_back(format!(
+            "[{} {}] {}",
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+    }
+
+    fn flush(&self) {
+        self.output.flush();
+    }
+}
+
+#[cfg(windows)]
+const KEPT_LOG_FILES: usize = 5;
+
+/// Where the log lines go on Windows
+#[cfg(windows)]
+enum LogFile {
+    /// Lines logged before the log file is opened, so the loading of the bundle is logged too
+    Pending(Vec<u8>),
+    Open(std::fs::File),
+    /// The log file could not be opened, the lines are dropped
+    Unavailable,
+}
+
+#[cfg(windows)]
+fn log_file() -> &'static Mutex<LogFile> {
+    static MEM: OnceLock<Mutex<LogFile>> = OnceLock::new();
+    MEM.get_or_init(|| Mutex::new(LogFile::Pending(Vec::new())))
+}
+
+#[cfg(windows)]
+struct LogFileWriter;
+
+#[cfg(windows)]
+impl std::io::Write for LogFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut *log_file().lock().unwrap() {
+            LogFile::Pending(lines) => lines.extend_from_slice(buf),
+            LogFile::Open(file) => return file.write(buf),
+            LogFile::Unavailable => {}
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut *log_file().lock().unwrap() {
+            LogFile::Open(file) => file.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The log file path in the settings directory of the bundle. Older logs are shifted so the last few are kept.
+#[cfg(windows)]
+fn rotate_log_files() -> std::path::PathBuf {
+    use crate::bundle::{BuildConfiguration, Bundle};
+
+    let (dir, name): (std::path::PathBuf, String) = match Bundle::try_current() {
+        Some(bundle) => (
+            bundle.settings_path.clone(),
+            bundle
+                .config
+                .name
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_lowercase()
+                    } else {
+                        '-'
+                    }
+                })
+                .collect(),
+        ),
+        // Still respect the data directory if the bundle is broken, that's where the player will look
+        None => (
+            BuildConfiguration::data_dir_override().unwrap_or_default(),
+            "nes-bundler".to_string(),
+        ),
+    };
+    let log_path = |idx: usize| {
+        dir.join(if idx == 0 {
+            format!("{name}-log.txt")
+        } else {
+            format!("{name}-log.{idx}.txt")
+        })
+    };
+    for idx in (0..KEPT_LOG_FILES - 1).rev() {
+        let _ = std::fs::rename(log_path(idx), log_path(idx + 1));
+    }
+    log_path(0)
+}
+
+pub fn init() {
+    let mut output = env_logger::Builder::new();
+    output.filter_level(LevelFilter::Trace);
+
+    // Windows builds have no console, the lines are kept until `open_log_file` knows where to put them
+    #[cfg(windows)]
+    output.target(env_logger::Target::Pipe(Box::new(LogFileWriter)));
+
+    let env_filter = env_logger::Builder::from_env(env_logger::Env::default()).build();
+    log::set_max_level(env_filter.filter());
+    if let Err(e) = log::set_boxed_logger(Box::new(Logger {
+        output: output.build(),
+        env_filter,
+    })) {
+        eprintln!("Could not set up logging: {e:?}");
+    }
+}
+
+/// Start writing the log to a file in the bundle's settings directory (on Windows), with what has been logged so far.
+/// This loads the bundle, so it's called after `init` to have the loading logged.
+pub fn open_log_file() {
+    #[cfg(windows)]
+    {
+        let log_path = rotate_log_files();
+        let opened = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&log_path);
+
+        let error = {
+            let mut log_file = log_file().lock().unwrap();
+            let LogFile::Pending(lines) = std::mem::replace(&mut *log_file, LogFile::Unavailable)
+            else {
+                return;
+            };
+            match opened {
+                Ok(mut file) => {
+                    use std::io::Write;
+                    let _ = file.write_all(&lines);
+                    *log_file = LogFile::Open(file);
+                    None
+                }
+                Err(e) => Some(e),
+            }
+        };
+        // Logged once the log file is unlocked, the logger needs it
+        if let Some(e) = error {
+            log::warn!("Could not open {log_path:?} for writing, {e:?}");
+        }
+    }
+}
+
+/// The most recent log lines, oldest first
+pub fn recent_log() -> String {
+    match recent_lines().try_lock() {
+        Ok(recent_lines) => recent_lines.iter().cloned().collect::<Vec<_>>().join("\n"),
+        Err(_) => "<unavailable>".to_string(),
+    }
+}
+
+#[cfg(feature = "debug")]
+pub fn ui(ui: &mut egui::Ui) {
+    let current_level = level_overridden()
+        .load(Ordering::Relaxed)
+        .then(log::max_level);
+    let mut level = current_level;
+    let level_text = |level: Option<LevelFilter>| {
+        level.map_or("From environment".to_string(), |level| level.to_string())
+    };
+    egui::ComboBox::from_label("Log level")
+        .selected_text(level_text(level))
+        .show_ui(ui, |ui| {
+            for option in [None].into_iter().chain(LevelFilter::iter().map(Some)) {
+                ui.selectable_value(&mut level, option, level_text(option));
+            }
+        });
+    match level {
+        _ if level == current_level => {}
+        Some(level) => {
+            level_overridden().store(true, Ordering::Relaxed);
+            log::set_max_level(level);
+        }
+        None => {
+            level_overridden().store(false, Ordering::Relaxed);
+            log::set_max_level(
+                env_logger::Builder::from_env(env_logger::Env::default())
+                    .build()
+                    .filter(),
+            );
+        }
+    }
+
+    if ui.button("Copy recent log").clicked() {
+        ui.ctx().copy_text(recent_log());
+    }
+}
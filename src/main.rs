@@ -11,23 +11,22 @@ use futures::executor::block_on;
 use input::gamepad::ToGamepadEvent;
 use input::gui::InputsGui;
 use input::sdl2_impl::Sdl2Gamepads;
-use input::{Inputs, JoypadState};
+use input::Inputs;
 use main_view::MainView;
 
 use sdl2::EventPump;
-use settings::{Settings, MAX_PLAYERS};
+use settings::Settings;
 use winit::application::ApplicationHandler;
 use winit::window::Window;
 
 use crate::window::Fullscreen;
-use emulation::{Emulator, EmulatorCommand, VideoBufferPool, SAMPLE_RATE};
+use emulation::{Emulator, EmulatorCommand, SharedState, VideoBufferPool, SAMPLE_RATE};
 use integer_scaling::MINIMUM_INTEGER_SCALING_SIZE;
 use std::sync::mpsc::Sender;
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use window::egui_winit_wgpu::Renderer;
 
-use emulation::{NES_HEIGHT, NES_WIDTH_4_3};
 use window::create_window;
 use winit::event::{StartCause, WindowEvent};
 use winit::event_loop::EventLoop;
@@ -36,19 +35,25 @@ use crate::main_view::gui::GuiEvent;
 
 mod audio;
 mod bundle;
+mod crash_report;
 mod emulation;
 mod gui;
 mod input;
 mod integer_scaling;
+mod logging;
 mod main_view;
 #[cfg(feature = "netplay")]
 mod netplay;
+mod restart;
 mod settings;
+mod video;
 mod window;
 
 #[tokio::main(worker_threads = 2)]
 async fn main() {
-    init_logger();
+    logging::init();
+    crash_report::install();
+    logging::open_log_file();
 
     #[cfg(feature = "netplay")]
     if std::env::args()
@@ -64,24 +69,30 @@ async fn main() {
     log::info!("NES Bundler is starting!");
 
     if let Err(e) = run().await {
-        log::error!("nes-bundler failed to run :(\n{:?}", e)
+        log::error!("nes-bundler failed to run :(\n{:?}", e);
+        let _ = sdl2::messagebox::show_simple_message_box(
+            sdl2::messagebox::MessageBoxFlag::ERROR,
+            Bundle::try_current().map_or("NES Bundler", |bundle| bundle.config.name.as_str()),
+            &format!("The game could not start.\n\n{e}"),
+            None::<&sdl2::video::Window>,
+        );
+        std::process::exit(1);
     }
     std::process::exit(0);
 }
 
-type SharedInputs = Arc<RwLock<[JoypadState; MAX_PLAYERS]>>;
-
 struct Application {
     window: Option<Arc<Window>>,
     main_view: Option<MainView>,
 
-    last_mouse_touch: Instant,
-    mouse_hide_timeout: Duration,
+    /// When the mouse was last used, `None` if it hasn't been used yet
+    last_mouse_touch: Option<Instant>,
+    focused: bool,
     audio_gui: AudioGui,
     inputs_gui: InputsGui,
     emulator_gui: EmulatorGui,
     sdl_event_pump: EventPump,
-    shared_inputs: SharedInputs,
+    shared_state: SharedState,
     frame_buffer: VideoBufferPool,
     emulator_tx: Sender<EmulatorCommand>,
 }
@@ -89,6 +100,8 @@ impl Application {
     async fn new(_event_loop: &EventLoop<()>) -> anyhow::Result<Self> {
         // Needed because: https://github.com/libsdl-org/SDL/issues/5380#issuecomment-1071626081
         sdl2::hint::set("SDL_JOYSTICK_THREAD", "1");
+        // Always get the gamepad events, whether they reach the game when the window isn't focused is a setting
+        sdl2::hint::set("SDL_JOYSTICK_ALLOW_BACKGROUND_EVENTS", "1");
         // TODO: Perhaps do this to fix this issue: https://github.com/libsdl-org/SDL/issues/7896#issuecomment-1616700934
         //sdl2::hint::set("SDL_JOYSTICK_RAWINPUT", "0");
 
@@ -97,12 +110,13 @@ impl Application {
 
         let mut audio = Audio::new(
             &sdl_context,
-            Duration::from_millis(Settings::current().audio.latency as u64),
+            Settings::current().audio.latency(),
             SAMPLE_RATE as u32,
         )?;
 
         let inputs = Inputs::new(Sdl2Gamepads::new(
             sdl_context.game_controller().map_err(anyhow::Error::msg)?,
+            sdl_context.joystick().map_err(anyhow::Error::msg)?,
         ));
         let audio_tx = audio.stream.start()?;
 
@@ -110,25 +124,54 @@ impl Application {
         let audio_gui = AudioGui::new(audio);
 
         let emulator = Emulator::new()?;
-        let shared_inputs = Arc::new(RwLock::new([JoypadState(0); MAX_PLAYERS]));
+        let shared_state = SharedState::default();
         let frame_buffer = VideoBufferPool::new();
         let (emulator_gui, emulator_tx) = emulator
-            .start_thread(audio_tx, shared_inputs.clone(), frame_buffer.clone())
+            .start_thread(audio_tx, shared_state.clone(), frame_buffer.clone())
             .await?;
 
-        let mouse_hide_timeout = Duration::from_secs(1);
+        if Bundle::current().games.len() > 1 {
+            // Start by choosing what to play, joining netplay below takes precedence
+            main_view::gui::MainGui::set_main_menu_state(main_view::gui::MainMenuState::Games);
+        }
+
+        #[cfg(feature = "netplay")]
+        if let Some(room_name) = std::env::args()
+            .skip(1)
+            .find_map(|arg| netplay::parse_invite_link(&arg))
+        {
+            log::info!("Joining private game {room_name} from invite link");
+            let _ = emulator_tx.send(EmulatorCommand::Netplay(netplay::NetplayCommand::JoinGame(
+                room_name,
+            )));
+            main_view::gui::MainGui::set_main_menu_state(main_view::gui::MainMenuState::Netplay);
+        } else {
+            match Bundle::current().config.netplay.start_mode() {
+                netplay::StartMode::Game => {}
+                netplay::StartMode::Netplay => main_view::gui::MainGui::set_main_menu_state(
+                    main_view::gui::MainMenuState::Netplay,
+                ),
+                netplay::StartMode::FindGame => {
+                    log::info!("Looking for a public game at start");
+                    let _ = emulator_tx
+                        .send(EmulatorCommand::Netplay(netplay::NetplayCommand::FindGame));
+                    main_view::gui::MainGui::set_main_menu_state(
+                        main_view::gui::MainMenuState::Netplay,
+                    );
+                }
+            }
+        }
+
         Ok(Self {
             window: None,
             main_view: None,
-            mouse_hide_timeout,
-            last_mouse_touch: Instant::now()
-                .checked_sub(mouse_hide_timeout)
-                .expect("there to be an instant `mouse_hide_timeout` seconds in the past"),
+            focused: true,
+            last_mouse_touch: None,
             audio_gui,
             inputs_gui,
             emulator_gui,
             sdl_event_pump,
-            shared_inputs,
+            shared_state,
             frame_buffer,
             emulator_tx,
         })
@@ -139,14 +182,30 @@ impl ApplicationHandler for Application {
         let window = create_window(
             &Bundle::current().config.name,
             MINIMUM_INTEGER_SCALING_SIZE,
-            Size::new(NES_WIDTH_4_3, NES_HEIGHT),
+            Settings::current().video.min_window_size(),
             event_loop,
         )
         .expect("a window to be created");
         let window = Arc::new(window);
 
-        let renderer = block_on(Renderer::new(window.clone())).expect("a renderer to be created");
-        let main_view = MainView::new(renderer, self.emulator_tx.clone());
+        let renderer = match block_on(Renderer::new(window.clone())) {
+            Ok(renderer) => renderer,
+            Err(e) => {
+                log::error!("Could not create a renderer: {e:?}");
+                let _ = sdl2::messagebox::show_simple_message_box(
+                    sdl2::messagebox::MessageBoxFlag::ERROR,
+                    &Bundle::current().config.name,
+                    &format!("Could not start the graphics.\nMake sure your graphics drivers are up to date.\n\n{e}"),
+                    None::<&sdl2::video::Window>,
+                );
+                std::process::exit(1);
+            }
+        };
+        let main_view = MainView::new(
+            renderer,
+            self.emulator_tx.clone(),
+            self.shared_state.clone(),
+        );
         self.main_view = Some(main_view);
         self.window = Some(window);
     }
@@ -171,7 +230,26 @@ impl ApplicationHandler for Application {
     ) {
         if let Some(main_view) = &mut self.main_view {
             match window_event {
-                WindowEvent::CloseRequested | WindowEvent::Destroyed => event_loop.exit(),
+                WindowEvent::CloseRequested => {
+                    if Bundle::current().config.lock_quit {
+                        // A kiosk can't be closed from the window either
+                        log::debug!("Ignoring the close request, quitting is locked");
+                    } else if self.shared_state.netplay_active()
+                        && Settings::current().emulation.confirm_quit_in_netplay
+                        && !matches!(
+                            main_view::gui::MainGui::main_menu_state(),
+                            main_view::gui::MainMenuState::ConfirmQuit
+                        )
+                    {
+                        main_view::gui::MainGui::set_main_menu_state(
+                            main_view::gui::MainMenuState::ConfirmQuit,
+                        );
+                    } else {
+                        shut_down(&self.emulator_tx, &self.shared_state);
+                        event_loop.exit();
+                    }
+                }
+                WindowEvent::Destroyed => event_loop.exit(),
                 WindowEvent::RedrawRequested => {
                     main_view.render(
                         &self.frame_buffer,
@@ -179,13 +257,22 @@ impl ApplicationHandler for Application {
                         &mut self.inputs_gui,
                         &mut self.emulator_gui,
                     );
+                    let restart = restart::restart_requested();
+                    if main_view::gui::MainGui::quit_requested() || restart {
+                        shut_down(&self.emulator_tx, &self.shared_state);
+                        if restart {
+                            restart::relaunch();
+                        }
+                        event_loop.exit();
+                    }
                     if let Some(window) = &self.window {
                         window.request_redraw();
                     }
                 }
                 WindowEvent::MouseInput { .. } | WindowEvent::CursorMoved { .. } => {
-                    self.last_mouse_touch = Instant::now();
+                    self.last_mouse_touch = Some(Instant::now());
                 }
+                WindowEvent::Focused(focused) => self.focused = focused,
                 _ => {}
             }
 
@@ -202,13 +289,20 @@ impl ApplicationHandler for Application {
                     &mut self.emulator_gui,
                 );
             }
-            let new_inputs = if !main_view.main_gui.visible() {
-                self.inputs_gui.inputs.joypads
-            } else {
-                // Don't let the inputs control the game if the gui is showing
-                [JoypadState(0), JoypadState(0)]
-            };
-            *self.shared_inputs.write().unwrap() = new_inputs;
+            // Gamepads keep working without focus if enabled, the keyboard never does
+            let ignore_inputs = main_view.main_gui.visible()
+                || !self.focused && !Settings::current().input.gamepad_in_background;
+            let new_inputs = self.inputs_gui.inputs.game_joypads(ignore_inputs);
+            *self.shared_state.inputs.write().unwrap() = new_inputs;
+            *self.shared_state.turbo.write().unwrap() =
+                self.inputs_gui.inputs.game_turbo(ignore_inputs);
+
+            // Unpause after the inputs are updated so the first frame after the menu sees what's actually held
+            main_view.sync_pause(self.focused);
+            self.audio_gui
+                .audio
+                .stream
+                .set_paused(self.shared_state.paused());
 
             main_view.handle_window_event(
                 &window_event,
@@ -217,18 +311,71 @@ impl ApplicationHandler for Application {
                 &mut self.emulator_gui,
             );
             if let Some(window) = &self.window {
+                let video_settings = &Settings::current().video;
+                let idle = video_settings.cursor_hide_timeout().is_some_and(|timeout| {
+                    self.last_mouse_touch.map_or(true, |last_mouse_touch| {
+                        last_mouse_touch.elapsed() >= timeout
+                    })
+                });
                 window.set_cursor_visible(
-                    !(window.is_fullscreen()
+                    !((window.is_fullscreen() || video_settings.hide_cursor_in_window)
                         && !main_view.main_gui.visible()
-                        && Instant::now()
-                            .duration_since(self.last_mouse_touch)
-                            .gt(&self.mouse_hide_timeout)),
+                        && idle),
                 );
             }
         }
     }
 }
 
+/// Wrap up everything that would be lost or left hanging when the application quits (or restarts)
+fn shut_down(emulator_tx: &Sender<EmulatorCommand>, shared_state: &SharedState) {
+    leave_netplay(emulator_tx, shared_state);
+    finish_recording(emulator_tx, shared_state);
+    flush_sram(emulator_tx);
+}
+
+/// Make sure the last progress in the game is saved, it's otherwise only written every second
+fn flush_sram(emulator_tx: &Sender<EmulatorCommand>) {
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    if emulator_tx
+        .send(EmulatorCommand::FlushSram(done_tx))
+        .is_ok()
+    {
+        let _ = done_rx.recv_timeout(Duration::from_secs(1));
+    }
+}
+
+/// Close a netplay session before quitting, so the peer sees us leave instead of a timeout
+#[allow(unused_variables)]
+fn leave_netplay(emulator_tx: &Sender<EmulatorCommand>, shared_state: &SharedState) {
+    #[cfg(feature = "netplay")]
+    if shared_state.netplay_active() {
+        log::info!("Leaving the netplay game before quitting");
+        let _ = emulator_tx.send(EmulatorCommand::Netplay(
+            netplay::NetplayCommand::Disconnect,
+        ));
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while shared_state.netplay_active() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+fn finish_recording(emulator_tx: &Sender<EmulatorCommand>, shared_state: &SharedState) {
+    if let Err(e) = audio::wav::WavRecording::stop() {
+        log::error!("Could not save the audio recording: {e:?}");
+    }
+    if shared_state.recording() {
+        log::info!("Finishing the recording before quitting");
+        let _ = emulator_tx.send(EmulatorCommand::ToggleRecording);
+        // Encoding the sound and muxing it in takes a moment for long recordings
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while shared_state.recording() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
 async fn run() -> anyhow::Result<()> {
     let event_loop = EventLoop::new()?;
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
@@ -240,32 +387,6 @@ async fn run() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn init_logger() {
-    #[cfg(windows)]
-    {
-        match std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open("nes-bundler-log.txt")
-        {
-            Ok(log_file) => {
-                env_logger::Builder::from_env(env_logger::Env::default())
-                    .target(env_logger::Target::Pipe(Box::new(log_file)))
-                    .init();
-            }
-            Err(e) => {
-                env_logger::init();
-                log::warn!("Could not open nes-bundler-log.txt for writing, {:?}", e)
-            }
-        }
-    }
-    #[cfg(not(windows))]
-    {
-        env_logger::init();
-    }
-}
-
 pub struct Size {
     pub width: u32,
     pub height: u32,
@@ -1,22 +1,59 @@
 use std::{
-    sync::{mpsc::Sender, Arc, OnceLock, RwLock},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, OnceLock, RwLock,
+    },
     time::{Duration, Instant},
 };
 
 use egui::{
     Align2, Button, Color32, Context, FontId, Label, Margin, Response, RichText, Style, Ui, Widget,
 };
+use serde::Deserialize;
 use winit::dpi::LogicalSize;
 
 use crate::{
     audio::gui::AudioGui,
     bundle::Bundle,
-    emulation::{gui::EmulatorGui, EmulatorCommand},
-    gui::{esc_pressed, MenuButton},
-    input::{gamepad::GamepadEvent, gui::InputsGui, KeyEvent},
+    emulation::{cheats::CheatsGui, gui::EmulatorGui, EmulatorCommand},
+    gui::{esc_down, esc_pressed, MenuButton},
+    input::{gamepad::GamepadEvent, gui::InputsGui, hotkeys::MenuOpenMethod, KeyEvent},
     settings::Settings,
+    video::gui::VideoGui,
 };
 
+#[derive(Deserialize, Debug)]
+pub struct MenuHintVoca {
+    /// The hint shown when the game starts, derived from how the menu is opened if not set
+    #[serde(default = "Default::default")]
+    pub text: Option<String>,
+    /// How long the hint is shown (in seconds), 0 to never show it
+    #[serde(default = "MenuHintVoca::default_duration")]
+    pub duration: u64,
+}
+
+impl MenuHintVoca {
+    fn default_duration() -> u64 {
+        5
+    }
+
+    fn text(&self) -> String {
+        self.text
+            .clone()
+            .unwrap_or_else(|| Settings::current().hotkeys.menu_hint())
+    }
+}
+
+impl Default for MenuHintVoca {
+    fn default() -> Self {
+        Self {
+            text: None,
+            duration: Self::default_duration(),
+        }
+    }
+}
+
 pub trait ToGuiEvent {
     /// Convert the struct to a GuiEvent
     fn to_gui_event(&self) -> Option<GuiEvent>;
@@ -48,11 +85,24 @@ pub enum MainMenuState {
     Main,
     Settings,
     Netplay,
+    Controls,
+    Games,
+    About,
+    ConfirmQuit,
 }
 pub struct MainGui {
     start_time: Instant,
     window: Arc<winit::window::Window>,
     emulator_tx: Sender<EmulatorCommand>,
+    esc_held_since: Option<Instant>,
+    last_esc_tap: Option<Instant>,
+    /// Escape is still held after opening the menu by holding it
+    esc_latched: bool,
+    /// The menu was last used with a gamepad, where mashing the confirm button can easily hit the wrong item
+    pub gamepad_navigation: bool,
+    /// The quit confirmation was opened from the menu rather than by closing the window during netplay
+    confirm_quit_from_menu: bool,
+    cheats_gui: CheatsGui,
 }
 
 impl MainGui {
@@ -60,13 +110,60 @@ impl MainGui {
         static MEM: OnceLock<RwLock<MainMenuState>> = OnceLock::new();
         MEM.get_or_init(|| RwLock::new(MainMenuState::Closed))
     }
+    fn _last_main_menu_state() -> &'static RwLock<MainMenuState> {
+        static MEM: OnceLock<RwLock<MainMenuState>> = OnceLock::new();
+        MEM.get_or_init(|| RwLock::new(MainMenuState::Main))
+    }
     pub fn set_main_menu_state(main_menu_state: MainMenuState) {
+        if !matches!(
+            main_menu_state,
+            MainMenuState::Closed | MainMenuState::ConfirmQuit
+        ) {
+            *Self::_last_main_menu_state().write().unwrap() = main_menu_state.clone();
+        }
         *Self::_main_menu_state().write().unwrap() = main_menu_state;
     }
+    /// Open the menu, either at the main menu or where it was last closed (depending on the settings)
+    pub fn open_main_menu() {
+        let last_state = Self::_last_main_menu_state().read().unwrap().clone();
+        Self::set_main_menu_state(match last_state {
+            MainMenuState::Settings if Bundle::current().config.lock_settings => {
+                MainMenuState::Main
+            }
+            last_state if Settings::current().hotkeys.menu_reopen_last => last_state,
+            _ => MainMenuState::Main,
+        });
+    }
     pub fn main_menu_state() -> MainMenuState {
         Self::_main_menu_state().read().unwrap().clone()
     }
 
+    fn _toast() -> &'static RwLock<Option<(String, Instant)>> {
+        static MEM: OnceLock<RwLock<Option<(String, Instant)>>> = OnceLock::new();
+        MEM.get_or_init(|| RwLock::new(None))
+    }
+    /// Briefly show a message on top of the game, replacing any previous one
+    pub fn show_toast(message: impl Into<String>) {
+        Self::show_toast_for(message, Self::TOAST_DURATION);
+    }
+    /// Like `show_toast` but for messages that need to stay up longer
+    pub fn show_toast_for(message: impl Into<String>, duration: Duration) {
+        *Self::_toast().write().unwrap() = Some((message.into(), Instant::now() + duration));
+    }
+    const TOAST_DURATION: Duration = Duration::from_secs(2);
+
+    fn _quit_requested() -> &'static AtomicBool {
+        static MEM: AtomicBool = AtomicBool::new(false);
+        &MEM
+    }
+    /// Ask the application to quit, it will leave any netplay session gracefully first
+    pub fn request_quit() {
+        Self::_quit_requested().store(true, Ordering::Relaxed);
+    }
+    pub fn quit_requested() -> bool {
+        Self::_quit_requested().load(Ordering::Relaxed)
+    }
+
     // Convenience
     pub fn visible(&self) -> bool {
         !matches!(Self::main_menu_state(), MainMenuState::Closed)
@@ -80,6 +177,53 @@ impl MainGui {
             start_time: Instant::now(),
             window,
             emulator_tx,
+            esc_held_since: None,
+            last_esc_tap: None,
+            esc_latched: false,
+            gamepad_navigation: false,
+            confirm_quit_from_menu: false,
+            cheats_gui: CheatsGui::default(),
+        }
+    }
+
+    const MENU_HOLD_DURATION: Duration = Duration::from_millis(600);
+    const MENU_DOUBLE_TAP_WINDOW: Duration = Duration::from_millis(400);
+
+    fn menu_requested(&mut self, ctx: &Context) -> bool {
+        let menu_open = Settings::current().hotkeys.menu_open;
+        match menu_open {
+            MenuOpenMethod::Tap => esc_pressed(ctx),
+            MenuOpenMethod::DoubleTap => {
+                if !esc_pressed(ctx) {
+                    return false;
+                }
+                let now = Instant::now();
+                if self.last_esc_tap.is_some_and(|last_tap| {
+                    now.duration_since(last_tap) < Self::MENU_DOUBLE_TAP_WINDOW
+                }) {
+                    self.last_esc_tap = None;
+                    true
+                } else {
+                    self.last_esc_tap = Some(now);
+                    false
+                }
+            }
+            MenuOpenMethod::Hold => {
+                // Swallow the press (and key repeats) while holding
+                esc_pressed(ctx);
+                if !esc_down(ctx) {
+                    self.esc_held_since = None;
+                    return false;
+                }
+                let held_since = *self.esc_held_since.get_or_insert_with(Instant::now);
+                if held_since.elapsed() >= Self::MENU_HOLD_DURATION {
+                    self.esc_held_since = None;
+                    self.esc_latched = true;
+                    true
+                } else {
+                    false
+                }
+            }
         }
     }
 
@@ -87,7 +231,7 @@ impl MainGui {
         ui.add(
             Label::new(
                 RichText::new(text)
-                    .font(FontId::monospace(30.0))
+                    .font(FontId::monospace(MenuButton::font_size()))
                     .strong()
                     .background_color(Self::MESSAGE_TEXT_BACKGROUND)
                     .color(Self::MESSAGE_TEXT_COLOR),
@@ -133,31 +277,77 @@ impl MainGui {
         audio_gui: &mut AudioGui,
         inputs_gui: &mut InputsGui,
         emulator_gui: &mut EmulatorGui,
+        video_gui: &mut VideoGui,
+        recording: bool,
     ) {
         {
             #[cfg(feature = "debug")]
             puffin::profile_scope!("Main ui");
 
-            if !self.visible() && esc_pressed(ctx) {
-                Self::set_main_menu_state(MainMenuState::Main);
+            Settings::current().accessibility.apply(ctx);
+
+            if self.esc_latched {
+                if esc_down(ctx) {
+                    // Don't let the held key close the menu it just opened
+                    esc_pressed(ctx);
+                } else {
+                    self.esc_latched = false;
+                }
+            }
+            if !self.visible() && self.menu_requested(ctx) {
+                Self::open_main_menu();
             }
             match Self::main_menu_state() {
                 MainMenuState::Main => {
                     Self::ui_main_container(&self.window, None, ctx, |ui| {
+                        if Bundle::current().config.high_score.is_some() {
+                            if let Some(high_score) = Settings::current().high_score {
+                                ui.vertical_centered(|ui| {
+                                    Self::message_ui(ui, format!("HIGH SCORE {high_score}"));
+                                });
+                                ui.end_row();
+                            }
+                        }
                         if Self::menu_item_ui(ui, "BACK").clicked() || esc_pressed(ctx) {
                             Self::set_main_menu_state(MainMenuState::Closed);
                         }
 
+                        if Bundle::current().games.len() > 1
+                            && Self::menu_item_ui(ui, "CHANGE GAME").clicked()
+                        {
+                            Self::set_main_menu_state(MainMenuState::Games);
+                        }
+
                         if let Some(name) = emulator_gui.name() {
                             if Self::menu_item_ui(ui, name.to_uppercase()).clicked() {
                                 Self::set_main_menu_state(MainMenuState::Netplay);
                             }
                         }
 
-                        if Self::menu_item_ui(ui, "SETTINGS").clicked() {
+                        if !Bundle::current().config.lock_settings
+                            && Self::menu_item_ui(ui, "SETTINGS").clicked()
+                        {
                             Self::set_main_menu_state(MainMenuState::Settings);
                         }
 
+                        if Self::menu_item_ui(ui, "CONTROLS").clicked() {
+                            Self::set_main_menu_state(MainMenuState::Controls);
+                        }
+
+                        let recording_label = if recording {
+                            "STOP RECORDING"
+                        } else {
+                            "RECORD VIDEO"
+                        };
+                        if Self::menu_item_ui(ui, recording_label).clicked() {
+                            let _ = self.emulator_tx.send(EmulatorCommand::ToggleRecording);
+                            Self::set_main_menu_state(MainMenuState::Closed);
+                        }
+
+                        if Self::menu_item_ui(ui, "ABOUT").clicked() {
+                            Self::set_main_menu_state(MainMenuState::About);
+                        }
+
                         #[cfg(feature = "debug")]
                         {
                             if Self::menu_item_ui(ui, "PROFILING").clicked() {
@@ -165,8 +355,17 @@ impl MainGui {
                             }
                         }
 
-                        if Self::menu_item_ui(ui, "QUIT GAME").clicked() {
-                            std::process::exit(0);
+                        if !Bundle::current().config.lock_quit
+                            && Self::menu_item_ui(ui, "QUIT GAME").clicked()
+                        {
+                            if self.gamepad_navigation
+                                && Settings::current().emulation.confirm_quit_on_gamepad
+                            {
+                                self.confirm_quit_from_menu = true;
+                                Self::set_main_menu_state(MainMenuState::ConfirmQuit);
+                            } else {
+                                Self::request_quit();
+                            }
                         }
                     });
                 }
@@ -182,6 +381,15 @@ impl MainGui {
                             ui.add_space(10.0);
                             ui.separator();
                             ui.add_space(10.0);
+                            if let Some(name) = video_gui.name() {
+                                ui.vertical_centered(|ui| {
+                                    ui.heading(name);
+                                });
+                                video_gui.ui(ui);
+                            }
+                            ui.add_space(10.0);
+                            ui.separator();
+                            ui.add_space(10.0);
                             if let Some(name) = inputs_gui.name() {
                                 ui.vertical_centered(|ui| {
                                     ui.heading(name);
@@ -189,6 +397,54 @@ impl MainGui {
                                 inputs_gui.ui(ui);
                             }
 
+                            ui.add_space(10.0);
+                            ui.separator();
+                            ui.vertical_centered(|ui| {
+                                ui.heading("Accessibility");
+                            });
+                            let mut accessibility = Settings::current().accessibility;
+                            accessibility.ui(ui);
+                            Settings::current_mut().accessibility = accessibility;
+
+                            let mut menu_open = Settings::current().hotkeys.menu_open;
+                            egui::ComboBox::from_label("Open menu with")
+                                .selected_text(menu_open.to_string())
+                                .show_ui(ui, |ui| {
+                                    for method in MenuOpenMethod::ALL {
+                                        ui.selectable_value(
+                                            &mut menu_open,
+                                            method,
+                                            method.to_string(),
+                                        );
+                                    }
+                                })
+                                .response
+                                .on_hover_text("The menu key or the menu button on a gamepad");
+                            Settings::current_mut().hotkeys.menu_open = menu_open;
+
+                            let mut menu_reopen_last = Settings::current().hotkeys.menu_reopen_last;
+                            ui.checkbox(&mut menu_reopen_last, "Reopen the menu where it was left")
+                                .on_hover_text("Go back to the last menu page (like Settings) instead of the main menu");
+                            Settings::current_mut().hotkeys.menu_reopen_last = menu_reopen_last;
+
+                            ui.add_space(10.0);
+                            ui.separator();
+                            ui.vertical_centered(|ui| {
+                                ui.heading("Emulation");
+                            });
+                            let mut emulation = Settings::current().emulation.clone();
+                            emulation.ui(ui);
+                            Settings::current_mut().emulation = emulation;
+
+                            ui.add_space(10.0);
+                            ui.separator();
+                            ui.vertical_centered(|ui| {
+                                ui.heading("Cheats");
+                            });
+                            let mut cheats = Settings::current().cheats.clone();
+                            self.cheats_gui.ui(ui, &mut cheats);
+                            Settings::current_mut().cheats = cheats;
+
                             if Bundle::current().config.supported_nes_regions.len() > 1 {
                                 ui.separator();
                                 ui.vertical_centered(|ui| {
@@ -208,7 +464,7 @@ impl MainGui {
                                                 .radio_value(
                                                     Settings::current_mut().get_nes_region(),
                                                     supported_region.clone(),
-                                                    format!("{:?}", supported_region),
+                                                    supported_region.to_string(),
                                                 )
                                                 .changed()
                                             {
@@ -223,6 +479,24 @@ impl MainGui {
 
                             ui.vertical_centered(|ui| {
                                 ui.add_space(20.0);
+                                // The audio latency can only be set when the audio starts
+                                let restart_required = audio_gui.audio.stream.latency()
+                                    != Settings::current().audio.latency();
+                                if restart_required {
+                                    ui.label(
+                                        RichText::new("Some changes need a restart to apply")
+                                            .color(Color32::DARK_RED),
+                                    );
+                                    if Button::new(
+                                        RichText::new("Restart to apply")
+                                            .font(FontId::proportional(20.0)),
+                                    )
+                                    .ui(ui)
+                                    .clicked()
+                                    {
+                                        crate::restart::request_restart();
+                                    }
+                                }
                                 if Button::new(
                                     RichText::new("Close").font(FontId::proportional(20.0)),
                                 )
@@ -244,6 +518,112 @@ impl MainGui {
                         });
                     }
                 }
+                MainMenuState::Controls => {
+                    Self::ui_main_container(&self.window, Some("Controls"), ctx, |ui| {
+                        inputs_gui.controls_ui(ui);
+                        ui.vertical_centered(|ui| {
+                            ui.add_space(20.0);
+                            if Button::new(RichText::new("Close").font(FontId::proportional(20.0)))
+                                .ui(ui)
+                                .clicked()
+                                || esc_pressed(ui.ctx())
+                            {
+                                Self::set_main_menu_state(MainMenuState::Main);
+                            }
+                        });
+                    });
+                }
+                MainMenuState::Games => {
+                    Self::ui_main_container(&self.window, None, ctx, |ui| {
+                        let selected = Bundle::current().selected_game().name.clone();
+                        for game in &Bundle::current().games {
+                            let text = if game.name == selected {
+                                format!("> {} <", game.name.to_uppercase())
+                            } else {
+                                game.name.to_uppercase()
+                            };
+                            if Self::menu_item_ui(ui, text).clicked() {
+                                let _ = self
+                                    .emulator_tx
+                                    .send(EmulatorCommand::ChangeGame(game.name.clone()));
+                                Self::set_main_menu_state(MainMenuState::Closed);
+                            }
+                        }
+                        if Self::menu_item_ui(ui, "BACK").clicked() || esc_pressed(ctx) {
+                            Self::set_main_menu_state(MainMenuState::Main);
+                        }
+                    });
+                }
+                MainMenuState::About => {
+                    let config = &Bundle::current().config;
+                    Self::ui_main_container(&self.window, Some("About"), ctx, |ui| {
+                        ui.vertical_centered(|ui| {
+                            ui.heading(&config.name);
+                            ui.label(format!("Version {}", config.version()));
+                            if let Some(author) = &config.author {
+                                ui.label(format!("By {author}"));
+                            }
+                            if let Some(website) = &config.website {
+                                ui.hyperlink(website);
+                            }
+                            if let Some(license) = &config.license {
+                                ui.add_space(10.0);
+                                ui.label(license);
+                            }
+                            ui.add_space(10.0);
+                            ui.separator();
+                            ui.label(
+                                RichText::new(format!(
+                                    "Made with nes-bundler {}",
+                                    env!("CARGO_PKG_VERSION")
+                                ))
+                                .small(),
+                            );
+                            ui.hyperlink("https://github.com/tedsteen/nes-bundler");
+
+                            ui.add_space(20.0);
+                            if Button::new(RichText::new("Close").font(FontId::proportional(20.0)))
+                                .ui(ui)
+                                .clicked()
+                                || esc_pressed(ui.ctx())
+                            {
+                                Self::set_main_menu_state(MainMenuState::Main);
+                            }
+                        });
+                    });
+                }
+                MainMenuState::ConfirmQuit => {
+                    let from_menu = self.confirm_quit_from_menu;
+                    Self::ui_main_container(&self.window, None, ctx, |ui| {
+                        ui.vertical_centered(|ui| {
+                            Self::message_ui(
+                                ui,
+                                if from_menu {
+                                    "QUIT THE GAME?"
+                                } else {
+                                    "LEAVE THE NETPLAY GAME?"
+                                },
+                            );
+                        });
+                        ui.end_row();
+                        // Back comes first so it's what gets focused
+                        if Self::menu_item_ui(ui, "BACK").clicked() || esc_pressed(ctx) {
+                            Self::set_main_menu_state(if from_menu {
+                                MainMenuState::Main
+                            } else {
+                                MainMenuState::Closed
+                            });
+                        }
+                        if !Bundle::current().config.lock_quit
+                            && Self::menu_item_ui(ui, "QUIT GAME").clicked()
+                        {
+                            Self::request_quit();
+                        }
+                    });
+                    if !matches!(Self::main_menu_state(), MainMenuState::ConfirmQuit) {
+                        self.confirm_quit_from_menu = false;
+                    }
+                }
                 MainMenuState::Closed => {}
             }
         }
@@ -259,7 +639,7 @@ impl MainGui {
                 .show(ctx, |ui| {
                     ui.vertical_centered(|ui| {
                         let gui_components: &mut [&mut dyn GuiComponent] =
-                            &mut [audio_gui, inputs_gui, emulator_gui];
+                            &mut [audio_gui, inputs_gui, emulator_gui, video_gui];
                         for gui in gui_components.iter_mut() {
                             if gui.name().is_some() {
                                 {
@@ -271,8 +651,14 @@ impl MainGui {
                                 }
                             }
                         }
-                        if self.start_time.elapsed() < Duration::from_secs(5) {
-                            Self::message_ui(ui, "Press ESC for menu");
+                        let menu_hint = &Bundle::current().config.vocabulary.menu_hint;
+                        if self.start_time.elapsed() < Duration::from_secs(menu_hint.duration) {
+                            Self::message_ui(ui, menu_hint.text());
+                        }
+                        if let Some((message, hide_at)) = &*Self::_toast().read().unwrap() {
+                            if Instant::now() < *hide_at {
+                                Self::message_ui(ui, message);
+                            }
                         }
                     });
                 });
@@ -285,9 +671,10 @@ impl MainGui {
         audio_gui: &mut AudioGui,
         inputs_gui: &mut InputsGui,
         emulator_gui: &mut EmulatorGui,
+        video_gui: &mut VideoGui,
     ) {
         let gui_components: &mut [&mut dyn GuiComponent] =
-            &mut [audio_gui, inputs_gui, emulator_gui];
+            &mut [audio_gui, inputs_gui, emulator_gui, video_gui];
 
         for gui in gui_components {
             gui.handle_event(gui_event);
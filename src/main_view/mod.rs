@@ -1,16 +1,31 @@
-use std::sync::mpsc::Sender;
+use std::{
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
 
 use egui::{load::SizedTexture, Color32, Image, Vec2};
 
 use crate::{
     audio::gui::AudioGui,
+    bundle::Bundle,
     emulation::{
-        gui::EmulatorGui, EmulatorCommand, VideoBufferPool, NES_HEIGHT, NES_WIDTH, NES_WIDTH_4_3,
+        gui::EmulatorGui, EmulatorCommand, SharedState, VideoBufferPool, NES_HEIGHT, NES_WIDTH,
+        NES_WIDTH_4_3, SAVE_SLOTS,
     },
     input::{
-        buttons::GamepadButton, gamepad::GamepadEvent, gui::InputsGui, keys::Modifiers, KeyEvent,
+        buttons::GamepadButton,
+        gamepad::GamepadEvent,
+        gui::InputsGui,
+        hotkeys::Hotkey,
+        keys::{KeyCode, Modifiers},
+        Inputs, JoypadState, KeyEvent,
     },
     integer_scaling::{calculate_size_corrected, MINIMUM_INTEGER_SCALING_SIZE},
+    settings::{Settings, MAX_PLAYERS},
+    video::{
+        frame_blending::FrameBlender, gui::VideoGui, post_process::PostProcessor,
+        screenshot::save_screenshot, TitleInfo,
+    },
     window::{
         egui_winit_wgpu::{texture::Texture, Renderer},
         Fullscreen,
@@ -23,19 +38,51 @@ pub mod gui;
 
 pub struct MainView {
     pub main_gui: MainGui,
+    video_gui: VideoGui,
     modifiers: Modifiers,
     nes_texture: Texture,
+    post_processor: PostProcessor,
+    frame_blender: FrameBlender,
     renderer: Renderer,
+    emulator_tx: Sender<EmulatorCommand>,
+    shared_state: SharedState,
+    slow_motion: bool,
+    fast_forward_key_held: bool,
+    fast_forwarding: bool,
+    /// Paused with the pause hotkey, for stepping through frames
+    debug_paused: bool,
+    /// The last frame from the emulator, kept for screenshots
+    last_frame: Vec<u8>,
+    screenshot_requested: bool,
+    menu_combo_held_since: Option<Instant>,
+    menu_combo_triggered: bool,
+    pause_requested: bool,
+    /// The save state slot the save and load hotkeys act on
+    save_slot: u8,
+    title: String,
+    min_window_size: u16,
+    fps_counter: (Instant, u32),
+    fps: u32,
+    /// Frames rendered since the counters were last sampled
+    render_counter: u32,
+    render_fps: u32,
+    /// The emulator's frame when the counters were last sampled
+    emulator_frame: u32,
+    emulator_fps: u32,
 }
 
 fn to_egui_key(gamepad_button: &GamepadButton) -> Option<egui::Key> {
+    let hotkeys = &Settings::current().hotkeys;
+    // The menu button acts as the menu key
+    if *gamepad_button == hotkeys.menu_button {
+        return Some(hotkeys.menu_egui_key());
+    }
     match gamepad_button {
         GamepadButton::DPadUp => Some(egui::Key::ArrowUp),
         GamepadButton::DPadDown => Some(egui::Key::ArrowDown),
         GamepadButton::DPadLeft => Some(egui::Key::ArrowLeft),
         GamepadButton::DPadRight => Some(egui::Key::ArrowRight),
         GamepadButton::A => Some(egui::Key::Enter),
-        GamepadButton::Guide => Some(egui::Key::Escape),
         _ => None,
     }
 }
@@ -63,16 +110,254 @@ fn to_egui_event(gamepad_event: &GamepadEvent) -> Option<egui::Event> {
 }
 
 impl MainView {
-    pub fn new(mut renderer: Renderer, emulator_tx: Sender<EmulatorCommand>) -> Self {
+    pub fn new(
+        mut renderer: Renderer,
+        emulator_tx: Sender<EmulatorCommand>,
+        shared_state: SharedState,
+    ) -> Self {
         Self {
-            main_gui: MainGui::new(renderer.window.clone(), emulator_tx),
+            main_gui: MainGui::new(renderer.window.clone(), emulator_tx.clone()),
+            video_gui: VideoGui::new(),
             modifiers: Modifiers::empty(),
 
-            nes_texture: Texture::new(&mut renderer, NES_WIDTH, NES_HEIGHT, Some("nes frame")),
+            nes_texture: Texture::new(
+                &mut renderer,
+                NES_WIDTH,
+                NES_HEIGHT,
+                Some("nes frame"),
+                Bundle::current().config.shader.as_ref(),
+                Settings::current().video.video_filter,
+            ),
+            post_processor: PostProcessor::new(),
+            frame_blender: FrameBlender::new(),
             renderer,
+            emulator_tx,
+            shared_state,
+            slow_motion: false,
+            fast_forward_key_held: false,
+            fast_forwarding: false,
+            debug_paused: false,
+            last_frame: Vec::new(),
+            screenshot_requested: false,
+            menu_combo_held_since: None,
+            menu_combo_triggered: false,
+            pause_requested: false,
+            save_slot: 0,
+            title: Bundle::current().config.name.clone(),
+            min_window_size: Settings::current().video.min_window_size,
+            fps_counter: (Instant::now(), 0),
+            fps: 0,
+            render_counter: 0,
+            render_fps: 0,
+            emulator_frame: 0,
+            emulator_fps: 0,
+        }
+    }
+
+    /// Keep the window title up to date with the extra information chosen in the settings
+    fn update_title(&mut self) {
+        let name = &Bundle::current().config.name;
+        let title = match Settings::current().video.title_info {
+            TitleInfo::Nothing => None,
+            TitleInfo::RoomCode => self
+                .shared_state
+                .room_name()
+                .map(|room_name| format!("Room {room_name}")),
+            TitleInfo::Fps => Some(format!("{} FPS", self.fps)),
+        }
+        .map_or_else(|| name.clone(), |info| format!("{name} - {info}"));
+
+        if title != self.title {
+            self.renderer.window.set_title(&title);
+            self.title = title;
+        }
+    }
+
+    fn update_min_window_size(&mut self) {
+        let video_settings = &Settings::current().video;
+        if video_settings.min_window_size != self.min_window_size {
+            self.min_window_size = video_settings.min_window_size;
+            self.renderer
+                .window
+                .set_min_inner_size(Some(video_settings.min_window_size()));
+        }
+    }
+
+    /// Ask the emulator to pause while the menu is open or the window isn't focused (if enabled)
+    pub fn sync_pause(&mut self, focused: bool) {
+        let menu_pause = self.main_gui.visible() && Settings::current().emulation.pause_in_menu();
+        // Pausing one peer would desync a netplay session, and a game played with a gamepad in the background should keep running
+        let focus_pause = !focused
+            && Bundle::current().config.pause_on_focus_loss
+            && !Settings::current().input.gamepad_in_background
+            && !self.shared_state.netplay_active();
+        let pause = menu_pause || focus_pause;
+        if pause != self.pause_requested {
+            self.pause_requested = pause;
+            let _ = self.emulator_tx.send(EmulatorCommand::Pause(pause));
+        }
+    }
+
+    fn check_menu_combo(&mut self, inputs: &Inputs) {
+        let (menu_combo, hold) = {
+            let hotkeys = &Settings::current().hotkeys;
+            (
+                hotkeys.menu_combo.clone(),
+                Duration::from_millis(hotkeys.menu_combo_hold as u64),
+            )
+        };
+        if !inputs.any_gamepad_holding(&menu_combo) {
+            self.menu_combo_held_since = None;
+            self.menu_combo_triggered = false;
+            return;
+        }
+        let held_since = *self.menu_combo_held_since.get_or_insert_with(Instant::now);
+        // Only trigger once per hold so closing the menu doesn't reopen it right away
+        if !self.menu_combo_triggered && !self.main_gui.visible() && held_since.elapsed() >= hold {
+            self.menu_combo_triggered = true;
+            MainGui::open_main_menu();
+        }
+    }
+
+    /// Fast-forward while the key or the gamepad combo is held
+    fn check_fast_forward(&mut self, inputs: &Inputs) {
+        let combo_held =
+            inputs.any_gamepad_holding(&Settings::current().hotkeys.fast_forward_combo);
+        // The netplay session is in charge of the speed
+        let fast_forward = (self.fast_forward_key_held || combo_held)
+            && !self.main_gui.visible()
+            && !self.shared_state.netplay_active()
+            && Bundle::current().config.fast_forward_speed().is_some();
+        if fast_forward != self.fast_forwarding {
+            self.fast_forwarding = fast_forward;
+            self.send_speed();
         }
     }
 
+    /// Tell the emulator how fast to run, fast-forward wins over slow motion
+    fn send_speed(&self) {
+        let speed = match Bundle::current().config.fast_forward_speed() {
+            Some(speed) if self.fast_forwarding => speed,
+            _ if self.slow_motion => Settings::current().emulation.slow_motion_factor(),
+            _ => 1.0,
+        };
+        let _ = self.emulator_tx.send(EmulatorCommand::SetSpeed(speed));
+    }
+
+    fn handle_hotkey(&mut self, key_code: KeyCode, audio_gui: &mut AudioGui) -> bool {
+        if Bundle::current().config.lock_settings {
+            return false;
+        }
+        let Some(hotkey) = Self::hotkey_for(key_code) else {
+            return false;
+        };
+        match hotkey {
+            Hotkey::SlowMotion => self.toggle_slow_motion(),
+            Hotkey::SpeedIndicator => {
+                let emulation = &mut Settings::current_mut().emulation;
+                emulation.show_speed = !emulation.show_speed;
+            }
+            Hotkey::StateHash => {
+                let emulation = &mut Settings::current_mut().emulation;
+                emulation.show_state_hash = !emulation.show_state_hash;
+            }
+            Hotkey::Stats => {
+                let emulation = &mut Settings::current_mut().emulation;
+                emulation.show_stats = !emulation.show_stats;
+            }
+            Hotkey::Performance => {
+                let emulation = &mut Settings::current_mut().emulation;
+                emulation.show_performance = !emulation.show_performance;
+            }
+            Hotkey::SaveState => {
+                let _ = self
+                    .emulator_tx
+                    .send(EmulatorCommand::SaveState(self.save_slot));
+            }
+            Hotkey::LoadState => {
+                let _ = self
+                    .emulator_tx
+                    .send(EmulatorCommand::LoadState(self.save_slot));
+            }
+            Hotkey::SaveSlot(slot) => {
+                let _ = self.emulator_tx.send(EmulatorCommand::SaveState(slot));
+            }
+            Hotkey::LoadSlot(slot) => {
+                let _ = self.emulator_tx.send(EmulatorCommand::LoadState(slot));
+            }
+            Hotkey::NextSaveSlot => self.select_save_slot(self.save_slot + 1),
+            Hotkey::PreviousSaveSlot => self.select_save_slot(self.save_slot + SAVE_SLOTS - 1),
+            Hotkey::Rewind => self.rewind(true),
+            Hotkey::FastForward => self.fast_forward_key_held = true,
+            Hotkey::Screenshot => self.screenshot_requested = true,
+            Hotkey::Pause => self.set_debug_paused(!self.debug_paused),
+            Hotkey::StepFrame => {
+                self.set_debug_paused(true);
+                if self.debug_paused {
+                    let _ = self.emulator_tx.send(EmulatorCommand::StepFrame);
+                }
+            }
+            Hotkey::VolumeDown => audio_gui.volume_down(),
+            Hotkey::VolumeUp => audio_gui.volume_up(),
+            Hotkey::Mute => audio_gui.toggle_mute(),
+        }
+        true
+    }
+
+    /// The hotkey bound to `key_code`, unless the key is also bound to a joypad
+    fn hotkey_for(key_code: KeyCode) -> Option<Hotkey> {
+        let settings = Settings::current();
+        if settings.input.is_joypad_key(&key_code) {
+            return None;
+        }
+        settings.hotkeys.hotkey_for(key_code)
+    }
+
+    fn set_debug_paused(&mut self, paused: bool) {
+        if paused == self.debug_paused {
+            return;
+        }
+        if paused && self.shared_state.netplay_active() {
+            MainGui::show_toast("Pausing is not available during netplay");
+            return;
+        }
+        self.debug_paused = paused;
+        let _ = self.emulator_tx.send(EmulatorCommand::DebugPause(paused));
+        MainGui::show_toast(if paused { "Paused" } else { "Resumed" });
+    }
+
+    fn rewind(&mut self, rewind: bool) {
+        if rewind && self.shared_state.netplay_active() {
+            MainGui::show_toast("Rewind is not available during netplay");
+            return;
+        }
+        if rewind && !Settings::current().emulation.rewind {
+            return;
+        }
+        let _ = self.emulator_tx.send(EmulatorCommand::Rewind(rewind));
+    }
+
+    /// Select a save state slot, wrapping around at the ends
+    fn select_save_slot(&mut self, slot: u8) {
+        self.save_slot = slot % SAVE_SLOTS;
+        MainGui::show_toast(format!("Save slot {}", self.save_slot + 1));
+    }
+
+    fn toggle_slow_motion(&mut self) {
+        if self.shared_state.netplay_active() {
+            MainGui::show_toast("Slow motion is not available during netplay");
+            return;
+        }
+        self.slow_motion = !self.slow_motion;
+        let speed = if self.slow_motion {
+            Settings::current().emulation.slow_motion_factor()
+        } else {
+            1.0
+        };
+        self.send_speed();
+        MainGui::show_toast(format!("Speed {speed:.2}x"));
+    }
+
     pub fn handle_window_event(
         &mut self,
         window_event: &winit::event::WindowEvent,
@@ -80,8 +365,15 @@ impl MainView {
         inputs_gui: &mut InputsGui,
         emulator_gui: &mut EmulatorGui,
     ) {
-        if let winit::event::WindowEvent::Resized(physical_size) = window_event {
-            self.renderer.resize(*physical_size);
+        match window_event {
+            winit::event::WindowEvent::Resized(physical_size) => {
+                self.renderer.resize(*physical_size);
+            }
+            winit::event::WindowEvent::KeyboardInput { .. }
+            | winit::event::WindowEvent::MouseInput { .. } => {
+                self.main_gui.gamepad_navigation = false;
+            }
+            _ => {}
         }
 
         if !self
@@ -110,27 +402,35 @@ impl MainView {
                 self.modifiers = *modifiers;
                 false
             }
-            Keyboard(KeyEvent::Pressed(key_code)) => self
-                .renderer
-                .window
-                .check_and_set_fullscreen(self.modifiers, *key_code),
+            Keyboard(KeyEvent::Pressed(key_code)) => {
+                self.renderer
+                    .window
+                    .check_and_set_fullscreen(self.modifiers, *key_code)
+                    || self.handle_hotkey(*key_code, audio_gui)
+            }
+            Keyboard(KeyEvent::Released(key_code)) => {
+                let hotkey = Self::hotkey_for(*key_code);
+                match hotkey {
+                    Some(Hotkey::Rewind) => self.rewind(false),
+                    Some(Hotkey::FastForward) => self.fast_forward_key_held = false,
+                    _ => {}
+                }
+                matches!(hotkey, Some(Hotkey::Rewind | Hotkey::FastForward))
+            }
             _ => {
                 if let GuiEvent::Gamepad(gamepad_event) = gui_event {
                     if let Some(event) = to_egui_event(gamepad_event) {
                         if self.main_gui.visible() {
                             // If the gui is visible convert gamepad events to fake input events so we can control the ui with the gamepad
+                            self.main_gui.gamepad_navigation = true;
                             self.renderer.egui.state.egui_input_mut().events.push(event)
                         } else {
-                            // If the gui is not visible pass on only the guide button
+                            // If the gui is not visible pass on only the menu button
+                            let menu_button = Settings::current().hotkeys.menu_button;
                             if matches!(
                                 gamepad_event,
-                                GamepadEvent::ButtonDown {
-                                    button: GamepadButton::Guide,
-                                    ..
-                                } | GamepadEvent::ButtonUp {
-                                    button: GamepadButton::Guide,
-                                    ..
-                                }
+                                GamepadEvent::ButtonDown { button, .. }
+                                | GamepadEvent::ButtonUp { button, .. } if *button == menu_button
                             ) {
                                 self.renderer.egui.state.egui_input_mut().events.push(event)
                             }
@@ -142,13 +442,71 @@ impl MainView {
             }
         };
         if !consumed {
-            self.main_gui
-                .handle_event(gui_event, audio_gui, inputs_gui, emulator_gui);
+            self.main_gui.handle_event(
+                gui_event,
+                audio_gui,
+                inputs_gui,
+                emulator_gui,
+                &mut self.video_gui,
+            );
         }
     }
 
     pub const MENU_TINT: Color32 = Color32::from_rgb(50, 50, 50);
 
+    /// One row per player with the pressed buttons lit up
+    fn input_overlay_ui(ui: &mut egui::Ui, joypads: [JoypadState; MAX_PLAYERS]) {
+        use crate::input::JoypadButton::*;
+        egui::Grid::new("input-overlay-grid")
+            .num_columns(9)
+            .spacing([6.0, 2.0])
+            .show(ui, |ui| {
+                let players = Bundle::current().config.players();
+                for (player, joypad) in joypads.iter().enumerate().take(players) {
+                    ui.label(
+                        egui::RichText::new(format!("P{}", player + 1))
+                            .monospace()
+                            .color(Color32::WHITE),
+                    );
+                    for button in [Left, Up, Down, Right, Select, Start, B, A] {
+                        let text = egui::RichText::new(button.to_string()).monospace();
+                        ui.label(if joypad.is_pressed(button) {
+                            text.color(Color32::WHITE)
+                        } else {
+                            text.color(Color32::DARK_GRAY)
+                        });
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    /// Draws the NES frame at the largest integer scale that fits, in physical pixels and with square pixels,
+    /// centered and snapped to the pixel grid so that nothing shimmers when scrolling.
+    /// Returns the size it was drawn at, which can be larger than the area when the frame is cropped.
+    fn pixel_perfect_ui(ui: &mut egui::Ui, nes_texture_id: egui::TextureId, tinted: bool) -> Vec2 {
+        let pixels_per_point = ui.ctx().pixels_per_point();
+        let area = ui.max_rect();
+        let physical_size = area.size() * pixels_per_point;
+        let fit = (physical_size.x / NES_WIDTH as f32).min(physical_size.y / NES_HEIGHT as f32);
+        // Never below 1x, in a window smaller than the NES resolution the edges are cropped instead
+        let scale = fit.floor().max(1.0);
+        let size = Vec2::new(NES_WIDTH as f32, NES_HEIGHT as f32) * scale / pixels_per_point;
+        let min = ((area.center() - size / 2.0) * pixels_per_point).round() / pixels_per_point;
+
+        ui.painter_at(area).image(
+            nes_texture_id,
+            egui::Rect::from_min_size(min, size),
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            if tinted {
+                Self::MENU_TINT
+            } else {
+                Color32::WHITE
+            },
+        );
+        size
+    }
+
     pub fn render(
         &mut self,
         frame_buffer: &VideoBufferPool,
@@ -156,12 +514,95 @@ impl MainView {
         inputs_gui: &mut InputsGui,
         emulator_gui: &mut EmulatorGui,
     ) {
+        self.check_menu_combo(&inputs_gui.inputs);
+        self.check_fast_forward(&inputs_gui.inputs);
+
+        let video_settings = Settings::current().video.clone();
+        if self
+            .nes_texture
+            .set_video_filter(&mut self.renderer, video_settings.video_filter)
+            && !self.last_frame.is_empty()
+        {
+            self.nes_texture.update(&self.renderer, &self.last_frame);
+        }
         if let Some(nes_frame) = &frame_buffer.pop_ref() {
-            self.nes_texture.update(&self.renderer.queue, nes_frame);
+            self.fps_counter.1 += 1;
+            let frame = self.post_processor.process(nes_frame, &video_settings);
+            self.last_frame.clear();
+            self.last_frame.extend_from_slice(frame);
+            if video_settings.frame_blending {
+                self.frame_blender.push(frame);
+            } else {
+                self.nes_texture.update(&self.renderer, frame);
+            }
+        }
+
+        if std::mem::take(&mut self.screenshot_requested) && !self.last_frame.is_empty() {
+            save_screenshot(
+                self.last_frame.clone(),
+                video_settings.screenshot_aspect_corrected,
+            );
+        }
+
+        self.render_counter += 1;
+        if self.fps_counter.0.elapsed() >= Duration::from_secs(1) {
+            self.fps = self.fps_counter.1;
+            self.fps_counter = (Instant::now(), 0);
+            self.render_fps = std::mem::take(&mut self.render_counter);
+            // The frame counter starts over when the game is restarted
+            let emulator_frame = self.shared_state.frame();
+            self.emulator_fps = emulator_frame.saturating_sub(self.emulator_frame);
+            self.emulator_frame = emulator_frame;
+        }
+        self.update_title();
+        self.update_min_window_size();
+
+        let speed = self.shared_state.speed();
+        if video_settings.frame_blending {
+            let fps = Settings::current().nes_region().to_fps() * speed;
+            let frame = self
+                .frame_blender
+                .blend(Duration::from_secs_f32(1.0 / fps.max(1.0)));
+            self.nes_texture.update(&self.renderer, frame);
+        } else {
+            self.frame_blender.reset();
         }
 
+        let pixel_perfect = video_settings.integer_scaling(self.renderer.window.is_fullscreen());
         let nes_texture_id = self.nes_texture.get_id();
         let main_gui = &mut self.main_gui;
+        let video_gui = &mut self.video_gui;
+        let fps_override = Settings::current()
+            .emulation
+            .fps_override()
+            .filter(|_| !self.shared_state.netplay_active());
+        let show_speed = fps_override.is_some()
+            || Settings::current().emulation.show_speed && (speed - 1.0).abs() > 0.001;
+        let state_hash = self.shared_state.state_hash();
+        let recording = self.shared_state.recording();
+        let joypads = self.shared_state.joypads();
+        let stats = Settings::current().emulation.show_stats.then(|| {
+            let uptime = self.shared_state.uptime().as_secs();
+            format!(
+                "Frame {}\nUptime {}:{:02}:{:02}\nResets {}",
+                self.shared_state.frame(),
+                uptime / 3600,
+                uptime / 60 % 60,
+                uptime % 60,
+                self.shared_state.resets()
+            )
+        });
+        let performance = Settings::current().emulation.show_performance.then(|| {
+            format!(
+                "Emulator {} FPS\nRender {} FPS\nAudio buffer {:.0}%",
+                self.emulator_fps,
+                self.render_fps,
+                audio_gui.audio.stream.buffer_fill() * 100.0
+            )
+        });
+        // Where the frame ends up on the screen, in pixels
+        let mut nes_image_size = None;
+        let nes_image_size_out = &mut nes_image_size;
         let render_result = self.renderer.render(move |ctx| {
             #[cfg(feature = "debug")]
             puffin::profile_scope!("ui");
@@ -172,6 +613,12 @@ impl MainView {
                 egui::CentralPanel::default()
                     .frame(egui::Frame::none().fill(egui::Color32::BLACK))
                     .show(ctx, |ui| {
+                        if pixel_perfect {
+                            let size =
+                                Self::pixel_perfect_ui(ui, nes_texture_id, main_gui.visible());
+                            *nes_image_size_out = Some(size * ctx.pixels_per_point());
+                            return;
+                        }
                         let available_size = ui.available_size();
                         let new_size = if available_size.x
                             < MINIMUM_INTEGER_SCALING_SIZE.width as f32
@@ -196,14 +643,14 @@ impl MainView {
                             )
                         };
 
+                        let new_size = Vec2 {
+                            x: new_size.width as f32,
+                            y: new_size.height as f32,
+                        };
+                        *nes_image_size_out = Some(new_size * ctx.pixels_per_point());
                         ui.centered_and_justified(|ui| {
-                            let mut nes_image = Image::from_texture(SizedTexture::new(
-                                nes_texture_id,
-                                Vec2 {
-                                    x: new_size.width as f32,
-                                    y: new_size.height as f32,
-                                },
-                            ));
+                            let mut nes_image =
+                                Image::from_texture(SizedTexture::new(nes_texture_id, new_size));
                             if main_gui.visible() {
                                 nes_image = nes_image.tint(Self::MENU_TINT);
                             }
@@ -211,9 +658,99 @@ impl MainView {
                         });
                     });
             }
-            main_gui.ui(ctx, audio_gui, inputs_gui, emulator_gui);
+            main_gui.ui(
+                ctx,
+                audio_gui,
+                inputs_gui,
+                emulator_gui,
+                video_gui,
+                recording,
+            );
+
+            if show_speed {
+                egui::Area::new(egui::Id::new("speed-indicator"))
+                    .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+                    .interactable(false)
+                    .show(ctx, |ui| {
+                        let text = match fps_override {
+                            Some(fps) => format!("{fps} FPS"),
+                            None => format!("{}x", (speed * 100.0).round() / 100.0),
+                        };
+                        ui.label(
+                            egui::RichText::new(text)
+                                .monospace()
+                                .size(20.0)
+                                .color(Color32::WHITE)
+                                .background_color(Color32::from_black_alpha(160)),
+                        );
+                    });
+            }
+
+            if let Some((frame, hash)) = state_hash {
+                egui::Area::new(egui::Id::new("state-hash"))
+                    .anchor(egui::Align2::LEFT_BOTTOM, [10.0, -10.0])
+                    .interactable(false)
+                    .show(ctx, |ui| {
+                        ui.label(
+                            egui::RichText::new(format!("#{frame} {hash:016X}"))
+                                .monospace()
+                                .size(16.0)
+                                .color(Color32::WHITE)
+                                .background_color(Color32::from_black_alpha(160)),
+                        );
+                    });
+            }
+
+            if let Some(joypads) = joypads {
+                egui::Area::new(egui::Id::new("input-overlay"))
+                    .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -10.0])
+                    .interactable(false)
+                    .show(ctx, |ui| {
+                        egui::Frame::none()
+                            .fill(Color32::from_black_alpha(160))
+                            .inner_margin(4.0)
+                            .show(ui, |ui| Self::input_overlay_ui(ui, joypads));
+                    });
+            }
+
+            if let Some(stats) = stats {
+                egui::Area::new(egui::Id::new("stats"))
+                    .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+                    .interactable(false)
+                    .show(ctx, |ui| {
+                        ui.label(
+                            egui::RichText::new(stats)
+                                .monospace()
+                                .size(16.0)
+                                .color(Color32::WHITE)
+                                .background_color(Color32::from_black_alpha(160)),
+                        );
+                    });
+            }
+
+            if let Some(performance) = performance {
+                egui::Area::new(egui::Id::new("performance"))
+                    .anchor(egui::Align2::RIGHT_TOP, [-10.0, 40.0])
+                    .interactable(false)
+                    .show(ctx, |ui| {
+                        ui.label(
+                            egui::RichText::new(performance)
+                                .monospace()
+                                .size(16.0)
+                                .color(Color32::WHITE)
+                                .background_color(Color32::from_black_alpha(160)),
+                        );
+                    });
+            }
         });
 
+        if let Some(size) = nes_image_size {
+            self.nes_texture.fit_output(
+                &mut self.renderer,
+                size.x.round() as u32,
+                size.y.round() as u32,
+            );
+        }
         match render_result {
             Ok(_) => {}
             Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
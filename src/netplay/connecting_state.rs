@@ -10,9 +10,11 @@ use std::time::{Duration, Instant};
 
 use crate::bundle::Bundle;
 use crate::netplay::netplay_state::get_netplay_id;
-use crate::settings::{Settings, MAX_PLAYERS};
+use crate::settings::Settings;
 
 use super::netplay_session::{GGRSConfig, NetplaySessionState};
+use super::spectating::SpectatingState;
+use super::{JoypadMapping, MAX_NETPLAY_PLAYERS};
 
 use super::NetplayNesState;
 
@@ -30,6 +32,18 @@ pub struct StaticNetplayServerConfiguration {
     pub unlock_url: Option<String>,
 }
 
+impl StaticNetplayServerConfiguration {
+    #[cfg(feature = "debug")]
+    pub fn matchbox_server(&self) -> &str {
+        &self.matchbox.server
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn ice_urls(&self) -> &[String] {
+        &self.matchbox.ice.urls
+    }
+}
+
 pub enum ConnectingState {
     LoadingNetplayServerConfiguration(LoadingNetplayServerConfigurationState),
     PeeringUp(PeeringState),
@@ -37,6 +51,8 @@ pub enum ConnectingState {
 
     //TODO: Get rid of this state?
     Connected(NetplaySessionState),
+    /// Watching a game through the inputs the players relay
+    Spectating(SpectatingState),
 
     Retrying(RetryingState),
     Failed(String),
@@ -50,6 +66,7 @@ impl ConnectingState {
     pub fn resume(
         game_state: NetplayNesState,
         session_id: String,
+        input_delay: Option<u8>,
         netplay_server_configuration: StaticNetplayServerConfiguration,
     ) -> Self {
         Self::PeeringUp(PeeringState::new(
@@ -57,6 +74,8 @@ impl ConnectingState {
             StartMethod::Resume(StartState {
                 game_state,
                 session_id,
+                seed: None,
+                input_delay,
             }),
         ))
     }
@@ -156,99 +175,152 @@ pub struct PeeringState {
     pub socket: WebRtcSocket,
     netplay_server_configuration: StaticNetplayServerConfiguration,
     pub start_method: StartMethod,
+    /// Reports why the socket stopped, if it stops before we are done peering
+    socket_result: Receiver<String>,
+    /// When we started waiting for the other peer, to be able to give up on a quiet public pool
+    pub waiting_since: Instant,
 }
 impl PeeringState {
     pub fn new(
         netplay_server_configuration: StaticNetplayServerConfiguration,
         start_method: StartMethod,
     ) -> Self {
-        let matchbox_server = &netplay_server_configuration.matchbox.server;
-
         //TODO: matchbox will panic when we advance the frame on the ggrs session if we do not pass `players=2` here. See discussion (https://discord.com/channels/844211600009199626/1045611882691698688/1325596000928399495) for details.
         //      revert this when the bug is fixed in matchbox.
+        // Players only meet others that picked the same input delay
+        let input_delay = start_method
+            .input_delay()
+            .map(|input_delay| format!("_d{input_delay}"))
+            .unwrap_or_default();
         let room_name = match &start_method {
             StartMethod::Start(StartState { session_id, .. }, ..) => {
-                format!("join_{}?next=2", session_id)
+                format!("join_{}{input_delay}?next=2", session_id)
             }
             StartMethod::Resume(StartState {
                 session_id,
                 game_state,
                 ..
             }) => {
-                format!("resume_{}_{}?next=2", session_id, game_state.frame)
+                format!(
+                    "resume_{}_{}{input_delay}?next=2",
+                    session_id, game_state.frame
+                )
             }
             StartMethod::MatchWithRandom(StartState { session_id, .. }) => {
-                format!("random_{}?next=2", session_id)
-            }
-        };
-
-        let (username, password) = match &netplay_server_configuration.matchbox.ice.credentials {
-            IceCredentials::Password(IcePasswordCredentials { username, password }) => {
-                (Some(username.to_string()), Some(password.to_string()))
+                format!("random_{}{input_delay}?next=2", session_id)
             }
-            IceCredentials::None => (None, None),
+            // Spectators don't take part in the GGRS session, the players relay the inputs to a room of their own
+            StartMethod::Spectate(StartState { session_id, .. }, _) => watch_room(session_id),
         };
-
-        let (socket, loop_fut) = {
-            let room_url = format!("ws://{matchbox_server}/{room_name}");
-            let ice_server = RtcIceServerConfig {
-                urls: netplay_server_configuration.matchbox.ice.urls.clone(),
-                username,
-                credential: password,
-            };
-            log::debug!(
-                "Peering up through WebRTC socket: room_url={:?}, ice_server={:?}",
-                room_url,
-                ice_server
-            );
-            WebRtcSocketBuilder::new(room_url)
-                .ice_server(ice_server)
-                .add_channel(ChannelConfig::unreliable())
-                .build()
+        let channels = match &start_method {
+            StartMethod::Spectate(..) => vec![ChannelConfig::reliable()],
+            // The host of a private game tells the other player who is player 1 on the second channel
+            StartMethod::Start(..) => vec![ChannelConfig::unreliable(), ChannelConfig::reliable()],
+            _ => vec![ChannelConfig::unreliable()],
         };
-
-        let loop_fut = loop_fut.fuse();
-        let timeout = Delay::new(Duration::from_millis(100));
-
-        tokio::spawn(async move {
-            futures::pin_mut!(loop_fut, timeout);
-            loop {
-                select! {
-                    _ = (&mut timeout).fuse() => {
-                        timeout.reset(Duration::from_millis(100));
-                    }
-
-                    _ = &mut loop_fut => {
-                        break;
-                    }
-                }
-            }
-        });
+        let (socket, socket_result) =
+            open_socket(&netplay_server_configuration, &room_name, channels);
 
         Self {
             socket,
             netplay_server_configuration,
             start_method,
+            socket_result,
+            waiting_since: Instant::now(),
         }
     }
 
+    /// True if we have been looking for a public game for longer than the bundle allows
+    pub fn find_game_timed_out(&self) -> bool {
+        matches!(self.start_method, StartMethod::MatchWithRandom(_))
+            && Bundle::current()
+                .config
+                .netplay
+                .find_game_timeout()
+                .is_some_and(|timeout| self.waiting_since.elapsed() > timeout)
+    }
+
     fn advance(mut self) -> ConnectingState {
+        if let StartMethod::Spectate(start_state, room_name) = self.start_method {
+            // Waiting for the players happens while spectating, they may not have started yet
+            return ConnectingState::Spectating(SpectatingState::new(
+                self.socket,
+                self.socket_result,
+                start_state,
+                room_name,
+            ));
+        }
+        if let Ok(Some(reason)) = self.socket_result.try_recv() {
+            let tried = self.netplay_server_configuration.matchbox.ice.ice_urls();
+            log::error!(
+                "Failed to peer up: {reason}, tried ICE servers: {}",
+                tried.join(", ")
+            );
+            return ConnectingState::Failed(format!(
+                "Could not connect ({reason}).\nTried: {}",
+                tried.join(", ")
+            ));
+        }
+
         let socket = &mut self.socket;
         socket.update_peers();
 
         let connected_peers = socket.connected_peers().count();
-        if connected_peers >= MAX_PLAYERS {
+        if connected_peers >= MAX_NETPLAY_PLAYERS {
             return ConnectingState::Failed("Room is full".to_string());
         }
 
-        let remaining = MAX_PLAYERS - (connected_peers + 1);
+        let remaining = MAX_NETPLAY_PLAYERS - (connected_peers + 1);
         if remaining == 0 {
+            if let StartMethod::Start(start_state, _, join_or_host) = &mut self.start_method {
+                // The host picks who is player 1, the joining player waits to hear it
+                match join_or_host {
+                    JoinOrHost::Host => {
+                        let joypad_mapping = start_state
+                            .game_state
+                            .joypad_mapping
+                            .get_or_insert(JoypadMapping::P1)
+                            .clone();
+                        let peers: Vec<_> = socket.connected_peers().collect();
+                        for peer in peers {
+                            socket
+                                .channel_mut(PLAYERS_CHANNEL)
+                                .send(Box::new([joypad_mapping.player()]), peer);
+                        }
+                    }
+                    JoinOrHost::Join => {
+                        let host_mapping = socket
+                            .channel_mut(PLAYERS_CHANNEL)
+                            .receive()
+                            .into_iter()
+                            .find_map(|(_, packet)| JoypadMapping::from_player(*packet.first()?));
+                        match host_mapping {
+                            Some(host_mapping) => {
+                                start_state.game_state.joypad_mapping = Some(host_mapping.other())
+                            }
+                            None => return ConnectingState::PeeringUp(self),
+                        }
+                    }
+                }
+            }
             log::debug!("Got all players! Synchonizing...");
+            if let StartMethod::MatchWithRandom(start_state) = &mut self.start_method {
+                // A random match starts from a fresh power-on, agree on a seed so the initial state is identical
+                if let Some(seed) = shared_seed(socket) {
+                    log::info!("Random match seed: {seed:x}");
+                    start_state.game_state.seed_ram(seed);
+                    start_state.seed = Some(seed);
+                }
+            }
             let players = socket.players();
             let ggrs_config = self.netplay_server_configuration.ggrs.clone();
             let mut sess_build = SessionBuilder::<GGRSConfig>::new()
-                .with_num_players(MAX_PLAYERS)
-                .with_input_delay(ggrs_config.input_delay)
+                .with_num_players(MAX_NETPLAY_PLAYERS)
+                .with_input_delay(
+                    self.start_method
+                        .input_delay()
+                        .map_or(ggrs_config.input_delay, usize::from),
+                )
                 .with_fps(Settings::current_mut().get_nes_region().to_fps() as usize)
                 .unwrap()
                 .with_max_prediction_window(ggrs_config.max_prediction);
@@ -272,6 +344,78 @@ impl PeeringState {
     }
 }
 
+/// The channel the host of a private game uses to tell the other player who is player 1
+const PLAYERS_CHANNEL: usize = 1;
+
+/// The room the players relay their inputs to for spectators of `session_id`
+pub fn watch_room(session_id: &str) -> String {
+    format!("watch_{session_id}")
+}
+
+/// Open a WebRTC socket to `room_name` with `channels`. The receiver gets the reason the socket stopped, if it stops.
+pub fn open_socket(
+    netplay_server_configuration: &StaticNetplayServerConfiguration,
+    room_name: &str,
+    channels: Vec<ChannelConfig>,
+) -> (WebRtcSocket, Receiver<String>) {
+    let matchbox_server = &netplay_server_configuration.matchbox.server;
+    let (username, password) = match &netplay_server_configuration.matchbox.ice.credentials {
+        IceCredentials::Password(IcePasswordCredentials { username, password }) => {
+            (Some(username.to_string()), Some(password.to_string()))
+        }
+        IceCredentials::None => (None, None),
+    };
+
+    let (socket, loop_fut) = {
+        let room_url = format!("ws://{matchbox_server}/{room_name}");
+        // All urls go into the same ICE server config so WebRTC gathers candidates from all of them
+        // and picks whichever works, a dead TURN server is then just skipped.
+        let ice_server = RtcIceServerConfig {
+            urls: netplay_server_configuration.matchbox.ice.ice_urls(),
+            username,
+            credential: password,
+        };
+        log::debug!(
+            "Peering up through WebRTC socket: room_url={:?}, ice_server={:?}",
+            room_url,
+            ice_server
+        );
+        channels
+            .into_iter()
+            .fold(
+                WebRtcSocketBuilder::new(room_url).ice_server(ice_server),
+                WebRtcSocketBuilder::add_channel,
+            )
+            .build()
+    };
+
+    let loop_fut = loop_fut.fuse();
+    let timeout = Delay::new(Duration::from_millis(100));
+    let (sender, socket_result) = futures::channel::oneshot::channel::<String>();
+
+    tokio::spawn(async move {
+        futures::pin_mut!(loop_fut, timeout);
+        loop {
+            select! {
+                _ = (&mut timeout).fuse() => {
+                    timeout.reset(Duration::from_millis(100));
+                }
+
+                res = &mut loop_fut => {
+                    let reason = match res {
+                        Ok(()) => "Connection closed".to_string(),
+                        Err(e) => format!("{e}"),
+                    };
+                    // Nobody is listening anymore if the socket was dropped
+                    let _ = sender.send(reason);
+                    break;
+                }
+            }
+        }
+    });
+    (socket, socket_result)
+}
+
 pub struct SynchonizingState {
     p2p_session: P2PSession<GGRSConfig>,
     pub start_time: Instant,
@@ -320,22 +464,68 @@ pub enum StartMethod {
     Start(StartState, RoomName, JoinOrHost),
     Resume(StartState),
     MatchWithRandom(StartState),
+    /// Watch a private game without playing
+    Spectate(StartState, RoomName),
 }
 
 #[derive(Clone)]
 pub struct StartState {
     pub game_state: NetplayNesState,
     pub session_id: String,
+    /// The seed both peers agreed on for the initial state (only for random matches)
+    pub seed: Option<u64>,
+    /// The input delay picked by the player, the one from the server configuration is used if not set
+    pub input_delay: Option<u8>,
 }
 
 impl Debug for StartState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("StartState")
             .field("session_id", &self.session_id)
+            .field("seed", &self.seed)
+            .field("input_delay", &self.input_delay)
             .finish()
     }
 }
 
+impl StartMethod {
+    pub fn seed(&self) -> Option<u64> {
+        match self {
+            StartMethod::Start(start_state, ..)
+            | StartMethod::Resume(start_state)
+            | StartMethod::MatchWithRandom(start_state)
+            | StartMethod::Spectate(start_state, _) => start_state.seed,
+        }
+    }
+
+    pub fn input_delay(&self) -> Option<u8> {
+        match self {
+            StartMethod::Start(start_state, ..)
+            | StartMethod::Resume(start_state)
+            | StartMethod::MatchWithRandom(start_state)
+            | StartMethod::Spectate(start_state, _) => start_state.input_delay,
+        }
+    }
+}
+
+/// A seed derived from the ids of all peers, so every peer ends up with the same one without exchanging anything
+fn shared_seed(socket: &mut WebRtcSocket) -> Option<u64> {
+    let own_id = socket.id()?;
+    let mut peer_ids: Vec<String> = socket
+        .connected_peers()
+        .chain(Some(own_id))
+        .map(|peer_id| peer_id.0.to_string())
+        .collect();
+    if peer_ids.len() < MAX_NETPLAY_PLAYERS {
+        return None;
+    }
+    peer_ids.sort();
+    let digest = md5::compute(peer_ids.join(","));
+    Some(u64::from_be_bytes(
+        digest.0[..8].try_into().expect("8 bytes"),
+    ))
+}
+
 const RETRY_COOLDOWN: Duration = Duration::from_secs(5);
 const MAX_RETRY_ATTEMPTS: u16 = 3;
 
@@ -444,6 +634,19 @@ pub struct IceConfiguration {
     credentials: IceCredentials,
 }
 
+impl IceConfiguration {
+    /// The configured urls in order, without blanks and duplicates
+    fn ice_urls(&self) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        for url in self.urls.iter().map(|url| url.trim()) {
+            if !url.is_empty() && !urls.iter().any(|u| u == url) {
+                urls.push(url.to_string());
+            }
+        }
+        urls
+    }
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub enum IceCredentials {
     None,
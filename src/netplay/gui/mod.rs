@@ -10,13 +10,17 @@ use crate::{
     main_view::gui::{MainGui, MainMenuState},
     netplay::{
         connecting_state::{LoadingNetplayServerConfigurationState, PeeringState, StartMethod},
-        netplay_state::MAX_ROOM_NAME_LEN,
+        invite_link,
+        netplay_state::{valid_room_name, MAX_INPUT_DELAY, MAX_ROOM_NAME_LEN},
+        JoypadMapping,
     },
+    settings::Settings,
 };
 
 use super::{
     connecting_state::SynchonizingState,
     netplay_state::{ConnectedState, Netplay, NetplayState},
+    spectating::SpectatingState,
     ConnectingState, NetplayStateHandler,
 };
 #[cfg(feature = "debug")]
@@ -36,18 +40,35 @@ impl Default for NetplayVoca {
 
 pub struct NetplayGui {
     room_name: Option<String>,
+    /// The room name is entered to watch the game instead of joining it
+    watching: bool,
+    clipboard_room_name: Option<String>,
     last_screen: Option<&'static str>,
+    #[cfg(feature = "debug")]
+    server_test: super::server_test::ServerTest,
 }
 
 impl NetplayGui {
     pub fn new() -> Self {
         Self {
             room_name: None,
+            watching: false,
+            clipboard_room_name: None,
             last_screen: None,
+            #[cfg(feature = "debug")]
+            server_test: Default::default(),
         }
     }
 }
 
+/// A room name from the clipboard, if there is something that looks like one
+fn clipboard_room_name() -> Option<String> {
+    let text = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .ok()?;
+    valid_room_name(&text)
+}
+
 fn ui_text_small(text: impl Into<String>, color: Color32) -> RichText {
     RichText::new(text)
         .color(color)
@@ -81,7 +102,23 @@ impl NetplayGui {
             match &netplay_state_handler.netplay {
                 // Connecting is a modal state, you can't see any messages when in the netplay UI anyway
                 Some(NetplayState::Connecting(_)) => None,
-                Some(NetplayState::Resuming(_)) => Some("Trying to reconnect...".to_string()),
+                Some(NetplayState::Resuming(netplay)) => Some(match netplay.state.remaining() {
+                    Some(remaining) => format!("Reconnecting... {}s", remaining.as_secs()),
+                    None => "Trying to reconnect...".to_string(),
+                }),
+                Some(NetplayState::Reviewing(netplay)) if netplay.state.paused => {
+                    Some("Reviewing the game (paused)".to_string())
+                }
+                Some(NetplayState::Spectating(netplay)) => match &netplay.state {
+                    SpectatingState {
+                        ended: Some(reason),
+                        ..
+                    } => Some(reason.clone()),
+                    SpectatingState {
+                        started_at: None, ..
+                    } => Some("Waiting for the game to start...".to_string()),
+                    _ => None,
+                },
                 _ => None,
             }
             .iter()
@@ -104,7 +141,11 @@ impl NetplayGui {
 
             ui.vertical_centered(|ui| {
                 Label::new(MenuButton::ui_text(
-                    "JOIN PRIVATE GAME",
+                    if self.watching {
+                        "WATCH GAME"
+                    } else {
+                        "JOIN PRIVATE GAME"
+                    },
                     MenuButton::ACTIVE_COLOR,
                 ))
                 .selectable(false)
@@ -133,12 +174,24 @@ impl NetplayGui {
                     if ui
                         .add_enabled(
                             !room_name.is_empty(),
-                            Button::new(RichText::new("Join").font(FontId::proportional(30.0))),
+                            Button::new(
+                                RichText::new(if self.watching { "Watch" } else { "Join" })
+                                    .font(FontId::proportional(30.0)),
+                            ),
                         )
                         .clicked()
                     {
                         action = Some(Action::Join(room_name.clone()));
                     }
+                    if let Some(clipboard_room_name) = &self.clipboard_room_name {
+                        if room_name != clipboard_room_name
+                            && ui_button(&format!("Paste {clipboard_room_name}"))
+                                .ui(ui)
+                                .clicked()
+                        {
+                            room_name.clone_from(clipboard_room_name);
+                        }
+                    }
                     if !self.last_screen.eq(&Some("JOIN")) {
                         re.request_focus();
                     }
@@ -176,6 +229,11 @@ impl NetplayGui {
             if let Some(action) = action {
                 self.room_name = None;
                 match action {
+                    Action::Join(room_name) if self.watching => {
+                        return netplay_disconnected
+                            .watch_game(&room_name)
+                            .expect("to be able to watch a game");
+                    }
                     Action::Join(room_name) => {
                         return netplay_disconnected
                             .join_game(&room_name)
@@ -188,6 +246,7 @@ impl NetplayGui {
                 Find,
                 Join,
                 Host,
+                Watch,
             }
 
             let mut action = None;
@@ -212,6 +271,21 @@ impl NetplayGui {
                 }
             });
             ui.end_row();
+
+            ui.vertical_centered(|ui| {
+                if MenuButton::new("WATCH GAME").ui(ui).clicked() {
+                    action = Some(Action::Watch);
+                }
+            });
+            ui.end_row();
+
+            ui.vertical_centered(|ui| Self::input_delay_ui(ui));
+            ui.end_row();
+            #[cfg(feature = "debug")]
+            {
+                self.server_test.ui(ui);
+                ui.end_row();
+            }
             ui.vertical_centered(|ui| {
                 if ui_button("Close").ui(ui).clicked() || esc_pressed(ui.ctx()) {
                     self.room_name = None;
@@ -228,7 +302,11 @@ impl NetplayGui {
                             .find_game()
                             .expect("to be able to find a game");
                     }
-                    Action::Join => self.room_name = Some(String::new()),
+                    Action::Join | Action::Watch => {
+                        self.watching = matches!(action, Action::Watch);
+                        self.room_name = Some(String::new());
+                        self.clipboard_room_name = clipboard_room_name();
+                    }
                     Action::Host => {
                         return netplay_disconnected
                             .host_game()
@@ -241,14 +319,37 @@ impl NetplayGui {
         NetplayState::Disconnected(netplay_disconnected)
     }
 
+    /// Lets the player pick the input delay instead of the one from the netplay server, both players need the same
+    fn input_delay_ui(ui: &mut Ui) {
+        let mut settings = Settings::current_mut();
+        let mut custom = settings.netplay_input_delay.is_some();
+        ui.checkbox(&mut custom, "Custom input delay").on_hover_text(
+            "A lower delay feels more responsive on a good connection, a higher one stutters less on a bad one. You will only be matched with players using the same delay.",
+        );
+        match (custom, &mut settings.netplay_input_delay) {
+            (true, Some(input_delay)) => {
+                ui.add(
+                    egui::Slider::new(input_delay, 0..=MAX_INPUT_DELAY)
+                        .suffix(" frames")
+                        .text("Input delay"),
+                );
+            }
+            (true, input_delay @ None) => *input_delay = Some(2),
+            (false, input_delay) => *input_delay = None,
+        }
+    }
+
     fn ui_connecting(
         &mut self,
         ui: &mut Ui,
-        netplay_connecting: Netplay<ConnectingState>,
+        mut netplay_connecting: Netplay<ConnectingState>,
     ) -> NetplayState {
         enum Action {
             Cancel,
             Retry(StartMethod),
+            KeepSearching,
+            HostInstead,
+            SwapPlayers,
         }
         let mut action = None;
 
@@ -257,7 +358,7 @@ impl NetplayGui {
                 LoadingNetplayServerConfigurationState { start_method, .. },
             )
             | ConnectingState::PeeringUp(PeeringState { start_method, .. }) => match start_method {
-                StartMethod::Start(.., room_name, join_or_host) => {
+                StartMethod::Start(start_state, room_name, join_or_host) => {
                     use super::connecting_state::JoinOrHost::*;
                     match join_or_host {
                         Join => {
@@ -308,6 +409,45 @@ impl NetplayGui {
                         ))
                         .ui(ui);
                     });
+
+                    if matches!(join_or_host, Host) {
+                        ui.end_row();
+                        let player = start_state
+                            .game_state
+                            .joypad_mapping
+                            .as_ref()
+                            .map_or(0, |joypad_mapping| joypad_mapping.player());
+                        ui.vertical_centered(|ui| {
+                            Label::new(ui_text_small(
+                                format!("YOU ARE PLAYER {}", player + 1),
+                                MenuButton::ACTIVE_COLOR,
+                            ))
+                            .selectable(false)
+                            .ui(ui);
+                            if ui_button("Swap players")
+                                .ui(ui)
+                                .on_hover_text("Pick which controller port you play on")
+                                .clicked()
+                            {
+                                action = Some(Action::SwapPlayers);
+                            }
+                        });
+                        ui.end_row();
+                        ui.vertical_centered(|ui| {
+                            if ui_button("Copy").ui(ui).clicked() {
+                                ui.ctx().copy_text(room_name.clone());
+                            }
+                            if let Some(invite_link) = invite_link(room_name) {
+                                if ui_button("Copy invite link")
+                                    .ui(ui)
+                                    .on_hover_text(invite_link.as_str())
+                                    .clicked()
+                                {
+                                    ui.ctx().copy_text(invite_link);
+                                }
+                            }
+                        });
+                    }
                 }
                 StartMethod::MatchWithRandom(_) => {
                     ui.vertical_centered(|ui| {
@@ -320,14 +460,62 @@ impl NetplayGui {
                     });
                     ui.end_row();
 
+                    let timed_out = matches!(
+                        &netplay_connecting.state,
+                        ConnectingState::PeeringUp(peering_state) if peering_state.find_game_timed_out()
+                    );
+                    if timed_out {
+                        ui.vertical_centered(|ui| {
+                            Label::new(ui_text_small(
+                                "NO ONE'S AROUND. HOST A PRIVATE GAME?",
+                                MenuButton::ACTIVE_COLOR,
+                            ))
+                            .selectable(false)
+                            .ui(ui);
+                        });
+                        ui.end_row();
+
+                        ui.vertical_centered(|ui| {
+                            if MenuButton::new("HOST PRIVATE GAME").ui(ui).clicked() {
+                                action = Some(Action::HostInstead);
+                            }
+                        });
+                        ui.end_row();
+
+                        ui.vertical_centered(|ui| {
+                            if MenuButton::new("KEEP SEARCHING").ui(ui).clicked() {
+                                action = Some(Action::KeepSearching);
+                            }
+                        });
+                    } else {
+                        ui.vertical_centered(|ui| {
+                            Label::new(ui_text_small(
+                                "WAITING FOR SECOND PLAYER",
+                                MenuButton::ACTIVE_COLOR,
+                            ))
+                            .selectable(false)
+                            .ui(ui);
+                        });
+                    }
+                }
+                StartMethod::Spectate(_, room_name) => {
                     ui.vertical_centered(|ui| {
-                        Label::new(ui_text_small(
-                            "WAITING FOR SECOND PLAYER",
+                        Label::new(MenuButton::ui_text(
+                            "WATCHING PRIVATE GAME",
                             MenuButton::ACTIVE_COLOR,
                         ))
                         .selectable(false)
                         .ui(ui);
                     });
+                    ui.end_row();
+
+                    ui.vertical_centered(|ui| {
+                        Label::new(MenuButton::ui_text(
+                            room_name,
+                            Color32::from_rgb(255, 225, 0),
+                        ))
+                        .ui(ui);
+                    });
                 }
                 StartMethod::Resume(_) => {
                     //This is used internally during the `NetplayState::Resuming` state
@@ -405,6 +593,31 @@ impl NetplayGui {
                 Action::Retry(start_method) => {
                     return netplay_connecting.cancel().start(start_method);
                 }
+                Action::KeepSearching => {
+                    if let ConnectingState::PeeringUp(peering_state) = &mut netplay_connecting.state
+                    {
+                        peering_state.waiting_since = Instant::now();
+                    }
+                }
+                Action::SwapPlayers => {
+                    if let ConnectingState::LoadingNetplayServerConfiguration(
+                        LoadingNetplayServerConfigurationState { start_method, .. },
+                    )
+                    | ConnectingState::PeeringUp(PeeringState { start_method, .. }) =
+                        &mut netplay_connecting.state
+                    {
+                        if let StartMethod::Start(start_state, ..) = start_method {
+                            let joypad_mapping = &mut start_state.game_state.joypad_mapping;
+                            *joypad_mapping = joypad_mapping.as_ref().map(JoypadMapping::other);
+                        }
+                    }
+                }
+                Action::HostInstead => {
+                    return netplay_connecting
+                        .cancel()
+                        .host_game()
+                        .expect("to be able to host a game");
+                }
             }
         }
         NetplayState::Connecting(netplay_connecting)
@@ -431,6 +644,18 @@ impl NetplayGui {
         });
         ui.end_row();
 
+        if let Some(ping) = netplay_connected.state.netplay_session.ping {
+            ui.vertical_centered(|ui| {
+                Label::new(ui_text_small(
+                    format!("PING: {ping}ms"),
+                    MenuButton::ACTIVE_COLOR,
+                ))
+                .selectable(false)
+                .ui(ui);
+            });
+            ui.end_row();
+        }
+
         #[allow(dead_code)] // Some actions are only triggered by certain features
         enum Action {
             FakeDisconnect,
@@ -456,6 +681,9 @@ impl NetplayGui {
                     Self::stats_ui(ui, &netplay_connected.state.stats[0], 0);
                     Self::stats_ui(ui, &netplay_connected.state.stats[1], 1);
                 });
+                if let Some(seed) = netplay_connected.state.netplay_session.start_method.seed() {
+                    ui.label(format!("Seed: {seed:x}"));
+                }
                 if ui.button("Fake connection lost").clicked() {
                     action = Some(Action::FakeDisconnect);
                 }
@@ -477,6 +705,60 @@ impl NetplayGui {
         NetplayState::Connected(netplay_connected)
     }
 
+    fn ui_spectating(
+        &mut self,
+        ui: &mut Ui,
+        netplay_spectating: Netplay<SpectatingState>,
+    ) -> NetplayState {
+        let spectating = &netplay_spectating.state;
+        // Hide the menu once the game shows up
+        if spectating
+            .started_at
+            .is_some_and(|started_at| started_at.elapsed().as_millis() < 200)
+        {
+            MainGui::set_main_menu_state(MainMenuState::Closed);
+        }
+
+        ui.vertical_centered(|ui| {
+            Label::new(MenuButton::ui_text(
+                format!("WATCHING {}", spectating.room_name),
+                MenuButton::ACTIVE_COLOR,
+            ))
+            .selectable(false)
+            .ui(ui);
+        });
+        ui.end_row();
+
+        let status = match (&spectating.ended, spectating.started_at) {
+            (Some(reason), _) => Some(reason.to_uppercase()),
+            (None, None) => Some("WAITING FOR THE GAME TO START".to_string()),
+            (None, Some(_)) => None,
+        };
+        if let Some(status) = status {
+            ui.vertical_centered(|ui| {
+                Label::new(ui_text_small(status, MenuButton::ACTIVE_COLOR))
+                    .selectable(false)
+                    .ui(ui);
+            });
+            ui.end_row();
+        }
+
+        let stop_clicked = ui
+            .vertical_centered(|ui| ui_button("Stop watching").ui(ui).clicked())
+            .inner;
+        ui.end_row();
+
+        if esc_pressed(ui.ctx()) {
+            MainGui::set_main_menu_state(MainMenuState::Main);
+        }
+
+        if stop_clicked {
+            NetplayState::Disconnected(netplay_spectating.disconnect())
+        } else {
+            NetplayState::Spectating(netplay_spectating)
+        }
+    }
+
     pub fn ui(&mut self, ui: &mut Ui, netplay_state_handler: &mut NetplayStateHandler) {
         let netplay = &mut netplay_state_handler.netplay;
         *netplay = Some(match netplay.take().unwrap() {
@@ -487,9 +769,16 @@ impl NetplayGui {
                 self.ui_connecting(ui, netplay_connecting)
             }
             NetplayState::Connected(netplay_connected) => self.ui_connected(ui, netplay_connected),
+            NetplayState::Spectating(netplay_spectating) => {
+                self.ui_spectating(ui, netplay_spectating)
+            }
             NetplayState::Resuming(netplay_resuming) => {
                 ui.vertical_centered(|ui| {
-                    Label::new(MenuButton::ui_text("RESUMING...", MenuButton::ACTIVE_COLOR))
+                    let text = match netplay_resuming.state.remaining() {
+                        Some(remaining) => format!("RECONNECTING... {}S", remaining.as_secs()),
+                        None => "RESUMING...".to_string(),
+                    };
+                    Label::new(MenuButton::ui_text(text, MenuButton::ACTIVE_COLOR))
                         .selectable(false)
                         .ui(ui);
                 });
@@ -498,6 +787,17 @@ impl NetplayGui {
                     .vertical_centered(|ui| ui_button("Disconnect").ui(ui).clicked())
                     .inner;
                 ui.end_row();
+                let review_clicked = ui
+                    .vertical_centered(|ui| {
+                        ui_button("Review the end of the game")
+                            .ui(ui)
+                            .on_hover_text(
+                                "Stop reconnecting and step through the last moments locally",
+                            )
+                            .clicked()
+                    })
+                    .inner;
+                ui.end_row();
 
                 if esc_pressed(ui.ctx()) {
                     MainGui::set_main_menu_state(MainMenuState::Main);
@@ -505,10 +805,53 @@ impl NetplayGui {
 
                 if disconnect_clicked {
                     NetplayState::Disconnected(netplay_resuming.cancel())
+                } else if review_clicked {
+                    NetplayState::Reviewing(netplay_resuming.review())
                 } else {
                     NetplayState::Resuming(netplay_resuming)
                 }
             }
+            NetplayState::Reviewing(mut netplay_reviewing) => {
+                let review = &mut netplay_reviewing.state;
+                ui.vertical_centered(|ui| {
+                    Label::new(MenuButton::ui_text(
+                        format!("REVIEWING (FRAME {})", review.nes_state.frame()),
+                        MenuButton::ACTIVE_COLOR,
+                    ))
+                    .selectable(false)
+                    .ui(ui);
+                });
+                ui.end_row();
+                ui.vertical_centered(|ui| {
+                    if ui_button(if review.paused { "Play" } else { "Pause" })
+                        .ui(ui)
+                        .clicked()
+                    {
+                        review.paused = !review.paused;
+                    }
+                    if ui
+                        .add_enabled(review.paused, ui_button("Step frame"))
+                        .clicked()
+                    {
+                        review.step();
+                    }
+                });
+                ui.end_row();
+                let leave_clicked = ui
+                    .vertical_centered(|ui| ui_button("Back to local play").ui(ui).clicked())
+                    .inner;
+                ui.end_row();
+
+                if esc_pressed(ui.ctx()) {
+                    MainGui::set_main_menu_state(MainMenuState::Main);
+                }
+
+                if leave_clicked {
+                    NetplayState::Disconnected(netplay_reviewing.disconnect())
+                } else {
+                    NetplayState::Reviewing(netplay_reviewing)
+                }
+            }
             NetplayState::Failed(netplay_failed) => {
                 ui.label(format!(
                     "Failed to connect: {}",
@@ -1,7 +1,11 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
 
 use crate::{
-    emulation::{LocalNesState, NESBuffers, NesStateHandler},
+    bundle::Bundle,
+    emulation::{cheats::Cheat, LocalNesState, NESBuffers, NesStateHandler},
     input::JoypadState,
     settings::MAX_PLAYERS,
 };
@@ -10,13 +14,19 @@ use serde::Deserialize;
 
 use self::{
     connecting_state::{ConnectingState, NetplayServerConfiguration, StartMethod, StartState},
-    netplay_state::{Netplay, NetplayState},
+    netplay_state::{valid_room_name, Netplay, NetplayState},
 };
 
+/// Netplay is limited to two players, even when the bundle allows four players locally
+pub const MAX_NETPLAY_PLAYERS: usize = 2;
+
 mod connecting_state;
 pub mod gui;
 mod netplay_session;
 mod netplay_state;
+#[cfg(feature = "debug")]
+mod server_test;
+mod spectating;
 
 #[cfg(feature = "debug")]
 mod stats;
@@ -28,27 +38,46 @@ pub enum JoypadMapping {
 }
 
 impl JoypadMapping {
+    /// The player (0 for player 1) the local inputs go to
+    pub fn player(&self) -> u8 {
+        match self {
+            JoypadMapping::P1 => 0,
+            JoypadMapping::P2 => 1,
+        }
+    }
+
+    fn from_player(player: u8) -> Option<Self> {
+        match player {
+            0 => Some(JoypadMapping::P1),
+            1 => Some(JoypadMapping::P2),
+            _ => None,
+        }
+    }
+
+    /// The mapping of the other player
+    pub fn other(&self) -> Self {
+        match self {
+            JoypadMapping::P1 => JoypadMapping::P2,
+            JoypadMapping::P2 => JoypadMapping::P1,
+        }
+    }
+
     fn map(
         &self,
         joypad_state: [JoypadState; MAX_PLAYERS],
         local_player_idx: usize,
     ) -> [JoypadState; MAX_PLAYERS] {
-        match self {
-            JoypadMapping::P1 => {
-                if local_player_idx == 0 {
-                    [joypad_state[0], joypad_state[1]]
-                } else {
-                    [joypad_state[1], joypad_state[0]]
-                }
-            }
-            JoypadMapping::P2 => {
-                if local_player_idx == 0 {
-                    [joypad_state[1], joypad_state[0]]
-                } else {
-                    [joypad_state[0], joypad_state[1]]
-                }
-            }
+        let swapped = match self {
+            JoypadMapping::P1 => local_player_idx != 0,
+            JoypadMapping::P2 => local_player_idx == 0,
+        };
+        let mut mapped = [JoypadState::default(); MAX_PLAYERS];
+        if swapped {
+            (mapped[0], mapped[1]) = (joypad_state[1], joypad_state[0]);
+        } else {
+            (mapped[0], mapped[1]) = (joypad_state[0], joypad_state[1]);
         }
+        mapped
     }
 }
 
@@ -56,6 +85,109 @@ impl JoypadMapping {
 pub struct NetplayBuildConfiguration {
     pub netplay_id: Option<String>,
     pub server: NetplayServerConfiguration,
+    #[serde(default = "Default::default")]
+    pub url_scheme: Option<String>,
+    #[serde(default = "Default::default")]
+    pub start_mode: StartMode,
+    /// Seconds to look for a public game before offering to host a private one instead, 0 to never give up
+    #[serde(default = "NetplayBuildConfiguration::default_find_game_timeout")]
+    pub find_game_timeout: u64,
+    /// Seconds to try reconnecting after losing the other player before giving up, 0 to never give up
+    #[serde(default = "NetplayBuildConfiguration::default_resume_timeout")]
+    pub resume_timeout: u64,
+}
+
+/// What the game shows when it starts, for setups dedicated to netplay
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StartMode {
+    #[default]
+    Game,
+    /// Open the netplay menu
+    Netplay,
+    /// Start looking for a public game right away
+    FindGame,
+}
+
+impl NetplayBuildConfiguration {
+    fn default_find_game_timeout() -> u64 {
+        60
+    }
+
+    pub fn find_game_timeout(&self) -> Option<Duration> {
+        (self.find_game_timeout > 0).then(|| Duration::from_secs(self.find_game_timeout))
+    }
+
+    fn default_resume_timeout() -> u64 {
+        30
+    }
+
+    pub fn resume_timeout(&self) -> Option<Duration> {
+        (self.resume_timeout > 0).then(|| Duration::from_secs(self.resume_timeout))
+    }
+
+    /// The start mode from a `--start-mode=<mode>` argument, or the one from the bundle
+    pub fn start_mode(&self) -> StartMode {
+        std::env::args()
+            .skip(1)
+            .find_map(|arg| {
+                let mode = arg.strip_prefix("--start-mode=")?;
+                serde_yaml::from_str(mode)
+                    .inspect_err(|e| log::warn!("Unknown start mode {mode:?}: {e}"))
+                    .ok()
+            })
+            .unwrap_or(self.start_mode)
+    }
+}
+
+/// An invite link to the private game in `room_name`, if the bundle has a url scheme
+pub fn invite_link(room_name: &str) -> Option<String> {
+    let url_scheme = Bundle::current().config.netplay.url_scheme.as_ref()?;
+    Some(format!("{url_scheme}://join/{room_name}"))
+}
+
+/// The room name in an invite link like `<url_scheme>://join/<room name>`
+pub fn parse_invite_link(link: &str) -> Option<String> {
+    let url_scheme = Bundle::current().config.netplay.url_scheme.as_ref()?;
+    let (scheme, path) = link.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case(url_scheme) {
+        return None;
+    }
+    valid_room_name(path.strip_prefix("join/")?.trim_end_matches('/'))
+}
+
+#[derive(Debug)]
+pub enum NetplayCommand {
+    JoinGame(String),
+    /// Look for a public game to join
+    FindGame,
+    /// Leave any active session, closing the connection to the peer
+    Disconnect,
+}
+
+impl NetplayStateHandler {
+    pub fn handle_command(&mut self, command: NetplayCommand) {
+        self.netplay = self.netplay.take().map(|netplay| match (netplay, command) {
+            (NetplayState::Disconnected(netplay), NetplayCommand::JoinGame(room_name)) => netplay
+                .join_game(&room_name)
+                .expect("to be able to join game"),
+            (NetplayState::Disconnected(netplay), NetplayCommand::FindGame) => {
+                netplay.find_game().expect("to be able to find a game")
+            }
+            (netplay, NetplayCommand::Disconnect) => NetplayState::Disconnected(match netplay {
+                NetplayState::Disconnected(netplay) => netplay,
+                NetplayState::Connecting(netplay) => netplay.disconnect(),
+                NetplayState::Connected(netplay) => netplay.disconnect(),
+                NetplayState::Resuming(netplay) => netplay.disconnect(),
+                NetplayState::Reviewing(netplay) => netplay.disconnect(),
+                NetplayState::Spectating(netplay) => netplay.disconnect(),
+                NetplayState::Failed(netplay) => netplay.disconnect(),
+            }),
+            (netplay, command) => {
+                log::warn!("Ignoring {command:?} since netplay is already active");
+                netplay
+            }
+        });
+    }
 }
 
 pub struct NetplayStateHandler {
@@ -99,7 +231,7 @@ impl NesStateHandler for NetplayStateHandler {
             let sess = &netplay.state.netplay_session.p2p_session;
             if netplay.state.netplay_session.game_state.frame % 30 == 0 {
                 puffin::profile_scope!("Netplay stats");
-                for i in 0..MAX_PLAYERS {
+                for i in 0..MAX_NETPLAY_PLAYERS {
                     if let Ok(stats) = sess.network_stats(i) {
                         if !sess.local_player_handles().contains(&i) {
                             netplay.state.stats[i].push_stats(stats);
@@ -126,18 +258,113 @@ impl NesStateHandler for NetplayStateHandler {
         }
     }
 
+    fn save_state(&self) -> Option<LocalNesState> {
+        // Save states are only supported when disconnected
+        match &self.netplay {
+            Some(NetplayState::Disconnected(s)) => s.state.save_state(),
+            _ => None,
+        }
+    }
+
+    fn load_state(&mut self, state: &LocalNesState) -> bool {
+        match &mut self.netplay {
+            Some(NetplayState::Disconnected(s)) => s.state.load_state(state),
+            _ => false,
+        }
+    }
+
     fn frame(&self) -> u32 {
         match &self.netplay {
             Some(NetplayState::Connected(s)) => s.state.netplay_session.game_state.frame(),
             Some(NetplayState::Disconnected(s)) => s.state.frame(),
+            Some(NetplayState::Reviewing(s)) => s.state.nes_state.frame(),
+            Some(NetplayState::Spectating(s)) => s.state.nes_state.frame(),
             _ => 0,
         }
     }
 
+    fn state_hash(&self) -> Option<u64> {
+        match &self.netplay {
+            Some(NetplayState::Connected(s)) => s.state.netplay_session.game_state.state_hash(),
+            Some(NetplayState::Disconnected(s)) => s.state.state_hash(),
+            Some(NetplayState::Reviewing(s)) => s.state.nes_state.state_hash(),
+            Some(NetplayState::Spectating(s)) => s.state.nes_state.state_hash(),
+            _ => None,
+        }
+    }
+
+    fn joypads(&self) -> [JoypadState; MAX_PLAYERS] {
+        match &self.netplay {
+            Some(NetplayState::Connected(s)) => s.state.netplay_session.game_state.joypads(),
+            Some(NetplayState::Disconnected(s)) => s.state.joypads(),
+            Some(NetplayState::Reviewing(s)) => s.state.nes_state.joypads(),
+            Some(NetplayState::Spectating(s)) => s.state.nes_state.joypads(),
+            _ => Default::default(),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        match &self.netplay {
+            Some(NetplayState::Connected(s)) => s.state.netplay_session.game_state.peek(addr),
+            Some(NetplayState::Disconnected(s)) => s.state.peek(addr),
+            Some(NetplayState::Reviewing(s)) => s.state.nes_state.peek(addr),
+            Some(NetplayState::Spectating(s)) => s.state.nes_state.peek(addr),
+            _ => None,
+        }
+    }
+
+    fn apply_cheats(&mut self, cheats: &[Cheat]) {
+        // Only for local play, the peers would desync
+        if let Some(NetplayState::Disconnected(s)) = &mut self.netplay {
+            s.state.apply_cheats(cheats);
+        }
+    }
+
+    fn netplay_active(&self) -> bool {
+        !matches!(&self.netplay, Some(NetplayState::Disconnected(_)))
+    }
+
+    fn room_name(&self) -> Option<String> {
+        let start_method = match &self.netplay {
+            Some(NetplayState::Connecting(s)) => match &s.state {
+                ConnectingState::LoadingNetplayServerConfiguration(
+                    connecting_state::LoadingNetplayServerConfigurationState {
+                        start_method, ..
+                    },
+                )
+                | ConnectingState::PeeringUp(connecting_state::PeeringState {
+                    start_method, ..
+                }) => start_method,
+                _ => return None,
+            },
+            Some(NetplayState::Connected(s)) => &s.state.netplay_session.start_method,
+            Some(NetplayState::Spectating(s)) => return Some(s.state.room_name.clone()),
+            _ => return None,
+        };
+        match start_method {
+            StartMethod::Start(.., room_name, _) | StartMethod::Spectate(_, room_name) => {
+                Some(room_name.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn speed(&self) -> f32 {
+        match &self.netplay {
+            Some(NetplayState::Connected(s)) => s.state.netplay_session.game_state.speed(),
+            Some(NetplayState::Disconnected(s)) => s.state.speed(),
+            Some(NetplayState::Reviewing(s)) => s.state.nes_state.speed(),
+            Some(NetplayState::Spectating(s)) => s.state.nes_state.speed(),
+            _ => 1.0,
+        }
+    }
+
     fn set_speed(&mut self, speed: f32) {
         match &mut self.netplay {
             Some(NetplayState::Connected(s)) => s.state.netplay_session.game_state.set_speed(speed),
             Some(NetplayState::Disconnected(s)) => s.state.set_speed(speed),
+            Some(NetplayState::Reviewing(s)) => s.state.nes_state.set_speed(speed),
+            Some(NetplayState::Spectating(s)) => s.state.nes_state.set_speed(speed),
             _ => {}
         }
     }
@@ -10,8 +10,9 @@ use crate::{
 };
 
 use super::{
-    connecting_state::{StartMethod, StaticNetplayServerConfiguration},
-    JoypadMapping, NetplayNesState,
+    connecting_state::{StartMethod, StartState, StaticNetplayServerConfiguration},
+    spectating::SpectatorRelay,
+    JoypadMapping, NetplayNesState, MAX_NETPLAY_PLAYERS,
 };
 
 #[derive(Debug)]
@@ -22,14 +23,73 @@ impl Config for GGRSConfig {
     type Address = PeerId;
 }
 
+/// Keeps rollbacks from being heard. The frames after a rollback are replayed, only the ones that weren't
+/// played before produce audio, and the audio after a rollback fades in from the last sample that was played.
+struct RollbackAudio {
+    last_handled_frame: i32,
+    last_sample: f32,
+}
+
+impl RollbackAudio {
+    /// How many samples it takes to fade from the audio before a rollback to the audio after it
+    const FADE_SAMPLES: usize = 64;
+
+    fn new() -> Self {
+        Self {
+            last_handled_frame: -1,
+            last_sample: 0.0,
+        }
+    }
+
+    /// Whether `frame` was played before, it should be advanced without audio and video then
+    fn is_replay(&self, frame: i32) -> bool {
+        frame <= self.last_handled_frame
+    }
+
+    /// `frame` was played for the first time and produced `samples`
+    fn played(&mut self, frame: i32, samples: Option<&mut [f32]>, rolled_back: bool) {
+        if let Some(samples) = samples {
+            if rolled_back {
+                Self::fade_in(samples, self.last_sample);
+            }
+            if let Some(last_sample) = samples.last() {
+                self.last_sample = *last_sample;
+            }
+        }
+        self.last_handled_frame = frame;
+    }
+
+    /// Fade from `from` into `samples`, so a rollback that changed the sound doesn't click
+    fn fade_in(samples: &mut [f32], from: f32) {
+        let fade_samples = samples.len().min(Self::FADE_SAMPLES);
+        for (i, sample) in samples.iter_mut().take(fade_samples).enumerate() {
+            let t = (i + 1) as f32 / fade_samples as f32;
+            *sample = from + (*sample - from) * t;
+        }
+    }
+}
+
+/// The frames an advance of the session went through
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Advanced {
+    /// Frames replayed after a rollback, without audio and video since they were already played
+    pub replayed: usize,
+    /// New frames that produced audio and video, at most one per advance
+    pub played: usize,
+}
+
 pub struct NetplaySessionState {
     pub p2p_session: P2PSession<GGRSConfig>,
     pub game_state: NetplayNesState,
-    pub last_handled_frame: i32,
     pub last_confirmed_game_state1: NetplayNesState,
     pub last_confirmed_game_state2: NetplayNesState,
     pub start_method: StartMethod,
     pub netplay_server_configuration: StaticNetplayServerConfiguration,
+    rollback_audio: RollbackAudio,
+    /// Sends the inputs to the spectators, if this peer is the one doing it
+    spectator_relay: Option<SpectatorRelay>,
+    /// The round-trip time to the other player in milliseconds, once it's known
+    pub ping: Option<u128>,
 }
 
 impl NetplaySessionState {
@@ -41,22 +101,42 @@ impl NetplaySessionState {
         let mut game_state = match &start_method {
             StartMethod::Start(start_state, ..)
             | StartMethod::Resume(start_state)
-            | StartMethod::MatchWithRandom(start_state) => start_state.clone().game_state,
+            | StartMethod::MatchWithRandom(start_state)
+            | StartMethod::Spectate(start_state, _) => start_state.clone().game_state,
         };
         //Start counting from 0 to be in sync with ggrs frame counter.
         game_state.frame = 0;
 
+        // Private games can be watched, the first player relays the inputs. A resumed game starts
+        // from a state the spectators don't have, so it can't be watched.
+        let spectator_relay = match &start_method {
+            StartMethod::Start(StartState { session_id, .. }, ..)
+                if p2p_session.local_player_handles().contains(&0) =>
+            {
+                Some(SpectatorRelay::new(
+                    &netplay_server_configuration,
+                    session_id,
+                ))
+            }
+            _ => None,
+        };
+
         Self {
             p2p_session,
             game_state: game_state.clone(),
             last_confirmed_game_state1: game_state.clone(),
             last_confirmed_game_state2: game_state,
-            last_handled_frame: -1,
             start_method,
             netplay_server_configuration,
+            rollback_audio: RollbackAudio::new(),
+            spectator_relay,
+            ping: None,
         }
     }
 
+    /// How often (in frames) the ping is refreshed, so it doesn't jump around in the UI
+    const PING_INTERVAL: i32 = 30;
+
     pub fn get_local_player_idx(&self) -> usize {
         //There should be only one.
         *self
@@ -71,7 +151,7 @@ impl NetplaySessionState {
         joypad_state: [JoypadState; MAX_PLAYERS],
         joypad_mapping: &JoypadMapping,
         buffers: &mut NESBuffers,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<Advanced> {
         #[cfg(feature = "debug")]
         puffin::profile_function!();
 
@@ -96,35 +176,54 @@ impl NetplaySessionState {
 
         #[cfg(feature = "debug")]
         puffin::profile_scope!("ggrs advance_frame");
+        let mut advanced = Advanced::default();
         match sess.advance_frame() {
             Ok(requests) => {
+                let mut rolled_back = false;
                 for request in requests {
                     match request {
                         GgrsRequest::LoadGameState { cell, frame } => {
                             log::debug!("Loading (frame {:?})", frame);
                             self.game_state = cell.load().expect("ggrs state to load");
+                            rolled_back = true;
                         }
                         GgrsRequest::SaveGameState { cell, frame } => {
                             assert_eq!(self.game_state.frame, frame);
                             cell.save(frame, Some(self.game_state.clone()), None);
                         }
                         GgrsRequest::AdvanceFrame { inputs } => {
-                            let is_replay = self.game_state.frame <= self.last_handled_frame;
+                            let is_replay = self.rollback_audio.is_replay(self.game_state.frame);
                             let no_buffers = &mut NESBuffers {
                                 audio: None,
                                 video: None,
                             };
-                            self.game_state.advance(
-                                joypad_mapping.map(
-                                    [JoypadState(inputs[0].0), JoypadState(inputs[1].0)],
-                                    local_player_idx,
-                                ),
-                                if is_replay { no_buffers } else { buffers },
+                            let audio_start = buffers.audio.as_ref().map(|audio| audio.len());
+                            let joypads = joypad_mapping.map(
+                                std::array::from_fn(|player| {
+                                    JoypadState(inputs.get(player).map_or(0, |input| input.0))
+                                }),
+                                local_player_idx,
                             );
+                            if let Some(spectator_relay) = &mut self.spectator_relay {
+                                spectator_relay.record(self.game_state.frame, &joypads);
+                            }
+                            self.game_state
+                                .advance(joypads, if is_replay { no_buffers } else { buffers });
 
-                            if !is_replay {
-                                //This is not a replay
-                                self.last_handled_frame = self.game_state.frame;
+                            if is_replay {
+                                advanced.replayed += 1;
+                            } else {
+                                advanced.played += 1;
+                                let samples = buffers
+                                    .audio
+                                    .as_deref_mut()
+                                    .zip(audio_start)
+                                    .map(|(audio, audio_start)| &mut audio[audio_start..]);
+                                self.rollback_audio.played(
+                                    self.game_state.frame,
+                                    samples,
+                                    rolled_back,
+                                );
                                 if self.game_state.frame % (sess.max_prediction() + 1) as i32 == 0 {
                                     mem::swap(
                                         &mut self.last_confirmed_game_state1,
@@ -144,6 +243,19 @@ impl NetplaySessionState {
             }
         }
 
+        if let Some(spectator_relay) = &mut self.spectator_relay {
+            spectator_relay.update(sess.confirmed_frame());
+        }
+
+        if self.game_state.frame % Self::PING_INTERVAL == 0 {
+            if let Some(stats) = (0..MAX_NETPLAY_PLAYERS)
+                .filter(|handle| !sess.local_player_handles().contains(handle))
+                .find_map(|handle| sess.network_stats(handle).ok())
+            {
+                self.ping = Some(stats.ping);
+            }
+        }
+
         if sess.frames_ahead() > 0 {
             log::debug!(
                 "Frames ahead: {:?}, slowing down emulation",
@@ -155,6 +267,209 @@ impl NetplaySessionState {
         } else {
             self.game_state.set_speed(1.0)
         }
-        Ok(())
+        Ok(advanced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::{Arc, Mutex},
+    };
+
+    use ggrs::{Message, NonBlockingSocket, PlayerType, SessionBuilder, SessionState};
+
+    use super::*;
+    use crate::emulation::{LocalNesState, NESAudioFrame, NesRegion};
+
+    /// Advance `frame` like the session does, every frame sounds like its number
+    fn play_frame(audio: &mut RollbackAudio, frame: i32, rolled_back: bool) -> Option<Vec<f32>> {
+        if audio.is_replay(frame) {
+            return None;
+        }
+        let mut samples = vec![frame as f32; 4];
+        audio.played(frame, Some(&mut samples), rolled_back);
+        Some(samples)
+    }
+
+    #[test]
+    fn only_rolled_back_frames_skip_audio() {
+        let mut audio = RollbackAudio::new();
+        for frame in 0..3 {
+            assert_eq!(
+                play_frame(&mut audio, frame, false),
+                Some(vec![frame as f32; 4])
+            );
+        }
+
+        // Rolled back to frame 1, frames 1 and 2 are replayed before the new frame 3
+        assert_eq!(play_frame(&mut audio, 1, true), None);
+        assert_eq!(play_frame(&mut audio, 2, true), None);
+        assert_eq!(
+            play_frame(&mut audio, 3, true),
+            Some(vec![2.25, 2.5, 2.75, 3.0]),
+            "the new frame fades in from the last sample that was played"
+        );
+
+        assert_eq!(play_frame(&mut audio, 4, false), Some(vec![4.0; 4]));
+    }
+
+    /// Two peers exchanging messages in memory. The messages to a peer can be held back to make it mispredict.
+    #[derive(Default)]
+    struct Network {
+        inboxes: HashMap<PeerId, Vec<(PeerId, Message)>>,
+        held: HashSet<PeerId>,
+    }
+
+    struct TestSocket {
+        peer: PeerId,
+        network: Arc<Mutex<Network>>,
+    }
+
+    impl NonBlockingSocket<PeerId> for TestSocket {
+        fn send_to(&mut self, msg: &Message, addr: &PeerId) {
+            let mut network = self.network.lock().unwrap();
+            network
+                .inboxes
+                .entry(*addr)
+                .or_default()
+                .push((self.peer, msg.clone()));
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+            let mut network = self.network.lock().unwrap();
+            if network.held.contains(&self.peer) {
+                return Vec::new();
+            }
+            network.inboxes.remove(&self.peer).unwrap_or_default()
+        }
+    }
+
+    const SERVER_CONFIGURATION: &str = "
+matchbox:
+  server: localhost
+  ice:
+    urls: []
+    credentials: !None
+ggrs:
+  max_prediction: 8
+  input_delay: 0
+";
+
+    fn peer_id(handle: usize) -> PeerId {
+        PeerId(uuid::Uuid::from_u128(handle as u128 + 1))
+    }
+
+    fn session(network: &Arc<Mutex<Network>>, local_handle: usize) -> NetplaySessionState {
+        let mut builder = SessionBuilder::<GGRSConfig>::new()
+            .with_num_players(MAX_NETPLAY_PLAYERS)
+            .with_input_delay(0)
+            .with_max_prediction_window(8);
+        for handle in 0..MAX_NETPLAY_PLAYERS {
+            let player = if handle == local_handle {
+                PlayerType::Local
+            } else {
+                PlayerType::Remote(peer_id(handle))
+            };
+            builder = builder.add_player(player, handle).unwrap();
+        }
+        let p2p_session = builder
+            .start_p2p_session(TestSocket {
+                peer: peer_id(local_handle),
+                network: network.clone(),
+            })
+            .unwrap();
+        let game_state = NetplayNesState::new(
+            LocalNesState::start_rom_for_netplay(
+                include_bytes!("../../config/rom.nes"),
+                &NesRegion::Ntsc,
+            )
+            .unwrap(),
+        );
+        NetplaySessionState::new(
+            StartMethod::Resume(StartState {
+                game_state,
+                session_id: "test".to_string(),
+                seed: None,
+                input_delay: None,
+            }),
+            p2p_session,
+            serde_yaml::from_str(SERVER_CONFIGURATION).unwrap(),
+        )
+    }
+
+    /// Advance a peer with `pressed` held, returns the advanced frames and how many audio samples it produced
+    fn advance(
+        peer: &mut NetplaySessionState,
+        joypad_mapping: &JoypadMapping,
+        pressed: u8,
+    ) -> (Advanced, usize) {
+        let mut audio = NESAudioFrame::new();
+        let advanced = peer
+            .advance(
+                std::array::from_fn(|player| JoypadState(if player == 0 { pressed } else { 0 })),
+                joypad_mapping,
+                &mut NESBuffers {
+                    audio: Some(&mut audio),
+                    video: None,
+                },
+            )
+            .unwrap();
+        (advanced, audio.len())
+    }
+
+    const ONE_FRAME: Advanced = Advanced {
+        replayed: 0,
+        played: 1,
+    };
+
+    #[test]
+    fn a_rollback_burst_plays_one_frame_of_audio() {
+        let network = Arc::new(Mutex::new(Network::default()));
+        let mut host = session(&network, 0);
+        let mut guest = session(&network, 1);
+        for _ in 0..100 {
+            host.p2p_session.poll_remote_clients();
+            guest.p2p_session.poll_remote_clients();
+        }
+        assert!(matches!(
+            host.p2p_session.current_state(),
+            SessionState::Running
+        ));
+        assert!(matches!(
+            guest.p2p_session.current_state(),
+            SessionState::Running
+        ));
+
+        let (_, frame_samples) = advance(&mut host, &JoypadMapping::P1, 0);
+        assert!(frame_samples > 0);
+        assert_eq!(advance(&mut guest, &JoypadMapping::P2, 0).0, ONE_FRAME);
+        let one_frame_of_audio = |samples: usize| samples > 0 && samples < 2 * frame_samples;
+
+        // The host doesn't hear from the guest while the guest starts pressing a button, so it predicts wrong
+        network.lock().unwrap().held.insert(peer_id(0));
+        for _ in 0..3 {
+            let (advanced, samples) = advance(&mut host, &JoypadMapping::P1, 0);
+            assert_eq!(advanced, ONE_FRAME);
+            assert!(one_frame_of_audio(samples));
+            assert_eq!(advance(&mut guest, &JoypadMapping::P2, 1).0, ONE_FRAME);
+        }
+
+        // The guest's inputs arrive and the host rolls back, only the new frame is heard
+        network.lock().unwrap().held.remove(&peer_id(0));
+        let (advanced, samples) = advance(&mut host, &JoypadMapping::P1, 0);
+        assert_eq!(
+            advanced,
+            Advanced {
+                replayed: 3,
+                played: 1
+            }
+        );
+        assert!(one_frame_of_audio(samples));
+
+        let (advanced, samples) = advance(&mut host, &JoypadMapping::P1, 0);
+        assert_eq!(advanced, ONE_FRAME);
+        assert!(one_frame_of_audio(samples));
     }
 }
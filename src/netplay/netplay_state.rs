@@ -1,4 +1,4 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use uuid::Uuid;
@@ -11,8 +11,10 @@ use crate::{
 };
 
 use super::{
-    connecting_state::JoinOrHost, netplay_session::NetplaySessionState, ConnectingState,
-    JoypadMapping, StartMethod, StartState,
+    connecting_state::{JoinOrHost, StaticNetplayServerConfiguration},
+    netplay_session::NetplaySessionState,
+    spectating::SpectatingState,
+    ConnectingState, JoypadMapping, NetplayNesState, StartMethod, StartState,
 };
 
 pub enum NetplayState {
@@ -20,6 +22,8 @@ pub enum NetplayState {
     Connecting(Netplay<ConnectingState>),
     Connected(Netplay<ConnectedState>),
     Resuming(Netplay<ResumingState>),
+    Reviewing(Netplay<ReviewState>),
+    Spectating(Netplay<SpectatingState>),
     Failed(Netplay<FailedState>),
 }
 
@@ -57,6 +61,9 @@ impl NetplayState {
                 netplay.advance()
             }
             Disconnected(netplay) => netplay.advance(joypad_state, buffers),
+            Reviewing(netplay) => netplay.advance(joypad_state, buffers),
+            // A spectator only sees what the players press
+            Spectating(netplay) => netplay.advance(buffers),
             Failed(netplay) => netplay.advance(),
         }
     }
@@ -83,31 +90,83 @@ pub struct ConnectedState {
     session_id: String,
     pub start_time: Instant,
     #[cfg(feature = "debug")]
-    pub stats: [crate::netplay::stats::NetplayStats; crate::settings::MAX_PLAYERS],
+    pub stats: [crate::netplay::stats::NetplayStats; crate::netplay::MAX_NETPLAY_PLAYERS],
 }
 
 pub struct ResumingState {
     attempt1: ConnectingState,
     attempt2: ConnectingState,
+    /// The state before `last_confirmed_game_state`, the peer might not have seen the most recent one
+    earlier_confirmed_game_state: NetplayNesState,
+    /// The most recent state both peers agreed on, used if the game is reviewed instead of resumed
+    last_confirmed_game_state: NetplayNesState,
+    session_id: String,
+    input_delay: Option<u8>,
+    netplay_server_configuration: StaticNetplayServerConfiguration,
+    /// When to stop trying to reconnect, if ever
+    deadline: Option<Instant>,
+}
+
+/// Playing back the end of a netplay game locally after the connection was lost
+pub struct ReviewState {
+    pub nes_state: LocalNesState,
+    pub paused: bool,
+    step_requested: bool,
+}
+
+impl ReviewState {
+    /// Advance a single frame while paused
+    pub fn step(&mut self) {
+        self.step_requested = true;
+    }
 }
 impl ResumingState {
     fn new(netplay: &mut Netplay<ConnectedState>) -> Self {
         let netplay_session = &netplay.state.netplay_session;
 
         let session_id = netplay.state.session_id.clone();
+        let input_delay = netplay_session.start_method.input_delay();
         Self {
             attempt2: ConnectingState::resume(
                 netplay_session.last_confirmed_game_state2.clone(),
                 session_id.clone(),
+                input_delay,
                 netplay_session.netplay_server_configuration.clone(),
             ),
             attempt1: ConnectingState::resume(
                 netplay_session.last_confirmed_game_state1.clone(),
                 session_id.clone(),
+                input_delay,
                 netplay_session.netplay_server_configuration.clone(),
             ),
+            earlier_confirmed_game_state: netplay_session.last_confirmed_game_state1.clone(),
+            last_confirmed_game_state: netplay_session.last_confirmed_game_state2.clone(),
+            session_id,
+            input_delay,
+            netplay_server_configuration: netplay_session.netplay_server_configuration.clone(),
+            deadline: Bundle::current()
+                .config
+                .netplay
+                .resume_timeout()
+                .map(|timeout| Instant::now() + timeout),
         }
     }
+
+    /// A new attempt to resume from `game_state`, for when the previous one failed
+    fn retry(&self, game_state: &NetplayNesState) -> ConnectingState {
+        ConnectingState::resume(
+            game_state.clone(),
+            self.session_id.clone(),
+            self.input_delay,
+            self.netplay_server_configuration.clone(),
+        )
+    }
+
+    /// How long is left before giving up on reconnecting, if there is a limit
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
 }
 pub fn get_netplay_id() -> String {
     Settings::current_mut()
@@ -118,14 +177,23 @@ pub fn get_netplay_id() -> String {
 
 pub const MAX_ROOM_NAME_LEN: u8 = 4;
 
+/// The most input delay (in frames) a player can pick
+pub const MAX_INPUT_DELAY: u8 = 6;
+
+/// The uppercased room name if `text` looks like one
+pub fn valid_room_name(text: &str) -> Option<String> {
+    let room_name = text.trim().to_uppercase();
+    (room_name.len() == usize::from(MAX_ROOM_NAME_LEN)
+        && room_name.chars().all(|c| c.is_ascii_alphanumeric()))
+    .then_some(room_name)
+}
+
 impl Netplay<LocalNesState> {
     pub fn new() -> Result<Self> {
+        let rom = &Bundle::current().selected_game().rom;
+        let region = Settings::current_mut().get_nes_region().clone();
         Ok(Self {
-            state: LocalNesState::start_rom(
-                &Bundle::current().rom,
-                true,
-                Settings::current_mut().get_nes_region(),
-            )?,
+            state: LocalNesState::start_rom(rom, true, &region)?,
         })
     }
 
@@ -143,22 +211,42 @@ impl Netplay<LocalNesState> {
         self.join_or_host(&room_name.to_uppercase(), JoinOrHost::Join)
     }
 
+    /// Watch the private game in `room_name` without playing
+    pub fn watch_game(self, room_name: &str) -> Result<NetplayState> {
+        let room_name = room_name.to_uppercase();
+        Ok(self.start(StartMethod::Spectate(
+            Self::private_start_state(&room_name)?,
+            room_name,
+        )))
+    }
+
     fn join_or_host(self, room_name: &str, join_or_host: JoinOrHost) -> Result<NetplayState> {
+        let mut start_state = Self::private_start_state(room_name)?;
+        if let JoinOrHost::Host = join_or_host {
+            // The host is player 1 unless it picks otherwise while waiting, the joining player learns which when connecting
+            start_state.game_state.joypad_mapping = Some(JoypadMapping::P1);
+        }
+        Ok(self.start(StartMethod::Start(
+            start_state,
+            room_name.to_string(),
+            join_or_host,
+        )))
+    }
+
+    /// How a private game in `room_name` starts, the same for the players and the spectators
+    fn private_start_state(room_name: &str) -> Result<StartState> {
         let netplay_rom = &Bundle::current().netplay_rom;
         let session_id = format!("{}_{:x}", room_name, md5::compute(netplay_rom));
-        let nes_state = LocalNesState::start_rom(
+        let nes_state = LocalNesState::start_rom_for_netplay(
             netplay_rom,
-            false,
             Bundle::current().config.get_default_region(),
         )?;
-        Ok(self.start(StartMethod::Start(
-            StartState {
-                game_state: super::NetplayNesState::new(nes_state),
-                session_id,
-            },
-            room_name.to_string(),
-            join_or_host,
-        )))
+        Ok(StartState {
+            game_state: super::NetplayNesState::new(nes_state),
+            session_id,
+            seed: None,
+            input_delay: Settings::current().netplay_input_delay,
+        })
     }
 
     pub fn find_game(self) -> Result<NetplayState> {
@@ -168,14 +256,15 @@ impl Netplay<LocalNesState> {
         // TODO: When resuming using this session id there might be collisions, but it's unlikely.
         //       Should be fixed though.
         let session_id = format!("{:x}", rom_hash);
-        let nes_state = LocalNesState::start_rom(
+        let nes_state = LocalNesState::start_rom_for_netplay(
             netplay_rom,
-            false,
             Bundle::current().config.get_default_region(),
         )?;
         Ok(self.start(StartMethod::MatchWithRandom(StartState {
             game_state: super::NetplayNesState::new(nes_state),
             session_id,
+            seed: None,
+            input_delay: Settings::current().netplay_input_delay,
         })))
     }
 
@@ -208,7 +297,8 @@ impl Netplay<ConnectingState> {
                         session_id: match &connected.start_method {
                             StartMethod::Start(StartState { session_id, .. }, ..)
                             | StartMethod::MatchWithRandom(StartState { session_id, .. })
-                            | StartMethod::Resume(StartState { session_id, .. }) => {
+                            | StartMethod::Resume(StartState { session_id, .. })
+                            | StartMethod::Spectate(StartState { session_id, .. }, _) => {
                                 session_id.clone()
                             }
                         },
@@ -221,6 +311,10 @@ impl Netplay<ConnectingState> {
                     },
                 })
             }
+            ConnectingState::Spectating(spectating) => {
+                log::debug!("Spectating {}", spectating.room_name);
+                NetplayState::Spectating(Netplay::from(spectating))
+            }
             ConnectingState::Failed(reason) => NetplayState::Failed(Netplay {
                 state: FailedState { reason },
             }),
@@ -273,9 +367,30 @@ impl Netplay<ConnectedState> {
 impl Netplay<ResumingState> {
     fn advance(mut self) -> NetplayState {
         //log::trace!("Advancing Netplay<Resuming>");
+        if self
+            .state
+            .remaining()
+            .is_some_and(|remaining| remaining.is_zero())
+        {
+            log::warn!("Giving up on reconnecting");
+            return NetplayState::Failed(Netplay::from(FailedState {
+                reason: "Could not reconnect to the other player".to_string(),
+            }));
+        }
+
         self.state.attempt2 = self.state.attempt2.advance();
         self.state.attempt1 = self.state.attempt1.advance();
 
+        // Keep trying until the other player is back (or it's time to give up)
+        if let ConnectingState::Failed(reason) = &self.state.attempt2 {
+            log::info!("Reconnecting failed ({reason}), trying again");
+            self.state.attempt2 = self.state.retry(&self.state.last_confirmed_game_state);
+        }
+        if let ConnectingState::Failed(reason) = &self.state.attempt1 {
+            log::info!("Reconnecting failed ({reason}), trying again");
+            self.state.attempt1 = self.state.retry(&self.state.earlier_confirmed_game_state);
+        }
+
         if let ConnectingState::Connected(_) = &self.state.attempt2 {
             NetplayState::Connecting(Netplay {
                 state: self.state.attempt2,
@@ -293,6 +408,41 @@ impl Netplay<ResumingState> {
         log::debug!("Resume cancelled by user");
         self.disconnect()
     }
+
+    /// Stop trying to reconnect and play back the game locally from the last confirmed state
+    pub fn review(self) -> Netplay<ReviewState> {
+        let nes_state = self.state.last_confirmed_game_state.nes_state.clone();
+        log::debug!("Reviewing the game from frame {}", nes_state.frame());
+        Netplay::from(ReviewState {
+            nes_state,
+            paused: true,
+            // Show the state we start from
+            step_requested: true,
+        })
+    }
+}
+
+impl Netplay<ReviewState> {
+    fn advance(mut self, joypad_state: [JoypadState; 2], buffers: &mut NESBuffers) -> NetplayState {
+        let review = &mut self.state;
+        if !review.paused || review.step_requested {
+            review.step_requested = false;
+            review.nes_state.advance(joypad_state, buffers);
+        } else if let Some(audio) = &mut buffers.audio {
+            //No sound while paused
+            for _ in 0..1000 {
+                audio.push(0.0);
+            }
+        }
+        NetplayState::Reviewing(self)
+    }
+}
+
+impl Netplay<SpectatingState> {
+    fn advance(mut self, buffers: &mut NESBuffers) -> NetplayState {
+        self.state.advance(buffers);
+        NetplayState::Spectating(self)
+    }
 }
 
 impl Netplay<FailedState> {
@@ -0,0 +1,154 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::bundle::Bundle;
+
+use super::connecting_state::NetplayServerConfiguration;
+
+/// A quick reachability check of the configured netplay server that doesn't start a session.
+/// It does a WebSocket handshake with the matchbox server and a STUN binding request to the first ICE server.
+#[derive(Default)]
+pub struct ServerTest {
+    result: Arc<Mutex<Option<Vec<String>>>>,
+    running: bool,
+}
+
+impl ServerTest {
+    const TIMEOUT: Duration = Duration::from_secs(3);
+    const DEFAULT_STUN_PORT: u16 = 3478;
+
+    pub fn start(&mut self) {
+        if self.running {
+            return;
+        }
+        self.running = true;
+        *self.result.lock().unwrap() = None;
+
+        let result = self.result.clone();
+        std::thread::spawn(move || {
+            let report = Self::run();
+            for line in &report {
+                log::info!("Netplay server test: {line}");
+            }
+            *result.lock().unwrap() = Some(report);
+        });
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.vertical_centered(|ui| {
+            if ui
+                .add_enabled(!self.running, egui::Button::new("Test netplay server"))
+                .clicked()
+            {
+                self.start();
+            }
+            if let Some(report) = &*self.result.lock().unwrap() {
+                self.running = false;
+                for line in report {
+                    ui.label(line);
+                }
+            } else if self.running {
+                ui.spinner();
+            }
+        });
+    }
+
+    fn run() -> Vec<String> {
+        let conf = match &Bundle::current().config.netplay.server {
+            NetplayServerConfiguration::Static(conf) => conf,
+            NetplayServerConfiguration::TurnOn(server) => {
+                return vec![format!(
+                    "The server configuration is fetched from {server} when connecting, only static configurations can be tested"
+                )];
+            }
+        };
+
+        let mut report = vec![];
+        let matchbox_server = conf.matchbox_server();
+        report.push(match Self::websocket_handshake(matchbox_server) {
+            Ok(latency) => format!("Matchbox server {matchbox_server}: OK ({latency:?})"),
+            Err(e) => format!("Matchbox server {matchbox_server}: FAILED ({e})"),
+        });
+
+        report.push(match conf.ice_urls().first() {
+            Some(url) => match Self::stun_binding(url) {
+                Ok(latency) => format!("ICE server {url}: OK ({latency:?})"),
+                Err(e) => format!("ICE server {url}: FAILED ({e})"),
+            },
+            None => "No ICE servers configured".to_string(),
+        });
+        report
+    }
+
+    fn resolve(address: &str) -> Result<SocketAddr> {
+        address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("could not resolve {address}"))
+    }
+
+    fn websocket_handshake(server: &str) -> Result<Duration> {
+        let address = Self::resolve(server)?;
+        let start = Instant::now();
+        let mut stream = TcpStream::connect_timeout(&address, Self::TIMEOUT)?;
+        stream.set_read_timeout(Some(Self::TIMEOUT))?;
+        stream.set_write_timeout(Some(Self::TIMEOUT))?;
+        write!(
+            stream,
+            "GET /nes-bundler-server-test HTTP/1.1\r\nHost: {server}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )?;
+
+        let mut response = [0; 256];
+        let read = stream.read(&mut response)?;
+        let response = String::from_utf8_lossy(&response[..read]);
+        let status_line = response.lines().next().unwrap_or_default();
+        if status_line.contains(" 101 ") {
+            Ok(start.elapsed())
+        } else {
+            Err(anyhow!("unexpected response '{status_line}'"))
+        }
+    }
+
+    fn stun_binding(url: &str) -> Result<Duration> {
+        // The url looks like `stun:host:port?transport=udp` (or turn:/turns:, which also answer binding requests)
+        let host = url.split_once(':').map_or(url, |(_, host)| host);
+        let host = host.split('?').next().unwrap_or(host);
+        let address = if host.contains(':') {
+            Self::resolve(host)?
+        } else {
+            Self::resolve(&format!("{host}:{}", Self::DEFAULT_STUN_PORT))?
+        };
+
+        let socket = UdpSocket::bind(if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        socket.set_read_timeout(Some(Self::TIMEOUT))?;
+
+        // Binding request: type, length (no attributes), magic cookie and a random transaction id
+        let mut request = [0u8; 20];
+        request[0..2].copy_from_slice(&0x0001u16.to_be_bytes());
+        request[4..8].copy_from_slice(&0x2112_A442u32.to_be_bytes());
+        request[8..20].copy_from_slice(&rand::random::<[u8; 12]>());
+
+        let start = Instant::now();
+        socket.send_to(&request, address)?;
+        let mut response = [0u8; 512];
+        let (read, _) = socket.recv_from(&mut response)?;
+        let latency = start.elapsed();
+
+        // A binding success response with the same transaction id
+        if read >= 20 && response[0..2] == [0x01, 0x01] && response[8..20] == request[8..20] {
+            Ok(latency)
+        } else {
+            Err(anyhow!("unexpected STUN response"))
+        }
+    }
+}
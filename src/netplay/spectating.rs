@@ -0,0 +1,275 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use futures::channel::oneshot::Receiver;
+use matchbox_socket::{ChannelConfig, PeerId, PeerState, WebRtcSocket};
+
+use crate::{
+    emulation::{LocalNesState, NESBuffers, NesStateHandler},
+    input::JoypadState,
+    settings::MAX_PLAYERS,
+};
+
+use super::{
+    connecting_state::{open_socket, watch_room, StartState, StaticNetplayServerConfiguration},
+    MAX_NETPLAY_PLAYERS,
+};
+
+/// Keeps the packets well below what a WebRTC data channel can take in one message
+const MAX_FRAMES_PER_PACKET: usize = 4096;
+
+/// How many frames a spectator collects before playing, so a late packet doesn't stall the picture
+const BUFFER_FRAMES: usize = 6;
+
+/// How far behind a spectator can fall before it skips ahead (without sound or picture) to catch up
+const CATCH_UP_FRAMES: usize = 30;
+
+/// The most frames a spectator skips ahead in one go, so the emulator stays responsive while catching up
+const MAX_CATCH_UP_PER_ADVANCE: usize = 600;
+
+/// How many frames (about 30 minutes) the relay keeps for spectators that show up late.
+/// They replay the game from the start, so once older frames are dropped the game can't be watched anymore.
+const RELAY_HISTORY_FRAMES: usize = 60 * 60 * 30;
+
+/// A packet starts with the frame of its first inputs, followed by the inputs of each player for every frame
+fn encode(first_frame: usize, inputs: &[[u8; MAX_NETPLAY_PLAYERS]]) -> Box<[u8]> {
+    let mut packet = Vec::with_capacity(4 + inputs.len() * MAX_NETPLAY_PLAYERS);
+    packet.extend_from_slice(&(first_frame as u32).to_le_bytes());
+    packet.extend(inputs.iter().flatten());
+    packet.into_boxed_slice()
+}
+
+fn decode(packet: &[u8]) -> Option<(usize, impl Iterator<Item = &[u8]>)> {
+    let (first_frame, inputs) = packet.split_first_chunk::<4>()?;
+    (inputs.len() % MAX_NETPLAY_PLAYERS == 0).then(|| {
+        (
+            u32::from_le_bytes(*first_frame) as usize,
+            inputs.chunks_exact(MAX_NETPLAY_PLAYERS),
+        )
+    })
+}
+
+/// Sends the inputs of a private game to the spectators in its watch room, one of the players runs it.
+///
+/// GGRS has spectators of its own, but they have to be added to the P2P session before it starts and share
+/// its socket. The players pair up in a room that takes exactly two peers, and spectators should be able to
+/// show up at any time, so the inputs go through a room of their own instead.
+pub struct SpectatorRelay {
+    socket: WebRtcSocket,
+    /// The inputs of the most recent frames, see `RELAY_HISTORY_FRAMES`
+    inputs: VecDeque<[u8; MAX_NETPLAY_PLAYERS]>,
+    /// The frame of the first of the `inputs`
+    first_frame: usize,
+    /// How many frames have been sent to each spectator
+    spectators: HashMap<PeerId, usize>,
+}
+
+impl SpectatorRelay {
+    pub fn new(
+        netplay_server_configuration: &StaticNetplayServerConfiguration,
+        session_id: &str,
+    ) -> Self {
+        // The game goes on without spectators if the room can't be reached
+        let (socket, _) = open_socket(
+            netplay_server_configuration,
+            &watch_room(session_id),
+            vec![ChannelConfig::reliable()],
+        );
+        Self {
+            socket,
+            inputs: VecDeque::new(),
+            first_frame: 0,
+            spectators: HashMap::new(),
+        }
+    }
+
+    /// Remember the inputs of `frame`, a rollback records the frames it replays again
+    pub fn record(&mut self, frame: i32, joypads: &[JoypadState; MAX_PLAYERS]) {
+        let Some(frame) = usize::try_from(frame)
+            .ok()
+            .and_then(|frame| frame.checked_sub(self.first_frame))
+        else {
+            // Only confirmed frames are dropped, they are never played again
+            return;
+        };
+        if frame > self.inputs.len() {
+            log::warn!(
+                "Not relaying frame {}, the frames before it are missing",
+                self.first_frame + frame
+            );
+            return;
+        }
+        self.inputs.truncate(frame);
+        self.inputs
+            .push_back(std::array::from_fn(|player| *joypads[player]));
+    }
+
+    /// Send the frames up to `confirmed_frame` to the spectators, they will never be rolled back
+    pub fn update(&mut self, confirmed_frame: i32) {
+        for (peer, state) in self.socket.update_peers() {
+            match state {
+                PeerState::Connected => {
+                    log::info!("Spectator {peer:?} is watching");
+                    // A spectator that is too late gets the oldest frames there are and gives up
+                    self.spectators.insert(peer, self.first_frame);
+                }
+                PeerState::Disconnected => {
+                    log::info!("Spectator {peer:?} left");
+                    self.spectators.remove(&peer);
+                }
+            }
+        }
+
+        let confirmed = usize::try_from(confirmed_frame + 1)
+            .unwrap_or(0)
+            .min(self.first_frame + self.inputs.len());
+        let channel = self.socket.channel_mut(0);
+        for (peer, sent) in &mut self.spectators {
+            while *sent < confirmed {
+                let end = confirmed.min(*sent + MAX_FRAMES_PER_PACKET);
+                let inputs: Vec<_> = self
+                    .inputs
+                    .range(*sent - self.first_frame..end - self.first_frame)
+                    .copied()
+                    .collect();
+                channel.send(encode(*sent, &inputs), *peer);
+                *sent = end;
+            }
+        }
+
+        // Everyone has the confirmed frames now, the oldest ones can go
+        let dropped = self
+            .inputs
+            .len()
+            .saturating_sub(RELAY_HISTORY_FRAMES)
+            .min(confirmed.saturating_sub(self.first_frame));
+        self.inputs.drain(..dropped);
+        self.first_frame += dropped;
+    }
+}
+
+/// Watching a private game by playing back the inputs one of the players relays. Local inputs never reach the game.
+pub struct SpectatingState {
+    socket: WebRtcSocket,
+    socket_result: Receiver<String>,
+    pub room_name: String,
+    pub nes_state: LocalNesState,
+    /// The player sending the inputs, once it has sent any
+    relay: Option<PeerId>,
+    /// Inputs that have arrived but haven't been played yet
+    pending: VecDeque<[JoypadState; MAX_PLAYERS]>,
+    /// How many frames have arrived so far
+    received: usize,
+    /// Collecting frames before playing again
+    buffering: bool,
+    /// When the first inputs arrived
+    pub started_at: Option<Instant>,
+    /// Why there is nothing more to watch
+    pub ended: Option<String>,
+}
+
+impl SpectatingState {
+    pub fn new(
+        socket: WebRtcSocket,
+        socket_result: Receiver<String>,
+        start_state: StartState,
+        room_name: String,
+    ) -> Self {
+        Self {
+            socket,
+            socket_result,
+            room_name,
+            nes_state: start_state.game_state.nes_state,
+            relay: None,
+            pending: VecDeque::new(),
+            received: 0,
+            buffering: true,
+            started_at: None,
+            ended: None,
+        }
+    }
+
+    fn receive(&mut self) {
+        if let Ok(Some(reason)) = self.socket_result.try_recv() {
+            log::warn!("Lost the connection while spectating: {reason}");
+            self.ended
+                .get_or_insert(format!("Lost the connection ({reason})"));
+        }
+        for (peer, state) in self.socket.update_peers() {
+            if state == PeerState::Disconnected && self.relay == Some(peer) {
+                log::info!("The game being watched has ended");
+                self.ended.get_or_insert("The game has ended".to_string());
+            }
+        }
+
+        for (peer, packet) in self.socket.channel_mut(0).receive() {
+            let Some((first_frame, inputs)) = decode(&packet) else {
+                log::warn!("Ignoring a malformed packet from {peer:?}");
+                continue;
+            };
+            if first_frame != self.received {
+                if self.received == 0 {
+                    // The start of the game is no longer kept for spectators
+                    self.ended.get_or_insert(
+                        "The game has been going on for too long to watch".to_string(),
+                    );
+                    continue;
+                }
+                log::warn!(
+                    "Ignoring frame {first_frame} from {peer:?}, expected frame {}",
+                    self.received
+                );
+                continue;
+            }
+            self.relay = Some(peer);
+            self.started_at.get_or_insert_with(Instant::now);
+            for input in inputs {
+                self.pending.push_back(std::array::from_fn(|player| {
+                    JoypadState(input.get(player).copied().unwrap_or(0))
+                }));
+                self.received += 1;
+            }
+        }
+    }
+
+    pub fn advance(&mut self, buffers: &mut NESBuffers) {
+        self.receive();
+
+        // Skip ahead when far behind, e.g. after starting to watch a game that has been going for a while
+        let mut caught_up = 0;
+        while self.pending.len() > CATCH_UP_FRAMES && caught_up < MAX_CATCH_UP_PER_ADVANCE {
+            if let Some(joypads) = self.pending.pop_front() {
+                self.nes_state.advance(
+                    joypads,
+                    &mut NESBuffers {
+                        audio: None,
+                        video: None,
+                    },
+                );
+            }
+            caught_up += 1;
+        }
+
+        if self.pending.is_empty() {
+            self.buffering = true;
+        } else if self.pending.len() >= BUFFER_FRAMES {
+            self.buffering = false;
+        }
+        let joypads = if self.buffering {
+            None
+        } else {
+            self.pending.pop_front()
+        };
+        match joypads {
+            Some(joypads) => self.nes_state.advance(joypads, buffers),
+            None => {
+                //No sound while waiting for inputs
+                if let Some(audio) = &mut buffers.audio {
+                    for _ in 0..1000 {
+                        audio.push(0.0);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::settings::Settings;
+
+static RESTART_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Ask the application to restart, it shuts down like when quitting (leaving netplay, saving SRAM...) and starts again
+pub fn request_restart() {
+    RESTART_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn restart_requested() -> bool {
+    RESTART_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Launches the application again with the same arguments, once this one has shut down.
+/// The settings are saved first so the new process starts with them.
+pub fn relaunch() {
+    Settings::current().save();
+    match std::env::current_exe().and_then(|exe| {
+        std::process::Command::new(exe)
+            .args(std::env::args_os().skip(1))
+            .spawn()
+    }) {
+        Ok(_) => log::info!("Restarting"),
+        Err(e) => log::error!("Failed to restart, please start the game again: {e:?}"),
+    }
+}
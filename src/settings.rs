@@ -1,22 +1,29 @@
 use crate::{
     audio::AudioSettings,
     bundle::Bundle,
-    emulation::NesRegion,
-    input::{settings::InputSettings, InputConfigurationKind},
+    emulation::{cheats::Cheat, EmulationSettings, NesRegion},
+    gui::AccessibilitySettings,
+    input::{hotkeys::HotkeySettings, settings::InputSettings, InputConfigurationKind},
+    main_view::gui::MainGui,
+    video::VideoSettings,
 };
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
 use std::{
-    collections::hash_map::DefaultHasher,
-    fs::File,
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    fs::{self, File},
     hash::{Hash, Hasher},
-    io::{BufReader, BufWriter},
+    io::BufWriter,
     ops::{Deref, DerefMut},
+    path::Path,
     sync::{OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Duration,
 };
 
-pub const MAX_PLAYERS: usize = 2;
+/// Players 3 and 4 only play through the Four Score, when the bundle has `four_player` set
+pub const MAX_PLAYERS: usize = 4;
 
 pub struct AutoSavingSettings<'a> {
     inner: RwLockWriteGuard<'a, Settings>,
@@ -60,14 +67,44 @@ pub struct Settings {
     pub audio: AudioSettings,
     pub input: InputSettings,
     pub netplay_id: Option<String>,
+    /// The SRAM of the selected game as older versions saved it, it moves to a file in `sram/` once the game saves
     pub save_state: Option<String>,
     nes_region: Option<NesRegion>,
+    /// The selected game if the bundle has several, the first game if not set
+    #[serde(default = "Default::default")]
+    pub game: Option<String>,
+    /// The SRAM of the other games in the bundle as older versions saved it
+    #[serde(default = "Default::default")]
+    pub game_save_states: BTreeMap<String, String>,
+    /// The cheat codes of the selected game
+    #[serde(default = "Default::default")]
+    pub cheats: Vec<Cheat>,
+    /// The cheat codes of the other games in the bundle
+    #[serde(default = "Default::default")]
+    pub game_cheats: BTreeMap<String, Vec<Cheat>>,
+    #[serde(default = "Default::default")]
+    pub accessibility: AccessibilitySettings,
+    #[serde(default = "Default::default")]
+    pub video: VideoSettings,
+    #[serde(default = "Default::default")]
+    pub emulation: EmulationSettings,
+    #[serde(default = "Default::default")]
+    pub hotkeys: HotkeySettings,
+    #[serde(default = "Default::default")]
+    pub high_score: Option<u64>,
+    /// The netplay input delay (in frames) to use instead of the one from the netplay server
+    #[serde(default = "Default::default")]
+    pub netplay_input_delay: Option<u8>,
 }
 
 impl Settings {
-    fn _current() -> &'static RwLock<Settings> {
+    fn mem() -> &'static OnceLock<RwLock<Settings>> {
         static MEM: OnceLock<RwLock<Settings>> = OnceLock::new();
-        MEM.get_or_init(|| RwLock::new(Settings::load()))
+        &MEM
+    }
+
+    fn _current() -> &'static RwLock<Settings> {
+        Self::mem().get_or_init(|| RwLock::new(Settings::load()))
     }
 
     pub fn current_mut<'a>() -> AutoSavingSettings<'a> {
@@ -78,28 +115,36 @@ impl Settings {
         Self::_current().read().unwrap()
     }
 
+    /// The current settings, unless they are not loaded yet or locked for writing (or poisoned).
+    /// Never loads them, so it's safe to call from the panic hook.
+    pub fn try_current<'a>() -> Option<RwLockReadGuard<'a, Settings>> {
+        Self::mem().get()?.try_read().ok()
+    }
+
     fn load() -> Settings {
         let bundle = Bundle::current();
         let settings_file_path = &bundle.settings_path.join("settings.yaml");
         let default_settings = bundle.config.default_settings.clone();
 
-        let mut settings: Result<Settings> = File::open(settings_file_path)
+        let mut settings: Result<Settings> = fs::read_to_string(settings_file_path)
             .map_err(anyhow::Error::msg)
-            .and_then(|f| serde_yaml::from_reader(BufReader::new(f)).map_err(anyhow::Error::msg));
+            .and_then(|yaml| {
+                serde_yaml::from_str(&yaml).or_else(|e| {
+                    log::error!("Could not parse settings ({settings_file_path:?}): {e:?}");
+                    Ok(Self::recover(settings_file_path, &yaml, &default_settings))
+                })
+            });
 
         match &mut settings {
             Ok(settings) => {
                 let default_selected = default_settings.clone().input.selected;
                 //Make sure no gamepads are selected after loading settings (they will be autoselected later if they are connected)
-                if let InputConfigurationKind::Gamepad(_) =
-                    &settings.input.get_selected_configuration(0).kind
-                {
-                    settings.input.selected[0].clone_from(&default_selected[0]);
-                }
-                if let InputConfigurationKind::Gamepad(_) =
-                    &settings.input.get_selected_configuration(1).kind
-                {
-                    settings.input.selected[1].clone_from(&default_selected[1]);
+                for (player, default_selected) in default_selected.iter().enumerate() {
+                    if let InputConfigurationKind::Gamepad(_) =
+                        &settings.input.get_selected_configuration(player).kind
+                    {
+                        settings.input.selected[player].clone_from(default_selected);
+                    }
                 }
             }
             Err(e) => log::warn!(
@@ -108,12 +153,85 @@ impl Settings {
                 e
             ),
         }
-        //TODO: Check if the error is something else than file not found and log
-        //eprintln!("Failed to load config ({err}), falling back to default settings");
         settings.unwrap_or(default_settings)
     }
 
-    fn save(&self) {
+    /// Back up a settings file that can't be read and keep the parts of it that still make sense
+    fn recover(path: &Path, yaml: &str, default_settings: &Settings) -> Settings {
+        let backup_path = path.with_extension("yaml.bak");
+        match fs::copy(path, &backup_path) {
+            Ok(_) => log::info!("Backed up the broken settings to {backup_path:?}"),
+            Err(e) => log::error!("Could not back up the broken settings: {e:?}"),
+        }
+
+        let mut recovered =
+            serde_yaml::to_value(default_settings).expect("default settings to serialize");
+        let mut dropped = 0;
+        if let Ok(Value::Mapping(broken)) = serde_yaml::from_str(yaml) {
+            for (key, value) in broken {
+                if Self::try_recover(&mut recovered, &[&key], value.clone()) {
+                    continue;
+                }
+                // Try field by field, so that one bad input mapping doesn't reset all the input settings
+                match value {
+                    Value::Mapping(fields) => {
+                        for (field, field_value) in fields {
+                            if !Self::try_recover(&mut recovered, &[&key, &field], field_value) {
+                                log::warn!("Dropping unreadable setting {key:?}.{field:?}");
+                                dropped += 1;
+                            }
+                        }
+                    }
+                    _ => {
+                        log::warn!("Dropping unreadable setting {key:?}");
+                        dropped += 1;
+                    }
+                }
+            }
+        } else {
+            dropped += 1;
+        }
+
+        MainGui::show_toast_for(
+            if dropped > 0 {
+                "Some settings could not be read and were reset"
+            } else {
+                "The settings file was damaged but has been repaired"
+            },
+            Duration::from_secs(8),
+        );
+        serde_yaml::from_value(recovered).unwrap_or_else(|_| default_settings.clone())
+    }
+
+    /// Put `value` at `path` in `settings` if the result is still usable settings
+    fn try_recover(settings: &mut Value, path: &[&Value], value: Value) -> bool {
+        let mut candidate = settings.clone();
+        let mut target = &mut candidate;
+        for key in path {
+            let Some(mapping) = target.as_mapping_mut() else {
+                return false;
+            };
+            if !mapping.contains_key(*key) {
+                mapping.insert((*key).clone(), Value::Null);
+            }
+            target = mapping.get_mut(*key).expect("key to exist");
+        }
+        *target = value;
+
+        let usable = serde_yaml::from_value::<Settings>(candidate.clone()).is_ok_and(|settings| {
+            settings
+                .input
+                .selected
+                .iter()
+                .all(|id| settings.input.configurations.contains_key(id))
+        });
+        if usable {
+            *settings = candidate;
+        }
+        usable
+    }
+
+    pub fn save(&self) {
         let settings_file_path = &Bundle::current().settings_path.join("settings.yaml");
         if let Err(e) = File::create(settings_file_path)
             .map_err(anyhow::Error::msg)
@@ -133,6 +251,32 @@ impl Settings {
         hasher.finish()
     }
 
+    /// Switch to another game in the bundle, swapping in its SRAM. Returns false if it's already selected
+    pub fn select_game(&mut self, name: &str) -> bool {
+        let current = &Bundle::current().game(self.game.as_deref()).name;
+        if current == name {
+            return false;
+        }
+        if let Some(sram) = self.save_state.take() {
+            self.game_save_states.insert(current.clone(), sram);
+        }
+        self.save_state = self.game_save_states.remove(name);
+        let cheats = std::mem::take(&mut self.cheats);
+        if !cheats.is_empty() {
+            self.game_cheats.insert(current.clone(), cheats);
+        }
+        self.cheats = self.game_cheats.remove(name).unwrap_or_default();
+        self.game = Some(name.to_string());
+        true
+    }
+
+    /// The region in use, falling back to the bundle default without storing it
+    pub fn nes_region(&self) -> NesRegion {
+        self.nes_region
+            .clone()
+            .unwrap_or_else(|| Bundle::current().config.get_default_region().clone())
+    }
+
     pub fn get_nes_region(&mut self) -> &mut NesRegion {
         self.nes_region
             .get_or_insert_with(|| Bundle::current().config.get_default_region().clone())
@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+use crate::emulation::NESVideoFrame;
+
+/// Blends between the two most recent frames depending on how far into the current frame the display is.
+/// This smooths out the judder of e.g. 50Hz content on a 60Hz display, at the cost of some blur and a frame of latency.
+pub struct FrameBlender {
+    previous: Vec<u8>,
+    current: Vec<u8>,
+    output: Vec<u8>,
+    current_at: Option<Instant>,
+}
+
+impl FrameBlender {
+    pub fn new() -> Self {
+        Self {
+            previous: vec![0; NESVideoFrame::SIZE],
+            current: vec![0; NESVideoFrame::SIZE],
+            output: vec![0; NESVideoFrame::SIZE],
+            current_at: None,
+        }
+    }
+
+    pub fn push(&mut self, frame: &[u8]) {
+        if self.current_at.is_some() {
+            std::mem::swap(&mut self.previous, &mut self.current);
+        } else {
+            // Nothing to blend from yet
+            self.previous.copy_from_slice(frame);
+        }
+        self.current.copy_from_slice(frame);
+        self.current_at = Some(Instant::now());
+    }
+
+    /// Forget the frames so that the next blend doesn't start from a stale frame
+    pub fn reset(&mut self) {
+        self.current_at = None;
+    }
+
+    /// Returns the blend of the two most recent frames, weighted by the time since the latest frame arrived
+    pub fn blend(&mut self, frame_duration: Duration) -> &[u8] {
+        let weight = self
+            .current_at
+            .map(|current_at| current_at.elapsed().as_secs_f32() / frame_duration.as_secs_f32())
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+
+        self.output
+            .chunks_exact_mut(4)
+            .zip(
+                self.previous
+                    .chunks_exact(4)
+                    .zip(self.current.chunks_exact(4)),
+            )
+            .for_each(|(pixel, (previous_pixel, current_pixel))| {
+                for ((c, &previous_c), &current_c) in pixel
+                    .iter_mut()
+                    .zip(previous_pixel)
+                    .zip(current_pixel)
+                    .take(3)
+                {
+                    *c =
+                        (previous_c as f32 + (current_c as f32 - previous_c as f32) * weight) as u8;
+                }
+                pixel[3] = 255;
+            });
+        &self.output
+    }
+}
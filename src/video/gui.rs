@@ -0,0 +1,184 @@
+use egui::Ui;
+
+use crate::{bundle::Bundle, main_view::gui::GuiComponent, settings::Settings};
+
+use super::{
+    palette::{ColorFilter, Palette},
+    TitleInfo, VideoFilter,
+};
+
+pub struct VideoGui {}
+
+impl VideoGui {
+    pub fn new() -> Self {
+        let video_settings = &Settings::current().video;
+        Palette::set(
+            video_settings.palette.as_deref(),
+            &video_settings.color_filter,
+        );
+        Self {}
+    }
+}
+
+impl GuiComponent for VideoGui {
+    fn ui(&mut self, ui: &mut Ui) {
+        let video_settings = &mut Settings::current_mut().video;
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Palette");
+            egui::ComboBox::from_id_salt("video-palette")
+                .width(160.0)
+                .selected_text(video_settings.palette.as_deref().unwrap_or("Default"))
+                .show_ui(ui, |ui| {
+                    changed |= ui
+                        .selectable_value(&mut video_settings.palette, None, "Default")
+                        .changed();
+                    for file in Palette::files() {
+                        changed |= ui
+                            .selectable_value(&mut video_settings.palette, Some(file.clone()), file)
+                            .changed();
+                    }
+                })
+                .response
+                .on_hover_text(format!(
+                    "Put .pal files in {:?} to choose them here",
+                    Palette::directory()
+                ));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Color filter");
+            egui::ComboBox::from_id_salt("video-color-filter")
+                .width(160.0)
+                .selected_text(video_settings.color_filter.to_string())
+                .show_ui(ui, |ui| {
+                    for color_filter in ColorFilter::ALL {
+                        changed |= ui
+                            .selectable_value(
+                                &mut video_settings.color_filter,
+                                color_filter,
+                                color_filter.to_string(),
+                            )
+                            .changed();
+                    }
+                });
+        });
+        if changed {
+            Palette::set(
+                video_settings.palette.as_deref(),
+                &video_settings.color_filter,
+            );
+        }
+
+        // A shader from the bundle replaces the video filter
+        if Bundle::current().config.shader.is_none() {
+            egui::ComboBox::from_label("Video filter")
+                .selected_text(video_settings.video_filter.to_string())
+                .show_ui(ui, |ui| {
+                    for video_filter in VideoFilter::ALL {
+                        ui.selectable_value(
+                            &mut video_settings.video_filter,
+                            video_filter,
+                            video_filter.to_string(),
+                        );
+                    }
+                })
+                .response
+                .on_hover_text("Draw the game like on an old television, needs a bit more from the graphics card");
+        }
+
+        ui.checkbox(&mut video_settings.reduce_flashing, "Reduce flashing")
+            .on_hover_text("Damps large changes in brightness between frames. This is a best-effort safety aid and not a guarantee, it also slightly blurs fast motion.");
+        if video_settings.reduce_flashing {
+            ui.horizontal(|ui| {
+                ui.label("Threshold");
+                ui.add(egui::Slider::new(
+                    &mut video_settings.flash_threshold,
+                    5..=100,
+                ));
+            });
+        }
+
+        ui.checkbox(&mut video_settings.frame_blending, "Frame blending")
+            .on_hover_text("Smooths motion when the game runs at a different rate than the display (e.g. PAL games on a 60Hz display), at the cost of a slight blur.");
+
+        ui.add_enabled(
+            !video_settings.force_integer_scaling,
+            egui::Checkbox::new(
+                &mut video_settings.pixel_perfect_fullscreen,
+                "Pixel perfect fullscreen",
+            ),
+        )
+        .on_hover_text("Scale the picture by a whole number in fullscreen, leaving black borders. No shimmering when scrolling, but no 4:3 correction either.");
+        ui.checkbox(
+            &mut video_settings.force_integer_scaling,
+            "Integer scaling only",
+        )
+        .on_hover_text("Always scale the picture by a whole number, in a window too. Whatever doesn't fit is left black.");
+
+        ui.add(
+            egui::Slider::new(&mut video_settings.min_window_size, 50..=400)
+                .step_by(25.0)
+                .suffix("%")
+                .text("Minimum window size"),
+        )
+        .on_hover_text("The smallest the window can be resized to, relative to the NES resolution");
+
+        // Until the player chooses, the bundle's timeout is shown (in whole seconds)
+        let chosen = video_settings.cursor_hide_seconds.unwrap_or_else(|| {
+            Bundle::current()
+                .config
+                .cursor_hide_timeout()
+                .map(|timeout| timeout.as_secs_f32().round().min(30.0) as u8)
+        });
+        let mut cursor_hide_seconds = chosen;
+        let mut hide_cursor = cursor_hide_seconds.is_some();
+        ui.checkbox(&mut hide_cursor, "Hide mouse cursor")
+            .on_hover_text("Hide the cursor while playing when the mouse hasn't moved for a while");
+        match (hide_cursor, &mut cursor_hide_seconds) {
+            (true, Some(seconds)) => {
+                ui.add(
+                    egui::Slider::new(seconds, 0..=30)
+                        .suffix(" s")
+                        .text("Hide mouse cursor after"),
+                );
+                ui.checkbox(
+                    &mut video_settings.hide_cursor_in_window,
+                    "Hide mouse cursor in windowed mode",
+                )
+                .on_hover_text("Otherwise it's only hidden in fullscreen when the menu is closed");
+            }
+            (true, seconds @ None) => *seconds = Some(1),
+            (false, seconds) => *seconds = None,
+        }
+        if cursor_hide_seconds != chosen {
+            video_settings.cursor_hide_seconds = Some(cursor_hide_seconds);
+        }
+
+        egui::ComboBox::from_label("Window title")
+            .selected_text(video_settings.title_info.to_string())
+            .show_ui(ui, |ui| {
+                for title_info in TitleInfo::ALL {
+                    if title_info == TitleInfo::RoomCode && !cfg!(feature = "netplay") {
+                        continue;
+                    }
+                    ui.selectable_value(
+                        &mut video_settings.title_info,
+                        title_info,
+                        title_info.to_string(),
+                    );
+                }
+            })
+            .response
+            .on_hover_text("Extra information to show after the name in the title bar");
+
+        ui.checkbox(
+            &mut video_settings.screenshot_aspect_corrected,
+            "4:3 screenshots",
+        )
+        .on_hover_text("Stretch screenshots to 4:3 like the picture in the window");
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some("Video")
+    }
+}
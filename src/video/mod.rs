@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use self::palette::ColorFilter;
+use crate::{
+    bundle::Bundle,
+    emulation::{NES_HEIGHT, NES_WIDTH_4_3},
+    Size,
+};
+
+pub mod frame_blending;
+pub mod gui;
+pub mod palette;
+pub mod post_process;
+pub mod screenshot;
+
+/// Extra information appended to the window title, after the name of the bundle
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum TitleInfo {
+    #[default]
+    Nothing,
+    /// The code of the private netplay game being hosted or joined
+    RoomCode,
+    Fps,
+}
+
+impl TitleInfo {
+    pub const ALL: [TitleInfo; 3] = [TitleInfo::Nothing, TitleInfo::RoomCode, TitleInfo::Fps];
+}
+
+impl std::fmt::Display for TitleInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TitleInfo::Nothing => write!(f, "Nothing"),
+            TitleInfo::RoomCode => write!(f, "Netplay room code"),
+            TitleInfo::Fps => write!(f, "FPS"),
+        }
+    }
+}
+
+/// A built-in shader the NES frame is drawn through, to look more like it did on a television
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum VideoFilter {
+    #[default]
+    None,
+    Scanlines,
+    /// Scanlines, a slight bloom and a curved screen
+    Crt,
+}
+
+impl VideoFilter {
+    pub const ALL: [VideoFilter; 3] = [VideoFilter::None, VideoFilter::Scanlines, VideoFilter::Crt];
+
+    /// The WGSL source of the shader, see `Filter` for what it needs to provide
+    pub fn shader_source(&self) -> Option<&'static str> {
+        match self {
+            VideoFilter::None => None,
+            VideoFilter::Scanlines => Some(include_str!("shaders/scanlines.wgsl")),
+            VideoFilter::Crt => Some(include_str!("shaders/crt.wgsl")),
+        }
+    }
+}
+
+impl std::fmt::Display for VideoFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoFilter::None => write!(f, "None"),
+            VideoFilter::Scanlines => write!(f, "Scanlines"),
+            VideoFilter::Crt => write!(f, "CRT"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct VideoSettings {
+    #[serde(default = "Default::default")]
+    pub color_filter: ColorFilter,
+    #[serde(default = "Default::default")]
+    pub video_filter: VideoFilter,
+    /// A `.pal` file in the palettes directory, the bundle's palette is used if not set
+    #[serde(default = "Default::default")]
+    pub palette: Option<String>,
+    #[serde(default = "Default::default")]
+    pub reduce_flashing: bool,
+    /// The maximum change in average luminance (0-255) allowed between two frames when `reduce_flashing` is enabled
+    #[serde(default = "VideoSettings::default_flash_threshold")]
+    pub flash_threshold: u8,
+    #[serde(default = "Default::default")]
+    pub frame_blending: bool,
+    #[serde(default = "Default::default")]
+    pub title_info: TitleInfo,
+    /// Largest integer scale with square pixels when in fullscreen, instead of filling the screen
+    #[serde(default = "Default::default")]
+    pub pixel_perfect_fullscreen: bool,
+    /// Largest integer scale with square pixels in a window too, at any window size
+    #[serde(default = "Default::default")]
+    pub force_integer_scaling: bool,
+    /// The smallest the window can be made, in percent of the (4:3) NES resolution
+    #[serde(default = "VideoSettings::default_min_window_size")]
+    pub min_window_size: u16,
+    /// Hide the mouse cursor after it hasn't moved for this many seconds (while playing), 0 hides it right away
+    /// and `null` never hides it. Left out to follow the bundle's `cursor_hide_timeout_secs`.
+    #[serde(
+        default = "Default::default",
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cursor_hide_seconds: Option<Option<u8>>,
+    /// Hide the mouse cursor in windowed mode too, not only in fullscreen
+    #[serde(default = "Default::default")]
+    pub hide_cursor_in_window: bool,
+    /// Stretch screenshots to 4:3 like the picture in the window, instead of saving the NES pixels as they are
+    #[serde(default = "Default::default")]
+    pub screenshot_aspect_corrected: bool,
+}
+
+impl VideoSettings {
+    fn default_flash_threshold() -> u8 {
+        40
+    }
+
+    fn default_min_window_size() -> u16 {
+        100
+    }
+
+    /// How long the mouse needs to be still before the cursor is hidden, if it's hidden at all.
+    /// The bundle decides unless the player has chosen, and always when the settings are locked.
+    pub fn cursor_hide_timeout(&self) -> Option<Duration> {
+        let config = &Bundle::current().config;
+        match self.cursor_hide_seconds {
+            Some(seconds) if !config.lock_settings => {
+                seconds.map(|seconds| Duration::from_secs(seconds.min(30) as u64))
+            }
+            _ => config.cursor_hide_timeout(),
+        }
+    }
+
+    /// Whether the frame is drawn at an integer scale with square pixels
+    pub fn integer_scaling(&self, fullscreen: bool) -> bool {
+        self.force_integer_scaling || (self.pixel_perfect_fullscreen && fullscreen)
+    }
+
+    pub fn min_window_size(&self) -> Size {
+        let scale = self.min_window_size.clamp(50, 400) as u32;
+        Size::new(NES_WIDTH_4_3 * scale / 100, NES_HEIGHT * scale / 100)
+    }
+}
+
+/// A `null` becomes `Some(None)`, telling it apart from a missing field which is left as `None`
+fn deserialize_present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        Self {
+            color_filter: Default::default(),
+            video_filter: Default::default(),
+            palette: None,
+            reduce_flashing: false,
+            flash_threshold: Self::default_flash_threshold(),
+            frame_blending: false,
+            title_info: TitleInfo::default(),
+            pixel_perfect_fullscreen: false,
+            force_integer_scaling: false,
+            min_window_size: Self::default_min_window_size(),
+            cursor_hide_seconds: None,
+            hide_cursor_in_window: false,
+            screenshot_aspect_corrected: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor_hide_seconds(yaml: &str) -> Option<Option<u8>> {
+        serde_yaml::from_str::<VideoSettings>(yaml)
+            .unwrap()
+            .cursor_hide_seconds
+    }
+
+    #[test]
+    fn a_missing_cursor_hide_timeout_follows_the_bundle() {
+        assert_eq!(cursor_hide_seconds("{}"), None);
+        assert_eq!(cursor_hide_seconds("cursor_hide_seconds: ~"), Some(None));
+        assert_eq!(cursor_hide_seconds("cursor_hide_seconds: 3"), Some(Some(3)));
+    }
+
+    #[test]
+    fn the_chosen_cursor_hide_timeout_survives_a_round_trip() {
+        for chosen in [None, Some(None), Some(Some(0))] {
+            let video_settings = VideoSettings {
+                cursor_hide_seconds: chosen,
+                ..Default::default()
+            };
+            let yaml = serde_yaml::to_string(&video_settings).unwrap();
+            assert_eq!(cursor_hide_seconds(&yaml), chosen);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{bundle::Bundle, main_view::gui::MainGui};
+
+static NTSC_PAL: &[u8] = include_bytes!("../../config/palette.pal");
+
+/// The size of a palette file with the 64 NES colors
+const COLORS_SIZE: usize = 64 * 3;
+/// The size of a palette file with the 64 NES colors in all 8 color emphasis combinations
+const EMPHASIS_SIZE: usize = 8 * COLORS_SIZE;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum ColorFilter {
+    #[default]
+    None,
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl ColorFilter {
+    pub const ALL: [ColorFilter; 4] = [
+        ColorFilter::None,
+        ColorFilter::Protanopia,
+        ColorFilter::Deuteranopia,
+        ColorFilter::Tritanopia,
+    ];
+
+    // Simulates how the color blindness perceives colors in LMS color space
+    fn simulation_matrix(&self) -> Option<[[f32; 3]; 3]> {
+        match self {
+            ColorFilter::None => None,
+            ColorFilter::Protanopia => {
+                Some([[0.0, 2.023_44, -2.525_81], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+            }
+            ColorFilter::Deuteranopia => {
+                Some([[1.0, 0.0, 0.0], [0.494_207, 0.0, 1.248_27], [0.0, 0.0, 1.0]])
+            }
+            ColorFilter::Tritanopia => Some([
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [-0.395_913, 0.801_109, 0.0],
+            ]),
+        }
+    }
+
+    /// Daltonizes a color, shifting the information that is lost for the color blind into colors that are visible.
+    fn daltonize(&self, rgb: [u8; 3]) -> [u8; 3] {
+        const RGB_TO_LMS: [[f32; 3]; 3] = [
+            [17.882_4, 43.516_1, 4.119_35],
+            [3.455_65, 27.155_4, 3.867_14],
+            [0.029_956_6, 0.184_309, 1.467_09],
+        ];
+        const LMS_TO_RGB: [[f32; 3]; 3] = [
+            [0.080_944_45, -0.130_504_41, 0.116_721_07],
+            [-0.010_248_533, 0.054_019_327, -0.113_614_71],
+            [-0.000_365_296_94, -0.004_121_614_7, 0.693_511_4],
+        ];
+        const ERROR_SHIFT: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+        let Some(simulation) = self.simulation_matrix() else {
+            return rgb;
+        };
+
+        let original = rgb.map(|c| c as f32);
+        let simulated = mul(LMS_TO_RGB, mul(simulation, mul(RGB_TO_LMS, original)));
+        let error = [
+            original[0] - simulated[0],
+            original[1] - simulated[1],
+            original[2] - simulated[2],
+        ];
+        let correction = mul(ERROR_SHIFT, error);
+        [
+            (original[0] + correction[0]).clamp(0.0, 255.0) as u8,
+            (original[1] + correction[1]).clamp(0.0, 255.0) as u8,
+            (original[2] + correction[2]).clamp(0.0, 255.0) as u8,
+        ]
+    }
+}
+
+impl std::fmt::Display for ColorFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorFilter::None => write!(f, "None"),
+            ColorFilter::Protanopia => write!(f, "Protanopia (red-blind)"),
+            ColorFilter::Deuteranopia => write!(f, "Deuteranopia (green-blind)"),
+            ColorFilter::Tritanopia => write!(f, "Tritanopia (blue-blind)"),
+        }
+    }
+}
+
+fn mul(m: [[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+/// The RGB palette used to convert the NES palette indices to colors
+pub struct Palette(Vec<u8>);
+
+impl Palette {
+    fn _active() -> &'static RwLock<Arc<Palette>> {
+        static MEM: OnceLock<RwLock<Arc<Palette>>> = OnceLock::new();
+        MEM.get_or_init(|| RwLock::new(Arc::new(Palette(NTSC_PAL.to_vec()))))
+    }
+
+    pub fn active() -> Arc<Palette> {
+        Self::_active().read().unwrap().clone()
+    }
+
+    /// Recalculates the active palette from the base palette with a color filter applied.
+    /// The base palette is `palette_file` from the palettes directory, the bundle's palette or the built-in one.
+    /// The filter is applied to the palette entries once, so it's free during emulation.
+    pub fn set(palette_file: Option<&str>, color_filter: &ColorFilter) {
+        log::debug!("Applying palette {palette_file:?} with color filter {color_filter:?}");
+        let palette = Self::base(palette_file)
+            .chunks_exact(3)
+            .flat_map(|rgb| color_filter.daltonize([rgb[0], rgb[1], rgb[2]]))
+            .collect();
+        *Self::_active().write().unwrap() = Arc::new(Palette(palette));
+    }
+
+    /// Where the player can put their own `.pal` files
+    pub fn directory() -> PathBuf {
+        Bundle::current().settings_path.join("palettes")
+    }
+
+    /// The `.pal` files in the palettes directory
+    pub fn files() -> Vec<String> {
+        let mut files: Vec<String> = std::fs::read_dir(Self::directory())
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .filter(|name| name.to_lowercase().ends_with(".pal"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    }
+
+    fn base(palette_file: Option<&str>) -> Vec<u8> {
+        let path = palette_file
+            .map(|file| Self::directory().join(file))
+            .or_else(|| Bundle::current().config.palette_path.clone());
+        path.and_then(|path| {
+            Self::load(&path)
+                .inspect(|_| log::info!("Using the palette {path:?}"))
+                .inspect_err(|e| {
+                    log::warn!("Could not use the palette {path:?}, using the built-in one: {e:?}");
+                    MainGui::show_toast(format!("Could not use the palette: {e}"));
+                })
+                .ok()
+        })
+        .unwrap_or_else(|| NTSC_PAL.to_vec())
+    }
+
+    /// Read a palette file with 64 colors (192 bytes), or with the emphasis combinations too (1536 bytes)
+    fn load(path: &Path) -> Result<Vec<u8>> {
+        let palette = std::fs::read(path)?;
+        match palette.len() {
+            EMPHASIS_SIZE => Ok(palette),
+            COLORS_SIZE => Ok(Self::with_emphasis(&palette)),
+            len => anyhow::bail!(
+                "{path:?} is {len} bytes, a palette is {COLORS_SIZE} (or {EMPHASIS_SIZE}) bytes"
+            ),
+        }
+    }
+
+    /// Approximates the colors for the 8 combinations of the color emphasis bits from the 64 colors.
+    /// Emphasizing a color darkens the other two, emphasizing all of them darkens everything.
+    fn with_emphasis(colors: &[u8]) -> Vec<u8> {
+        const DIMMED: f32 = 0.75;
+        (0..8_u8)
+            .flat_map(|emphasis| {
+                colors.chunks_exact(3).flat_map(move |rgb| {
+                    [0, 1, 2].map(|channel| {
+                        let emphasized = emphasis & (1 << channel) != 0;
+                        if emphasis != 0 && (!emphasized || emphasis == 0b111) {
+                            (rgb[channel] as f32 * DIMMED) as u8
+                        } else {
+                            rgb[channel]
+                        }
+                    })
+                })
+            })
+            .collect()
+    }
+
+    pub fn rgb(&self, palette_index: usize) -> &[u8] {
+        let palette_index = palette_index * 3;
+        &self.0[palette_index..palette_index + 3]
+    }
+}
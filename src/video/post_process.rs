@@ -0,0 +1,71 @@
+use crate::emulation::NESVideoFrame;
+
+use super::VideoSettings;
+
+/// Post processing of the NES frames done on the CPU before they are uploaded to the GPU.
+pub struct PostProcessor {
+    output: Vec<u8>,
+    previous_output: Option<Vec<u8>>,
+}
+
+impl PostProcessor {
+    pub fn new() -> Self {
+        Self {
+            output: vec![0; NESVideoFrame::SIZE],
+            previous_output: None,
+        }
+    }
+
+    /// Returns the frame to present, or the unmodified frame if there is no post processing enabled.
+    pub fn process<'a>(&'a mut self, frame: &'a [u8], video_settings: &VideoSettings) -> &'a [u8] {
+        if !video_settings.reduce_flashing {
+            self.previous_output = None;
+            return frame;
+        }
+
+        self.output.copy_from_slice(frame);
+        if let Some(previous_output) = &self.previous_output {
+            Self::reduce_flashing(
+                &mut self.output,
+                previous_output,
+                video_settings.flash_threshold,
+            );
+        }
+
+        self.previous_output
+            .get_or_insert_with(|| vec![0; NESVideoFrame::SIZE])
+            .copy_from_slice(&self.output);
+        &self.output
+    }
+
+    /// Best-effort damping of large frame-to-frame luminance swings.
+    /// If the average luminance changes more than `threshold` the frame is blended towards the previous one
+    /// so that the change is limited to the threshold. This will slightly blur fast motion during flashes.
+    fn reduce_flashing(frame: &mut [u8], previous_frame: &[u8], threshold: u8) {
+        let luminance_delta =
+            (Self::average_luminance(frame) - Self::average_luminance(previous_frame)).abs();
+        if luminance_delta <= threshold as f32 {
+            return;
+        }
+
+        let new_weight = threshold as f32 / luminance_delta;
+        frame
+            .chunks_exact_mut(4)
+            .zip(previous_frame.chunks_exact(4))
+            .for_each(|(pixel, previous_pixel)| {
+                for (c, &previous_c) in pixel.iter_mut().zip(previous_pixel).take(3) {
+                    *c = (previous_c as f32 + (*c as f32 - previous_c as f32) * new_weight) as u8;
+                }
+            });
+    }
+
+    fn average_luminance(frame: &[u8]) -> f32 {
+        let total: f32 = frame
+            .chunks_exact(4)
+            .map(|pixel| {
+                0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32
+            })
+            .sum();
+        total / (frame.len() / 4) as f32
+    }
+}
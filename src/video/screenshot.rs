@@ -0,0 +1,45 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use image::{imageops::FilterType, RgbaImage};
+
+use crate::{
+    bundle::Bundle,
+    emulation::{NES_HEIGHT, NES_WIDTH, NES_WIDTH_4_3},
+    main_view::gui::MainGui,
+};
+
+/// Save a NES frame (RGBA at 256x240) as a PNG in the screenshots directory.
+/// The encoding is done on a blocking thread so it doesn't stutter the rendering.
+pub fn save_screenshot(frame: Vec<u8>, aspect_corrected: bool) {
+    tokio::task::spawn_blocking(move || match write_png(frame, aspect_corrected) {
+        Ok(path) => {
+            log::info!("Saved screenshot to {path:?}");
+            MainGui::show_toast("Screenshot saved");
+        }
+        Err(e) => {
+            log::error!("Could not save screenshot: {e:?}");
+            MainGui::show_toast("Could not save screenshot");
+        }
+    });
+}
+
+fn write_png(frame: Vec<u8>, aspect_corrected: bool) -> Result<PathBuf> {
+    let image = RgbaImage::from_raw(NES_WIDTH, NES_HEIGHT, frame)
+        .ok_or_else(|| anyhow::anyhow!("The frame is not {NES_WIDTH}x{NES_HEIGHT}"))?;
+    let image = if aspect_corrected {
+        image::imageops::resize(&image, NES_WIDTH_4_3, NES_HEIGHT, FilterType::Nearest)
+    } else {
+        image
+    };
+
+    let dir = Bundle::current().settings_path.join("screenshots");
+    std::fs::create_dir_all(&dir)?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = dir.join(format!("screenshot_{timestamp}.png"));
+    image.save(&path)?;
+    Ok(path)
+}
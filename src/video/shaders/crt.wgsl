@@ -0,0 +1,56 @@
+// A simple CRT television: a curved screen, scanlines and a slight bloom
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0) var nes_frame: texture_2d<f32>;
+@group(0) @binding(1) var nes_sampler: sampler;
+
+const CURVATURE: f32 = 0.06;
+const SCANLINE_STRENGTH: f32 = 0.5;
+const BLOOM_STRENGTH: f32 = 0.25;
+
+// One triangle that covers the whole output
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Barrel distortion, the picture bulges out towards the viewer
+fn curve(uv: vec2<f32>) -> vec2<f32> {
+    let centered = uv * 2.0 - 1.0;
+    let curved = centered * (1.0 + centered.yx * centered.yx * CURVATURE);
+    return curved * 0.5 + 0.5;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = curve(in.uv);
+    let size = vec2<f32>(textureDimensions(nes_frame));
+    let texel = 1.0 / size;
+
+    let color = textureSample(nes_frame, nes_sampler, uv).rgb;
+    // Bright pixels bleed into their neighbours
+    let neighbours = textureSample(nes_frame, nes_sampler, uv + vec2<f32>(texel.x, 0.0)).rgb
+        + textureSample(nes_frame, nes_sampler, uv - vec2<f32>(texel.x, 0.0)).rgb
+        + textureSample(nes_frame, nes_sampler, uv + vec2<f32>(0.0, texel.y)).rgb
+        + textureSample(nes_frame, nes_sampler, uv - vec2<f32>(0.0, texel.y)).rgb;
+    let bloom = neighbours * 0.25 * BLOOM_STRENGTH;
+
+    let distance = fract(uv.y * size.y) - 0.5;
+    let scanline = 1.0 - SCANLINE_STRENGTH * 4.0 * distance * distance;
+
+    // Darker towards the corners
+    let edge = max(uv * (1.0 - uv), vec2<f32>(0.0));
+    let vignette = clamp(pow(edge.x * edge.y * 16.0, 0.2), 0.0, 1.0);
+
+    let inside = all(uv >= vec2<f32>(0.0)) && all(uv <= vec2<f32>(1.0));
+    let crt = (color + bloom) * scanline * vignette;
+    return vec4<f32>(select(vec3<f32>(0.0), crt, inside), 1.0);
+}
@@ -0,0 +1,29 @@
+// Darkens the gaps between the lines of the NES picture
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0) var nes_frame: texture_2d<f32>;
+@group(0) @binding(1) var nes_sampler: sampler;
+
+// One triangle that covers the whole output
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(nes_frame, nes_sampler, in.uv).rgb;
+    let lines = f32(textureDimensions(nes_frame).y);
+    // Full brightness in the middle of a line, 60% between two lines
+    let distance = fract(in.uv.y * lines) - 0.5;
+    let scanline = 1.0 - 1.6 * distance * distance;
+    return vec4<f32>(color * scanline, 1.0);
+}
@@ -0,0 +1,206 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// Where to load a WGSL shader that the NES frame is drawn through, instead of showing it as is
+#[derive(Deserialize, Debug, Clone)]
+pub struct ShaderConfiguration {
+    /// A directory with a `nes.wgsl` file
+    pub path: PathBuf,
+    /// The size of the shader output in multiples of the NES resolution
+    #[serde(default = "ShaderConfiguration::default_scale")]
+    pub scale: u8,
+}
+
+impl ShaderConfiguration {
+    const SHADER_FILE: &'static str = "nes.wgsl";
+
+    fn default_scale() -> u8 {
+        1
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale.clamp(1, 8) as u32
+    }
+}
+
+/// Draws the NES frame through a shader supplied by the bundle, or one of the built-in video filters.
+/// The shader needs a `vs_main` entry point that is called with 3 vertices (a full screen triangle) and
+/// a `fs_main` entry point. The NES frame is bound as a `texture_2d<f32>` at `@group(0) @binding(0)`
+/// and its sampler at `@group(0) @binding(1)`.
+pub struct Filter {
+    /// The NES frame is uploaded here, the output is drawn to the texture that is shown
+    source: wgpu::Texture,
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Filter {
+    pub fn new(
+        device: &wgpu::Device,
+        configuration: &ShaderConfiguration,
+        source_size: wgpu::Extent3d,
+        output_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<Self> {
+        let shader_file = configuration.path.join(ShaderConfiguration::SHADER_FILE);
+        let shader_source = std::fs::read_to_string(&shader_file)
+            .map_err(|e| anyhow::anyhow!("Could not read {shader_file:?}: {e}"))?;
+        Self::from_source(
+            device,
+            &format!("{shader_file:?}"),
+            shader_source,
+            source_size,
+            output_format,
+        )
+    }
+
+    /// A filter with a shader that is already loaded, like the built-in video filters
+    pub fn from_source(
+        device: &wgpu::Device,
+        name: &str,
+        shader_source: impl Into<std::borrow::Cow<'static, str>>,
+        source_size: wgpu::Extent3d,
+        output_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<Self> {
+        // Catch validation errors so that a broken shader doesn't take the whole application down
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let filter = Self::create(device, name, shader_source, source_size, output_format);
+        if let Some(e) = futures::executor::block_on(device.pop_error_scope()) {
+            anyhow::bail!("Could not compile {name}:\n{e}");
+        }
+        Ok(filter)
+    }
+
+    fn create(
+        device: &wgpu::Device,
+        name: &str,
+        shader_source: impl Into<std::borrow::Cow<'static, str>>,
+        source_size: wgpu::Extent3d,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        });
+
+        let source = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("nes frame (shader input)"),
+            size: source_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("nes frame sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("nes shader bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("nes shader bind group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &source.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("nes shader pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("nes shader pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(output_format.into())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            source,
+            pipeline,
+            bind_group,
+        }
+    }
+
+    /// The texture the NES frame should be uploaded to
+    pub fn source(&self) -> &wgpu::Texture {
+        &self.source
+    }
+
+    /// Draw the uploaded NES frame through the shader to `output`
+    pub fn apply(&self, device: &wgpu::Device, queue: &wgpu::Queue, output: &wgpu::TextureView) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("nes shader encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("nes shader pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+}
@@ -1,5 +1,4 @@
-use egui::epaint::Shadow;
-use egui::{Context, Theme, Visuals};
+use egui::Context;
 use egui_wgpu::{Renderer, ScreenDescriptor};
 
 use egui_winit::{EventResponse, State};
@@ -24,17 +23,7 @@ impl EguiRenderer {
         let egui_context = Context::default();
         let id = egui_context.viewport_id();
 
-        const BORDER_RADIUS: f32 = 1.3;
-
-        let visuals = Visuals {
-            window_rounding: egui::Rounding::same(BORDER_RADIUS),
-            window_shadow: Shadow::NONE,
-            slider_trailing_fill: true,
-            handle_shape: egui::style::HandleShape::Rect { aspect_ratio: 0.6 },
-            ..Default::default()
-        };
-
-        egui_context.set_visuals_of(Theme::Dark, visuals);
+        crate::gui::AccessibilitySettings::default().apply(&egui_context);
 
         let egui_state =
             egui_winit::State::new(egui_context.clone(), id, &window, None, None, None);
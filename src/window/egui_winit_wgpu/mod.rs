@@ -11,6 +11,7 @@ use winit::window::Window;
 
 use crate::bundle::Bundle;
 
+pub mod filter;
 pub mod texture;
 
 pub struct Renderer {
@@ -33,14 +34,32 @@ impl Renderer {
         });
         let surface = instance.create_surface(Arc::clone(&window))?;
 
-        let adapter = instance
+        let adapter = match instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
                 compatible_surface: Some(&surface),
                 force_fallback_adapter: false,
             })
             .await
-            .expect("adapter to be crated");
+        {
+            Some(adapter) => adapter,
+            None => {
+                log::warn!("No GPU adapter found, falling back to software rendering. Performance will be limited.");
+                instance
+                    .request_adapter(&wgpu::RequestAdapterOptions {
+                        power_preference: wgpu::PowerPreference::default(),
+                        compatible_surface: Some(&surface),
+                        force_fallback_adapter: true,
+                    })
+                    .await
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "No graphics adapter could be found, not even a software one."
+                        )
+                    })?
+            }
+        };
+        log::info!("Using graphics adapter: {:?}", adapter.get_info());
 
         let (device, queue) = adapter
             .request_device(
@@ -1,9 +1,22 @@
-use super::Renderer;
+use crate::video::VideoFilter;
+
+use super::{
+    filter::{Filter, ShaderConfiguration},
+    Renderer,
+};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
 
 pub struct Texture {
+    label: Option<&'static str>,
     texture: wgpu::Texture,
+    view: wgpu::TextureView,
     size: wgpu::Extent3d,
     id: egui::TextureId,
+    filter: Option<Filter>,
+    /// The shader from the bundle, it takes the place of the video filter
+    shader: Option<ShaderConfiguration>,
+    video_filter: VideoFilter,
 }
 
 impl Texture {
@@ -12,37 +25,168 @@ impl Texture {
         width: u32,
         height: u32,
         label: Option<&'static str>,
+        shader: Option<&ShaderConfiguration>,
+        video_filter: VideoFilter,
     ) -> Self {
         let size = wgpu::Extent3d {
             width,
             height,
             depth_or_array_layers: 1,
         };
+        let (filter, output_size) = Self::create_filter(renderer, size, shader, video_filter);
+        let (texture, view) = Self::create_output(renderer, label, output_size, filter.is_some());
+        let id = renderer.egui.renderer.register_native_texture(
+            &renderer.device,
+            &view,
+            wgpu::FilterMode::Nearest,
+        );
+        Self {
+            label,
+            texture,
+            view,
+            size,
+            id,
+            filter,
+            shader: shader.cloned(),
+            video_filter,
+        }
+    }
+
+    fn create_filter(
+        renderer: &Renderer,
+        size: wgpu::Extent3d,
+        shader: Option<&ShaderConfiguration>,
+        video_filter: VideoFilter,
+    ) -> (Option<Filter>, wgpu::Extent3d) {
+        if let Some(shader) = shader {
+            let filter = Filter::new(&renderer.device, shader, size, FORMAT)
+                .inspect(|_| log::info!("Using shader from {:?}", shader.path))
+                .inspect_err(|e| {
+                    log::error!(
+                        "Could not load the shader, falling back to the built-in one: {e:?}"
+                    );
+                    let _ = sdl2::messagebox::show_simple_message_box(
+                        sdl2::messagebox::MessageBoxFlag::WARNING,
+                        "Could not load the shader",
+                        &format!("{e}\n\nThe game will be shown without it."),
+                        None::<&sdl2::video::Window>,
+                    );
+                })
+                .ok();
+            let scale = shader.scale();
+            return match filter {
+                Some(filter) => (
+                    Some(filter),
+                    wgpu::Extent3d {
+                        width: size.width * scale,
+                        height: size.height * scale,
+                        depth_or_array_layers: 1,
+                    },
+                ),
+                None => (None, size),
+            };
+        }
+
+        let filter = video_filter.shader_source().and_then(|shader_source| {
+            Filter::from_source(
+                &renderer.device,
+                &video_filter.to_string(),
+                shader_source,
+                size,
+                FORMAT,
+            )
+            .inspect_err(|e| log::error!("Could not create the {video_filter} filter: {e:?}"))
+            .ok()
+        });
+        // The output is resized to where the frame is shown once it has been drawn
+        (filter, size)
+    }
+
+    fn create_output(
+        renderer: &Renderer,
+        label: Option<&'static str>,
+        size: wgpu::Extent3d,
+        filtered: bool,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let usage = if filtered {
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT
+        } else {
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST
+        };
         let texture = renderer.device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format: FORMAT,
+            usage,
             view_formats: &[],
         });
-
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let id = renderer.egui.renderer.register_native_texture(
-            &renderer.device,
-            &view,
-            wgpu::FilterMode::Nearest,
-        );
-        Self { id, texture, size }
+        (texture, view)
+    }
+
+    /// Swap the texture egui shows for a new one, the texture id stays the same
+    fn replace_output(&mut self, renderer: &mut Renderer, size: wgpu::Extent3d) {
+        let (texture, view) =
+            Self::create_output(renderer, self.label, size, self.filter.is_some());
+        renderer
+            .egui
+            .renderer
+            .update_egui_texture_from_wgpu_texture(
+                &renderer.device,
+                &view,
+                wgpu::FilterMode::Nearest,
+                self.id,
+            );
+        self.texture = texture;
+        self.view = view;
     }
 
-    pub fn update(&self, queue: &wgpu::Queue, bytes: &[u8]) {
-        queue.write_texture(
+    /// Use another video filter, unless the bundle has its own shader.
+    /// Returns true if the filter changed, the texture is then empty until the next update.
+    pub fn set_video_filter(&mut self, renderer: &mut Renderer, video_filter: VideoFilter) -> bool {
+        if self.shader.is_some() || self.video_filter == video_filter {
+            return false;
+        }
+        self.video_filter = video_filter;
+        let (filter, output_size) = Self::create_filter(renderer, self.size, None, video_filter);
+        self.filter = filter;
+        self.replace_output(renderer, output_size);
+        true
+    }
+
+    /// A video filter draws at the size (in pixels) the frame is shown at, so scanlines line up with the screen
+    pub fn fit_output(&mut self, renderer: &mut Renderer, width: u32, height: u32) {
+        if self.shader.is_some() || self.filter.is_none() {
+            return;
+        }
+        let max_size = renderer.device.limits().max_texture_dimension_2d;
+        let size = wgpu::Extent3d {
+            width: width.clamp(1, max_size),
+            height: height.clamp(1, max_size),
+            depth_or_array_layers: 1,
+        };
+        if size == self.texture.size() {
+            return;
+        }
+        self.replace_output(renderer, size);
+        if let Some(filter) = &self.filter {
+            // Draw the last frame again, it's still in the source of the filter
+            filter.apply(&renderer.device, &renderer.queue, &self.view);
+        }
+    }
+
+    pub fn update(&self, renderer: &Renderer, bytes: &[u8]) {
+        let texture = match &self.filter {
+            Some(filter) => filter.source(),
+            None => &self.texture,
+        };
+        renderer.queue.write_texture(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
-                texture: &self.texture,
+                texture,
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
@@ -54,6 +198,9 @@ impl Texture {
             },
             self.size,
         );
+        if let Some(filter) = &self.filter {
+            filter.apply(&renderer.device, &renderer.queue, &self.view);
+        }
     }
     pub fn get_id(&self) -> egui::TextureId {
         self.id
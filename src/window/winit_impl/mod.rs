@@ -1,6 +1,7 @@
 use crate::{
     input::keys::{KeyCode, Modifiers},
     integer_scaling::MINIMUM_INTEGER_SCALING_SIZE,
+    settings::Settings,
 };
 
 use super::Fullscreen;
@@ -9,22 +10,11 @@ mod conversions;
 
 impl Fullscreen for winit::window::Window {
     fn check_and_set_fullscreen(&self, key_mod: Modifiers, key_code: KeyCode) -> bool {
-        #[cfg(target_os = "macos")]
-        if key_mod.contains(Modifiers::LOGO)
-            && (key_code == KeyCode::KeyF || key_code == KeyCode::Enter)
-        {
+        if Settings::current().hotkeys.is_fullscreen(key_mod, key_code) {
             self.toggle_fullscreen();
             return true;
         }
 
-        #[cfg(not(target_os = "macos"))]
-        if (key_mod.contains(Modifiers::ALT) && key_code == KeyCode::Enter)
-            || key_code == KeyCode::F11
-        {
-            self.toggle_fullscreen();
-            return true;
-        };
-
         false
     }
 